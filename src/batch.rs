@@ -0,0 +1,477 @@
+//! Batch generation
+//!
+//! `olg batch apis.toml` generates a client for every `[[apis]]` entry of a manifest, several at a
+//! time, and reports which ones were generated as a markdown table. Subprocesses run through the
+//! [ProcessRunner](crate::process::ProcessRunner) in scope.
+use crate::{
+  builder::{GenerationReport, GenerationRequest, GenerationStatuses, GeneratorBuilder},
+  cli::{BatchArgs, CLIError, OutputDirModes},
+  diagnostics::Diagnostic,
+  events, fs,
+  generate::task_runners::TaskRunners,
+  process,
+};
+use futures::{
+  future::{Future, FutureExt},
+  stream::{self, StreamExt},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  fmt,
+  io::Error as IOError,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use toml::de::Error as TomlDeError;
+use url::Url;
+
+/// Batch errors
+#[derive(Debug, Error)]
+pub enum BatchError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  TomlDeError(#[from] TomlDeError),
+  #[error("The batch manifest {0} lists no `[[apis]]`")]
+  NoEntries(PathBuf),
+  #[error("`{name}` and `{other_name}` would both generate into {output_dir}")]
+  SharedOutputDir {
+    name: String,
+    other_name: String,
+    output_dir: PathBuf,
+  },
+}
+
+/// An API to generate a client for, with the same settings as the command line
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BatchEntry {
+  /// The site or API name
+  pub name: String,
+  pub api_url: Url,
+  pub spec_url: Option<Url>,
+  pub spec_file: Option<PathBuf>,
+  pub lib_name: Option<String>,
+  /// The output dir, `<manifest dir>/<lib_name>` by default
+  pub output: Option<PathBuf>,
+  pub workspace: Option<PathBuf>,
+  pub task_runner: Option<TaskRunners>,
+  /// A TOML file of extra `[env]` and `[tasks]` entries for the `Makefile.toml`
+  pub extra_tasks: Option<PathBuf>,
+  #[serde(default, skip_serializing_if = "is_false")]
+  pub readme_task_graph: bool,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub authors: Vec<String>,
+  pub autogenerate: Option<bool>,
+  #[serde(default, skip_serializing_if = "is_false")]
+  pub force: bool,
+  #[serde(default, skip_serializing_if = "is_false")]
+  pub merge: bool,
+  #[serde(default, skip_serializing_if = "is_false")]
+  pub keep_failed: bool,
+}
+/// Whether a flag is off, to leave it out of a written manifest
+fn is_false(flag: &bool) -> bool {
+  !flag
+}
+impl BatchEntry {
+  /// Make the builder of the entry, resolving its paths against the manifest dir
+  pub fn to_builder(
+    &self,
+    manifest_dir: &Path,
+  ) -> GeneratorBuilder {
+    let output_dir_mode = if self.force {
+      OutputDirModes::Force
+    } else if self.merge {
+      OutputDirModes::Merge
+    } else {
+      OutputDirModes::RequireEmpty
+    };
+    let mut builder = GeneratorBuilder::new(&self.name, self.api_url.clone())
+      .autogenerate(self.autogenerate.unwrap_or(true))
+      .keep_failed(self.keep_failed)
+      .output_dir_mode(output_dir_mode);
+    if let Some(spec_url) = self.spec_url.as_ref() {
+      builder = builder.spec_url(spec_url.clone());
+    }
+    if let Some(spec_file) = self.spec_file.as_ref() {
+      builder = builder.spec_file(manifest_dir.join(spec_file));
+    }
+    if let Some(lib_name) = self.lib_name.as_ref() {
+      builder = builder.lib_name(lib_name);
+    }
+    if !self.authors.is_empty() {
+      builder = builder.extra_authors(&self.authors);
+    }
+    if let Some(workspace) = self.workspace.as_ref() {
+      builder = builder.workspace(manifest_dir.join(workspace));
+    }
+    if let Some(task_runner) = self.task_runner {
+      builder = builder.task_runner(task_runner);
+    }
+    if let Some(extra_tasks) = self.extra_tasks.as_ref() {
+      builder = builder.extra_tasks(manifest_dir.join(extra_tasks));
+    }
+    builder = builder.readme_task_graph(self.readme_task_graph);
+    // a workspace member goes into the workspace unless told otherwise
+    match self.output.as_ref() {
+      Some(output) => builder.output_dir(manifest_dir.join(output)),
+      None if self.workspace.is_some() => builder,
+      None => {
+        let lib_name = builder.get_lib_name();
+        builder.output_dir(manifest_dir.join(lib_name))
+      }
+    }
+  }
+}
+
+/// A list of APIs to generate clients for
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchManifest {
+  #[serde(default)]
+  pub apis: Vec<BatchEntry>,
+}
+impl BatchManifest {
+  /// Read a manifest file
+  pub async fn read(path: impl AsRef<Path>) -> Result<Self, BatchError> {
+    let manifest: Self = toml::from_str(&fs::read_to_string(path.as_ref()).await?)?;
+    if manifest.apis.is_empty() {
+      return Err(BatchError::NoEntries(path.as_ref().to_path_buf()));
+    }
+    Ok(manifest)
+  }
+}
+
+/// How the generation of an API went
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum BatchOutcomes {
+  Generated { report: Box<GenerationReport> },
+  Failed { diagnostic: Box<Diagnostic> },
+}
+
+/// How the generation of an API of the manifest went
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BatchRow {
+  /// The site or API name
+  pub name: String,
+  pub lib_name: String,
+  /// The dir the client was generated in, unless its settings are invalid
+  pub output_dir_opt: Option<PathBuf>,
+  #[serde(flatten)]
+  pub outcome: BatchOutcomes,
+}
+
+/// What a batch run did
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BatchReport {
+  pub rows: Vec<BatchRow>,
+}
+impl BatchReport {
+  /// Whether every API was generated without merge conflicts
+  pub fn is_passing(&self) -> bool {
+    self.rows.iter().all(|row| match &row.outcome {
+      BatchOutcomes::Generated { report } => report.status == GenerationStatuses::Generated,
+      BatchOutcomes::Failed { .. } => false,
+    })
+  }
+}
+impl fmt::Display for BatchReport {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    writeln!(f, "| api | library | outcome | output dir |")?;
+    writeln!(f, "|---|---|---|---|")?;
+    for row in self.rows.iter() {
+      let outcome = match &row.outcome {
+        BatchOutcomes::Generated { report }
+          if report.status == GenerationStatuses::MergeConflicts =>
+        {
+          "**merge conflicts**"
+        }
+        BatchOutcomes::Generated { .. } => "generated",
+        BatchOutcomes::Failed { .. } => "**FAILED**",
+      };
+      let output_dir = row
+        .output_dir_opt
+        .as_ref()
+        .map(|output_dir| format!("`{}`", output_dir.to_string_lossy()))
+        .unwrap_or_default();
+      writeln!(
+        f,
+        "| {} | {} | {outcome} | {output_dir} |",
+        row.name, row.lib_name
+      )?;
+    }
+    let failures = self
+      .rows
+      .iter()
+      .filter_map(|row| match &row.outcome {
+        BatchOutcomes::Failed { diagnostic } => Some((row, diagnostic)),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    if !failures.is_empty() {
+      writeln!(f, "\n### Failures\n")?;
+    }
+    for (row, diagnostic) in failures {
+      let message = diagnostic.message.lines().next().unwrap_or_default();
+      match diagnostic.failed_step_opt {
+        Some(step) => writeln!(f, "- `{}` failed to {}: {message}", row.name, step.as_ref())?,
+        None => writeln!(f, "- `{}` failed: {message}", row.name)?,
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Generate every API of the manifest, rolling back the ones still running if `cancel` finishes
+///
+/// Up to [BatchArgs::get_jobs] APIs generate at a time, but APIs that join the same workspace take
+/// turns since they edit the same root files.
+pub async fn run_batch(
+  args: &BatchArgs,
+  cancel: impl Future<Output = ()>,
+) -> Result<BatchReport, BatchError> {
+  let manifest = BatchManifest::read(&args.manifest_path).await?;
+  let manifest_dir = args
+    .manifest_path
+    .parent()
+    .map(Path::to_path_buf)
+    .unwrap_or_default();
+  let mut entries = vec![];
+  let mut output_dir_names = HashMap::<PathBuf, String>::new();
+  let mut workspace_locks = HashMap::<PathBuf, Arc<Mutex<()>>>::new();
+  for entry in manifest.apis.iter() {
+    let builder = entry.to_builder(&manifest_dir);
+    let lib_name = builder.get_lib_name();
+    let request_result = builder.build();
+    let mut workspace_lock_opt = None;
+    if let Ok(request) = request_result.as_ref() {
+      let cli = request.get_cli();
+      let output_dir = cli.get_output_project_dir();
+      if let Some(other_name) = output_dir_names.insert(output_dir.clone(), entry.name.clone()) {
+        return Err(BatchError::SharedOutputDir {
+          name: entry.name.clone(),
+          other_name,
+          output_dir,
+        });
+      }
+      if let Some(workspace_dir) = cli.workspace_dir_opt.as_ref() {
+        workspace_lock_opt = Some(
+          workspace_locks
+            .entry(workspace_dir.clone())
+            .or_default()
+            .clone(),
+        );
+      }
+    }
+    entries.push((
+      entry.name.clone(),
+      lib_name,
+      request_result,
+      workspace_lock_opt,
+    ));
+  }
+  let cancel = cancel.shared();
+  let rows = stream::iter(entries)
+    .map(|(name, lib_name, request_result, workspace_lock_opt)| {
+      let cancel = cancel.clone();
+      async move {
+        let output_dir_opt = request_result
+          .as_ref()
+          .ok()
+          .map(|request| request.get_cli().get_output_project_dir());
+        let result = match request_result {
+          Ok(request) => run_entry(request, workspace_lock_opt, cancel).await,
+          Err(e) => Err(e),
+        };
+        let outcome = match result {
+          Ok(report) => {
+            events::message(format!("Generated `{name}`"));
+            BatchOutcomes::Generated {
+              report: Box::new(report),
+            }
+          }
+          Err(e) => {
+            events::warning(format!("`{name}` failed: {e}"));
+            BatchOutcomes::Failed {
+              diagnostic: Box::new(Diagnostic::from(&e)),
+            }
+          }
+        };
+        BatchRow {
+          name,
+          lib_name,
+          output_dir_opt,
+          outcome,
+        }
+      }
+    })
+    .buffered(args.get_jobs())
+    .collect::<Vec<_>>()
+    .await;
+  let report = BatchReport { rows };
+  if let Some(report_file) = args.report_file_opt.as_ref() {
+    fs::write(report_file, report.to_string(), Some("Batch report")).await?;
+  }
+  Ok(report)
+}
+
+/// Generate an API once no other API is generating into its workspace
+async fn run_entry(
+  request: GenerationRequest,
+  workspace_lock_opt: Option<Arc<Mutex<()>>>,
+  cancel: impl Future<Output = ()>,
+) -> Result<GenerationReport, CLIError> {
+  let _workspace_guard_opt = match workspace_lock_opt.as_ref() {
+    Some(workspace_lock) => Some(workspace_lock.lock().await),
+    None => None,
+  };
+  let request = request.with_process_runner(process::current());
+  // boxed like in the builder, to keep the generation futures off the stack
+  Box::pin(request.run_until(cancel)).await
+}
+
+#[cfg(test)]
+mod test_batch {
+  use super::*;
+  use crate::{
+    fs::{FileSystem, MemoryFileSystem},
+    process::RecordingProcessRunner,
+  };
+  use futures::future;
+  use std::num::NonZeroUsize;
+  use tracing_subscriber::prelude::*;
+  /// Run a batch manifest against memory backends
+  async fn run_manifest(manifest: &str) -> Result<BatchReport, BatchError> {
+    run_manifest_on(Arc::new(MemoryFileSystem::new()), manifest, None).await
+  }
+  /// Run a batch manifest against a memory filesystem, this many APIs at a time
+  async fn run_manifest_on(
+    file_system: Arc<MemoryFileSystem>,
+    manifest: &str,
+    jobs_opt: Option<NonZeroUsize>,
+  ) -> Result<BatchReport, BatchError> {
+    for (path, contents) in [
+      ("/virtual/apis.toml", manifest),
+      ("/virtual/specs/pets.yaml", "openapi: 3.0.0\n"),
+    ] {
+      file_system
+        .write(Path::new(path), contents.as_bytes())
+        .await
+        .expect("must write file");
+    }
+    let args = BatchArgs {
+      manifest_path: PathBuf::from("/virtual/apis.toml"),
+      jobs_opt,
+      ..Default::default()
+    };
+    let run = process::with_process_runner(
+      Arc::new(RecordingProcessRunner::default()),
+      run_batch(&args, future::pending()),
+    );
+    fs::with_file_system(file_system, run).await
+  }
+  #[tokio::test]
+  async fn reports_each_api() {
+    let report = run_manifest(
+      r#"
+        [[apis]]
+        name = "PetShoppe"
+        api-url = "https://www.petshoppe.example"
+        spec-file = "specs/pets.yaml"
+
+        [[apis]]
+        name = "Bad"
+        api-url = "https://bad.example"
+        spec-file = "specs/pets.yaml"
+        lib-name = "bad-client"
+      "#,
+    )
+    .await
+    .expect("must run batch");
+    assert!(!report.is_passing());
+    assert_eq!(
+      report.to_string(),
+      "| api | library | outcome | output dir |\n\
+       |---|---|---|---|\n\
+       | PetShoppe | pet_shoppe_openapi_client | generated | \
+       `/virtual/pet_shoppe_openapi_client` |\n\
+       | Bad | bad-client | **FAILED** |  |\n\
+       \n\
+       ### Failures\n\
+       \n\
+       - `Bad` failed: Library name `bad-client` has characters other than letters, digits and \
+       underscores. Try `bad_client`\n"
+    );
+  }
+  #[tokio::test]
+  async fn refuses_apis_sharing_an_output_dir() {
+    let result = run_manifest(
+      r#"
+        [[apis]]
+        name = "Pets"
+        api-url = "https://pets.example"
+        spec-file = "specs/pets.yaml"
+        output = "client"
+
+        [[apis]]
+        name = "Pets v2"
+        api-url = "https://pets.example/v2"
+        spec-file = "specs/pets.yaml"
+        output = "client"
+      "#,
+    )
+    .await;
+    assert!(matches!(
+      result,
+      Err(BatchError::SharedOutputDir { name, other_name, .. })
+        if name == "Pets v2" && other_name == "Pets"
+    ));
+  }
+  #[tokio::test]
+  async fn each_api_logs_to_its_own_crate() {
+    let subscriber = tracing_subscriber::registry().with(crate::logging::get_run_log_layer());
+    let _subscriber_guard = tracing::subscriber::set_default(subscriber);
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let report = run_manifest_on(
+      file_system.clone(),
+      r#"
+        [[apis]]
+        name = "PetShoppe"
+        api-url = "https://www.petshoppe.example"
+        spec-file = "specs/pets.yaml"
+
+        [[apis]]
+        name = "FishShoppe"
+        api-url = "https://www.fishshoppe.example"
+        spec-file = "specs/pets.yaml"
+      "#,
+      NonZeroUsize::new(2),
+    )
+    .await
+    .expect("must run batch");
+    assert!(report.is_passing(), "{report}");
+    for (lib_name, other_lib_name) in [
+      ("pet_shoppe_openapi_client", "fish_shoppe_openapi_client"),
+      ("fish_shoppe_openapi_client", "pet_shoppe_openapi_client"),
+    ] {
+      let log_file_path = Path::new("/virtual").join(lib_name).join("temp/olg.log");
+      let log = String::from_utf8(
+        file_system
+          .read(&log_file_path)
+          .await
+          .expect("must read log"),
+      )
+      .expect("must be utf-8");
+      assert!(log.contains(&format!("lib_name={lib_name}")), "{log}");
+      assert!(!log.contains(other_lib_name), "{log}");
+    }
+  }
+}
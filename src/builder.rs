@@ -0,0 +1,715 @@
+//! Programmatic generation
+//!
+//! Drives the same pipeline as the `olg` binary from typed settings, without parsing the command line.
+use crate::{
+  cli::{CLIError, Cli, InnerCli, OutputDirModes, Paths, SubCommands},
+  events::{self, GenerationEvent, GenerationObserver, OutputFormats},
+  fs::{self, FileChange, FileSystem, MemoryFileSystem},
+  generate::{
+    crate_scaffolds::{self, MergeReport, ScaffoldTransaction},
+    makefiles::{MakefileSpec, TaskNames},
+    parameters,
+    post_generation::PostGenerationConfig,
+    task_runners::TaskRunners,
+    utils::run_crate_task,
+    yamls::OpenAPIRustGeneratorConfigs,
+    CrateScaffoldingError,
+  },
+  logging,
+  process::{
+    self, ProcessError, ProcessRunner, ProcessSpec, RecordingProcessRunner, SystemProcessRunner,
+  },
+  testing::TestingError,
+};
+use chrono::{DateTime, Utc};
+use futures::future::{self, Future};
+use serde::{Deserialize, Serialize};
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
+use url::Url;
+
+/// Steps of the generation pipeline
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum GenerationSteps {
+  /// The crate was initialized
+  ScaffoldCrate,
+  /// The makefile and the config of its post-generation updates were written
+  WriteMakefile,
+  /// The spec file was copied into the crate
+  CopySpecFile,
+  /// The OpenAPI generator configs were written
+  WriteGeneratorConfigs,
+  /// The spec was downloaded
+  DownloadSpec,
+  /// The library code was generated
+  GenerateCode,
+  /// The generated crate was moved into the output dir
+  Commit,
+}
+
+/// How a generation ended
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum GenerationStatuses {
+  /// The crate was generated, or merged into without conflicts
+  #[default]
+  Generated,
+  /// The crate was merged into, but managed files differ from their generated versions
+  MergeConflicts,
+}
+impl GenerationStatuses {
+  /// Get the process exit code, which follows the ones of
+  /// [ErrorCategories](crate::diagnostics::ErrorCategories)
+  pub fn get_exit_code(&self) -> u8 {
+    match self {
+      Self::Generated => 0,
+      Self::MergeConflicts => 7,
+    }
+  }
+}
+
+/// What a generation did
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GenerationReport {
+  /// The generated library name
+  pub lib_name: String,
+  /// The dir the crate was generated in
+  pub output_dir: PathBuf,
+  /// When generation started
+  pub generation_timestamp: DateTime<Utc>,
+  /// The steps that completed, in order
+  pub completed_steps: Vec<GenerationSteps>,
+  /// How the generation ended
+  #[serde(default)]
+  pub status: GenerationStatuses,
+  /// What happened to each managed file if the output dir was merged into
+  pub merge_report_opt: Option<MergeReport>,
+  /// What would have happened, for a dry run
+  pub plan_opt: Option<GenerationPlan>,
+}
+
+/// What a dry run would have done
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GenerationPlan {
+  /// The files that would change
+  pub file_changes: Vec<FileChange>,
+  /// The commands that would run, in order
+  pub commands: Vec<ProcessSpec>,
+}
+impl fmt::Display for GenerationPlan {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    writeln!(f, "Files that would change:")?;
+    for FileChange { path, kind, .. } in self.file_changes.iter() {
+      writeln!(f, "  {:<8} {}", kind.as_ref(), path.to_string_lossy())?;
+    }
+    writeln!(f, "Commands that would run:")?;
+    for command in self.commands.iter() {
+      writeln!(f, "  $ {command}")?;
+    }
+    for file_change in self.file_changes.iter() {
+      write!(f, "\n{file_change}")?;
+    }
+    Ok(())
+  }
+}
+
+/// Builds a [GenerationRequest] from typed settings
+#[derive(Clone, Debug)]
+pub struct GeneratorBuilder {
+  inner_cli: InnerCli,
+}
+impl GeneratorBuilder {
+  /// Start building a generation for the given site or API name and API url
+  pub fn new(
+    site_or_api_name: impl Into<String>,
+    api_url: Url,
+  ) -> Self {
+    Self {
+      inner_cli: InnerCli {
+        site_or_api_name: site_or_api_name.into(),
+        api_url,
+        autogenerate: true,
+        api_spec_url_opt: None,
+        local_api_spec_filepath_opt: None,
+        lib_name_opt: None,
+        crates_index_dir_opt: None,
+        extra_authors: None,
+        output_project_dir_opt: None,
+        workspace_dir_opt: None,
+        task_runner: TaskRunners::default(),
+        extra_tasks_filepath_opt: None,
+        readme_task_graph: false,
+        keep_failed: false,
+        force: false,
+        merge: false,
+        dry_run: false,
+        step_timeout_secs_opt: None,
+        output_format: OutputFormats::Text,
+        verbose: 0,
+        quiet: 0,
+        command: None,
+      },
+    }
+  }
+  /// Fetch the spec from this url
+  pub fn spec_url(
+    mut self,
+    api_spec_url: Url,
+  ) -> Self {
+    self.inner_cli.api_spec_url_opt = Some(api_spec_url);
+    self
+  }
+  /// Use this local spec file
+  pub fn spec_file(
+    mut self,
+    local_api_spec_filepath: impl Into<PathBuf>,
+  ) -> Self {
+    self.inner_cli.local_api_spec_filepath_opt = Some(local_api_spec_filepath.into());
+    self
+  }
+  /// Override the default library name
+  pub fn lib_name(
+    mut self,
+    lib_name: impl Into<String>,
+  ) -> Self {
+    self.inner_cli.lib_name_opt = Some(lib_name.into());
+    self
+  }
+  /// Fail if the library name is taken according to this local crates.io index checkout
+  pub fn crates_index(
+    mut self,
+    crates_index_dir: impl Into<PathBuf>,
+  ) -> Self {
+    self.inner_cli.crates_index_dir_opt = Some(crates_index_dir.into());
+    self
+  }
+  /// Add extra authors
+  pub fn extra_authors<T: AsRef<str>>(
+    mut self,
+    extra_authors: &[T],
+  ) -> Self {
+    let extra_authors = extra_authors
+      .iter()
+      .map(AsRef::as_ref)
+      .collect::<Vec<_>>()
+      .join(";");
+    self.inner_cli.extra_authors = Some(extra_authors);
+    self
+  }
+  /// Generate into this dir instead of the current dir
+  pub fn output_dir(
+    mut self,
+    output_project_dir: impl Into<PathBuf>,
+  ) -> Self {
+    self.inner_cli.output_project_dir_opt = Some(output_project_dir.into());
+    self
+  }
+  /// Generate the crate as a member of the Cargo workspace in this dir
+  ///
+  /// Unless [Self::output_dir] is set, the crate is generated in a dir named after the library.
+  pub fn workspace(
+    mut self,
+    workspace_dir: impl Into<PathBuf>,
+  ) -> Self {
+    self.inner_cli.workspace_dir_opt = Some(workspace_dir.into());
+    self
+  }
+  /// Write the tasks of the crate for this task runner instead of cargo-make
+  pub fn task_runner(
+    mut self,
+    task_runner: TaskRunners,
+  ) -> Self {
+    self.inner_cli.task_runner = task_runner;
+    self
+  }
+  /// Merge the extra `[env]` and `[tasks]` entries of this TOML file into the `Makefile.toml`
+  pub fn extra_tasks(
+    mut self,
+    extra_tasks_filepath: impl Into<PathBuf>,
+  ) -> Self {
+    self.inner_cli.extra_tasks_filepath_opt = Some(extra_tasks_filepath.into());
+    self
+  }
+  /// Whether to describe the tasks of the crate in its README with a Mermaid graph
+  pub fn readme_task_graph(
+    mut self,
+    readme_task_graph: bool,
+  ) -> Self {
+    self.inner_cli.readme_task_graph = readme_task_graph;
+    self
+  }
+  /// Whether to generate the code after scaffolding the crate (enabled by default)
+  pub fn autogenerate(
+    mut self,
+    autogenerate: bool,
+  ) -> Self {
+    self.inner_cli.autogenerate = autogenerate;
+    self
+  }
+  /// Whether to keep the staging dir of a failed generation
+  pub fn keep_failed(
+    mut self,
+    keep_failed: bool,
+  ) -> Self {
+    self.inner_cli.keep_failed = keep_failed;
+    self
+  }
+  /// What to do with a non-empty output dir
+  pub fn output_dir_mode(
+    mut self,
+    output_dir_mode: OutputDirModes,
+  ) -> Self {
+    self.inner_cli.force = output_dir_mode == OutputDirModes::Force;
+    self.inner_cli.merge = output_dir_mode == OutputDirModes::Merge;
+    self
+  }
+  /// Kill any step's subprocess that runs for longer than this
+  pub fn step_timeout(
+    mut self,
+    step_timeout: Duration,
+  ) -> Self {
+    self.inner_cli.step_timeout_secs_opt = Some(step_timeout.as_secs().max(1));
+    self
+  }
+  /// Only plan the generation (see [GenerationReport::plan_opt])
+  pub fn dry_run(
+    mut self,
+    dry_run: bool,
+  ) -> Self {
+    self.inner_cli.dry_run = dry_run;
+    self
+  }
+  /// Run as a test generation (see [SubCommands::TestGeneration])
+  pub fn test_generation(
+    mut self,
+    generator_crate_local_path_opt: Option<PathBuf>,
+    generator_crate_repo_url_opt: Option<Url>,
+  ) -> Self {
+    self.inner_cli.command = Some(SubCommands::TestGeneration {
+      generator_crate_local_path_opt,
+      generator_crate_repo_url_opt,
+    });
+    self
+  }
+  /// Get the library name the settings give
+  pub fn get_lib_name(&self) -> String {
+    self.inner_cli.get_lib_name()
+  }
+  /// Validate the settings
+  pub fn build(self) -> Result<GenerationRequest, CLIError> {
+    Cli::try_from_inner_cli(self.inner_cli).map(GenerationRequest::from)
+  }
+}
+
+/// A validated generation ready to run
+#[derive(Clone)]
+pub struct GenerationRequest {
+  cli: Cli,
+  observer_opt: Option<Arc<dyn GenerationObserver>>,
+  file_system_opt: Option<Arc<dyn FileSystem>>,
+  process_runner_opt: Option<Arc<dyn ProcessRunner>>,
+}
+impl fmt::Debug for GenerationRequest {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    f.debug_struct("GenerationRequest")
+      .field("cli", &self.cli)
+      .field("has_observer", &self.observer_opt.is_some())
+      .field("has_file_system", &self.file_system_opt.is_some())
+      .field("has_process_runner", &self.process_runner_opt.is_some())
+      .finish()
+  }
+}
+impl From<Cli> for GenerationRequest {
+  fn from(cli: Cli) -> Self {
+    Self {
+      cli,
+      observer_opt: None,
+      file_system_opt: None,
+      process_runner_opt: None,
+    }
+  }
+}
+impl GenerationRequest {
+  /// Get the settings
+  pub fn get_cli(&self) -> &Cli {
+    &self.cli
+  }
+  /// Send generation events to this observer instead of printing them
+  pub fn with_observer(
+    mut self,
+    observer: Arc<dyn GenerationObserver>,
+  ) -> Self {
+    self.observer_opt = Some(observer);
+    self
+  }
+  /// Read and write files through this filesystem instead of the disk
+  pub fn with_file_system(
+    mut self,
+    file_system: Arc<dyn FileSystem>,
+  ) -> Self {
+    self.file_system_opt = Some(file_system);
+    self
+  }
+  /// Run subprocesses through this runner instead of spawning them
+  pub fn with_process_runner(
+    mut self,
+    process_runner: Arc<dyn ProcessRunner>,
+  ) -> Self {
+    self.process_runner_opt = Some(process_runner);
+    self
+  }
+  /// Record a completed step
+  fn complete_step(
+    completed_steps: &mut Vec<GenerationSteps>,
+    step: GenerationSteps,
+  ) {
+    completed_steps.push(step);
+    events::emit(GenerationEvent::StepCompleted { step });
+  }
+  /// Run a step, recording it if it completes or attributing its failure to it if not
+  async fn run_step<E: Into<CLIError>>(
+    completed_steps: &mut Vec<GenerationSteps>,
+    step: GenerationSteps,
+    f: impl Future<Output = Result<(), E>>,
+  ) -> Result<(), CLIError> {
+    f.await.map_err(|e| e.into().in_step(step))?;
+    Self::complete_step(completed_steps, step);
+    Ok(())
+  }
+  /// Run the generation
+  pub async fn run(&self) -> Result<GenerationReport, CLIError> {
+    self.run_until(future::pending::<()>()).await
+  }
+  /// Run the generation, rolling back if `cancel` finishes first
+  ///
+  /// The run keeps its log records apart from the ones of any concurrent run.
+  pub async fn run_until(
+    &self,
+    cancel: impl Future<Output = ()>,
+  ) -> Result<GenerationReport, CLIError> {
+    let run = self.run_with_backends(cancel);
+    let run = async {
+      match self.observer_opt.as_ref() {
+        Some(observer) => events::with_observer(observer.clone(), run).await,
+        None => run.await,
+      }
+    };
+    // boxed to keep the generation future off the stack
+    logging::with_run_log(Box::pin(run)).await
+  }
+  /// Run the generation with files and subprocesses going through the backends in use
+  ///
+  /// A dry run always uses a [MemoryFileSystem] over the disk and a [RecordingProcessRunner].
+  async fn run_with_backends(
+    &self,
+    cancel: impl Future<Output = ()>,
+  ) -> Result<GenerationReport, CLIError> {
+    if self.cli.dry_run {
+      let file_system = Arc::new(MemoryFileSystem::overlaying_disk());
+      let process_runner = Arc::new(RecordingProcessRunner::default());
+      let run = process::with_process_runner(process_runner.clone(), self.run_observed(cancel));
+      let mut report = fs::with_file_system(file_system.clone(), run).await?;
+      report.plan_opt = Some(GenerationPlan {
+        file_changes: file_system.get_changes().await?,
+        commands: process_runner.get_runs(),
+      });
+      return Ok(report);
+    }
+    let file_system = self.file_system_opt.clone().unwrap_or_else(fs::current);
+    let process_runner = self.process_runner_opt.clone().unwrap_or_else(|| {
+      let step_timeout_opt = self.cli.get_step_timeout_opt();
+      Arc::new(SystemProcessRunner::with_default_timeout(step_timeout_opt))
+    });
+    let run = process::with_process_runner(process_runner, self.run_observed(cancel));
+    fs::with_file_system(file_system, run).await
+  }
+  /// Write the run log without failing the run over it
+  async fn write_log(log_file_path: &Path) {
+    match logging::write_run_log(log_file_path).await {
+      Ok(true) => tracing::debug!(?log_file_path, "wrote run log"),
+      Ok(false) => {}
+      Err(e) => events::warning(format!(
+        "Could not write the log to `{}`: {e}",
+        log_file_path.to_string_lossy()
+      )),
+    }
+  }
+  /// Run the generation with events going to the observer in scope
+  #[tracing::instrument(level = "info", skip_all, fields(lib_name = %self.cli.get_lib_name()))]
+  async fn run_observed(
+    &self,
+    cancel: impl Future<Output = ()>,
+  ) -> Result<GenerationReport, CLIError> {
+    let cli = &self.cli;
+    let mut report = GenerationReport {
+      lib_name: cli.get_lib_name(),
+      output_dir: cli.get_output_project_dir(),
+      generation_timestamp: cli.generation_timestamp,
+      completed_steps: vec![],
+      status: GenerationStatuses::Generated,
+      merge_report_opt: None,
+      plan_opt: None,
+    };
+    if let Some(crates_index_dir) = cli.crates_index_dir_opt.as_ref() {
+      parameters::check_lib_name_availability(&report.lib_name, crates_index_dir).await?;
+    }
+    let transaction = ScaffoldTransaction::begin(cli).await?;
+    let result = tokio::select! {
+      // boxed to keep the deeply nested generation future off the stack of worker threads
+      result = Box::pin(Self::generate(transaction.get_staged_cli(), transaction.is_merging(), &mut report.completed_steps)) => result,
+      _ = cancel => Err(CrateScaffoldingError::Interrupted.into()),
+    };
+    match result {
+      Ok(()) => {
+        report.merge_report_opt = transaction
+          .commit()
+          .await
+          .map_err(|e| CLIError::from(e).in_step(GenerationSteps::Commit))?;
+        Self::complete_step(&mut report.completed_steps, GenerationSteps::Commit);
+        if let Some(merge_report) = report.merge_report_opt.as_ref() {
+          if !merge_report.conflicts.is_empty() {
+            report.status = GenerationStatuses::MergeConflicts;
+          }
+        }
+        if !cli.dry_run {
+          Self::write_log(&logging::get_run_log_file_path(cli).await).await;
+        }
+        Ok(report)
+      }
+      Err(e) => {
+        // the caller reports the error itself, so keep this out of the default terminal log
+        tracing::info!(error = %e, "generation failed");
+        // the output dir may have to stay empty, so only the kept staging dir gets the log
+        let log_file_path = if cli.keep_failed {
+          transaction
+            .get_staged_cli()
+            .get_output_project_subpath(&Paths::LogFile)
+        } else {
+          logging::get_fallback_log_file_path(cli)
+        };
+        transaction.rollback().await?;
+        if !cli.dry_run {
+          Self::write_log(&log_file_path).await;
+          events::warning(format!(
+            "See the log at `{}`",
+            log_file_path.to_string_lossy()
+          ));
+        }
+        Err(e)
+      }
+    }
+  }
+  /// Run every generation step
+  ///
+  /// The code is not generated when merging into an existing crate.
+  #[tracing::instrument(level = "debug", skip_all)]
+  async fn generate(
+    cli: &Cli,
+    is_merging: bool,
+    completed_steps: &mut Vec<GenerationSteps>,
+  ) -> Result<(), CLIError> {
+    use GenerationSteps::*;
+    Self::run_step(
+      completed_steps,
+      ScaffoldCrate,
+      crate_scaffolds::scaffold_crate(cli),
+    )
+    .await?;
+    Self::run_step(completed_steps, WriteMakefile, async {
+      let makefile_spec = MakefileSpec::try_from(cli)?
+        .merge_user_additions(cli)
+        .await?;
+      makefile_spec.write_task_files(cli).await?;
+      PostGenerationConfig::new(cli, &makefile_spec)?
+        .write_to_yaml_file(cli)
+        .await?;
+      Ok::<_, CLIError>(())
+    })
+    .await?;
+    let rust_generator_configs = OpenAPIRustGeneratorConfigs::new(cli);
+    Self::run_step(
+      completed_steps,
+      CopySpecFile,
+      rust_generator_configs.copy_spec_file(cli),
+    )
+    .await?;
+    Self::run_step(
+      completed_steps,
+      WriteGeneratorConfigs,
+      rust_generator_configs.write_to_yaml_file(cli),
+    )
+    .await?;
+    Self::run_subcommands(cli, is_merging, completed_steps).await
+  }
+  /// Run a task in the crate with its task runner, failing if the task does
+  async fn run_task(
+    cli: &Cli,
+    task_name: TaskNames,
+  ) -> Result<(), ProcessError> {
+    run_crate_task(cli, task_name).await.map(|_| ())
+  }
+  /// Run a subcommand
+  async fn run_subcommands(
+    cli: &Cli,
+    is_merging: bool,
+    completed_steps: &mut Vec<GenerationSteps>,
+  ) -> Result<(), CLIError> {
+    let InnerCli {
+      api_spec_url_opt,
+      autogenerate,
+      ..
+    } = &cli.inner_cli;
+    match cli.command.as_ref() {
+      Some(SubCommands::TestGeneration { .. }) => {
+        Self::run_step(completed_steps, GenerationSteps::GenerateCode, async {
+          Self::run_task(cli, TaskNames::GenerateAll)
+            .await
+            .map_err(TestingError::from)
+        })
+        .await?;
+      }
+      // tool commands are run before any generation
+      None | Some(SubCommands::Tool(_)) => {
+        if is_merging {
+          let task_spec = cli
+            .task_runner
+            .get_task_spec(TaskNames::GenerateAll.as_ref());
+          events::message(format!(
+            "Skipping code generation while merging. Run `{}` in the crate.",
+            task_spec.get_command_line()
+          ));
+        } else if *autogenerate && api_spec_url_opt.is_some() {
+          Self::run_step(
+            completed_steps,
+            GenerationSteps::DownloadSpec,
+            Self::run_task(cli, TaskNames::SpecDownloadDefault),
+          )
+          .await?;
+          Self::run_step(
+            completed_steps,
+            GenerationSteps::GenerateCode,
+            Self::run_task(cli, TaskNames::GenerateAll),
+          )
+          .await?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test_builder {
+  use super::*;
+  use crate::{generate::errors::ParameterError, testing};
+  #[test]
+  fn build_requires_a_spec_source() {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let result = GeneratorBuilder::new(testing::TEST_API_NAME, api_url.clone()).build();
+    assert!(matches!(
+      result,
+      Err(CLIError::ParameterError(
+        ParameterError::APIUrlNeededIfNoLocalFile
+      ))
+    ));
+    let request = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file("spec.yaml")
+      .output_dir("out")
+      .build()
+      .expect("must build request");
+    assert_eq!(
+      request.get_cli().get_output_project_dir(),
+      PathBuf::from("out")
+    );
+  }
+  #[tokio::test]
+  async fn runs_against_memory_backends() {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let (spec_path, output_dir) = (Path::new("/virtual/spec.yaml"), Path::new("/virtual/out"));
+    let file_system = Arc::new(MemoryFileSystem::new());
+    file_system
+      .write(spec_path, b"openapi: 3.0.0\n")
+      .await
+      .expect("must write spec");
+    let process_runner = Arc::new(RecordingProcessRunner::default());
+    let report = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file(spec_path)
+      .output_dir(output_dir)
+      .autogenerate(false)
+      .build()
+      .expect("must build request")
+      .with_file_system(file_system.clone())
+      .with_process_runner(process_runner.clone())
+      .run()
+      .await
+      .expect("must generate");
+    assert_eq!(
+      report.completed_steps.last(),
+      Some(&GenerationSteps::Commit)
+    );
+    assert_eq!(
+      file_system.get_files().into_keys().collect::<Vec<_>>(),
+      [
+        ".gitignore",
+        "Makefile.toml",
+        "generator_config.yaml",
+        "post_generation.yaml",
+        "spec.yaml",
+      ]
+      .map(|name| output_dir.join(name))
+      .into_iter()
+      .chain([spec_path.to_path_buf()])
+      .collect::<Vec<_>>()
+    );
+    let runs = process_runner.get_runs();
+    assert_eq!(runs.len(), 1, "unexpected runs: {runs:?}");
+    assert_eq!(runs[0].program, "cargo");
+    assert_eq!(runs[0].args[0], "init");
+  }
+  #[tokio::test]
+  async fn merges_only_into_an_existing_crate() {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let spec_url = Url::parse("https://www.petshoppe.example/spec.yaml").expect("must parse url");
+    let output_dir = Path::new("/virtual/out");
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let request = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_url(spec_url)
+      .output_dir(output_dir)
+      .output_dir_mode(OutputDirModes::Merge)
+      .build()
+      .expect("must build request")
+      .with_file_system(file_system.clone())
+      .with_process_runner(Arc::new(RecordingProcessRunner::default()));
+    let report = request.run().await.expect("must generate");
+    assert!(report
+      .completed_steps
+      .contains(&GenerationSteps::GenerateCode));
+    assert_eq!(report.status, GenerationStatuses::Generated);
+    file_system
+      .write(&output_dir.join(".gitignore"), b"/target\n/local\n")
+      .await
+      .expect("must edit gitignore");
+    let report = request.run().await.expect("must merge");
+    assert!(!report
+      .completed_steps
+      .contains(&GenerationSteps::GenerateCode));
+    assert_eq!(report.status, GenerationStatuses::MergeConflicts);
+    assert_eq!(
+      report.merge_report_opt.expect("must merge").conflicts,
+      vec![output_dir.join(".gitignore")]
+    );
+  }
+}
@@ -1,14 +1,27 @@
 //! CLI Data
 use crate::{
-  generate::{errors::*, parameters, utils},
+  batch::BatchError,
+  builder::GenerationSteps,
+  events::OutputFormats,
+  generate::{
+    errors::*, parameters, task_graphs::GraphFormats, task_runners::TaskRunners, utils, workspaces,
+  },
+  self_update::{SelfUpdateError, CRATES_IO_INDEX_URL},
   testing,
+  wizard::WizardError,
 };
 use chrono::{DateTime, Utc};
-use clap::{Parser, Subcommand};
+use clap::{
+  builder::PossibleValuesParser, ArgAction, ArgMatches, Args, CommandFactory, FromArgMatches,
+  Parser, Subcommand,
+};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Error as SerdeYAMLError;
-use std::{env, io::Error as IOError, ops::Deref, path::PathBuf};
+use std::{
+  env, ffi::OsString, io::Error as IOError, num::NonZeroUsize, ops::Deref, path::PathBuf, thread,
+  time::Duration,
+};
 use strum::EnumProperty;
 use thiserror::Error;
 use url::Url;
@@ -17,7 +30,7 @@ use url::Url;
 mod defaults {
   use super::*;
   /// Current working directory
-  pub const CWD: Lazy<PathBuf> = Lazy::new(|| env::current_dir().expect("must get current dir"));
+  pub static CWD: Lazy<PathBuf> = Lazy::new(|| env::current_dir().expect("must get current dir"));
 }
 use defaults::*;
 
@@ -25,6 +38,9 @@ use defaults::*;
 pub struct Cli {
   pub inner_cli: InnerCli,
   pub generation_timestamp: DateTime<Utc>,
+  /// Where the crate ends up if it is being built somewhere else first
+  #[serde(default)]
+  pub final_output_project_dir_opt: Option<PathBuf>,
 }
 impl Deref for Cli {
   type Target = InnerCli;
@@ -37,9 +53,41 @@ impl Cli {
   pub fn get_generation_timestamp_string(&self) -> String {
     self.generation_timestamp.to_rfc3339()
   }
-  /// Instantiate
-  pub async fn new() -> Result<Self, CLIError> {
-    let mut inner_cli = InnerCli::parse();
+  /// Get the dir the crate ends up in, even while it is being staged somewhere else
+  pub fn get_final_output_project_dir(&self) -> PathBuf {
+    self
+      .final_output_project_dir_opt
+      .clone()
+      .unwrap_or_else(|| self.get_output_project_dir())
+  }
+  /// Get the dir the crate ends up in as a string
+  pub fn get_final_output_project_dir_string(&self) -> String {
+    self
+      .get_final_output_project_dir()
+      .to_string_lossy()
+      .to_string()
+  }
+  /// Make a copy that builds the crate in a staging dir instead of the output dir
+  ///
+  /// A local spec file inside the output dir is moved along with it.
+  pub fn staged(
+    &self,
+    staging_dir: PathBuf,
+  ) -> Self {
+    let mut staged = self.clone();
+    let final_output_project_dir = self.get_final_output_project_dir();
+    if let Some(local_api_spec_filepath) = staged.inner_cli.local_api_spec_filepath_opt.as_mut() {
+      if let Ok(relative_path) = local_api_spec_filepath.strip_prefix(&final_output_project_dir) {
+        *local_api_spec_filepath = staging_dir.join(relative_path);
+      }
+    }
+    staged.inner_cli.output_project_dir_opt = Some(staging_dir);
+    staged.final_output_project_dir_opt = Some(final_output_project_dir);
+    staged
+  }
+  /// Instantiate from already parsed or programmatically built settings
+  #[tracing::instrument(level = "debug", skip_all, fields(name = %inner_cli.site_or_api_name))]
+  pub fn try_from_inner_cli(mut inner_cli: InnerCli) -> Result<Self, CLIError> {
     let InnerCli {
       command,
       output_project_dir_opt,
@@ -48,24 +96,41 @@ impl Cli {
     } = &mut inner_cli;
     if let Some(SubCommands::TestGeneration { .. }) = command.as_mut() {
       // use the temp directory
-      let temp_subdir_path = utils::get_temp_subdir();
+      let temp_subdir_path = output_project_dir_opt.get_or_insert_with(utils::get_temp_subdir);
       if local_api_spec_filepath_opt.is_none() {
         let yaml_spec_file_name = testing::TESTING_SPEC_FILE_NAME;
         let yaml_test_spec_path = temp_subdir_path.join(yaml_spec_file_name);
         let _ = local_api_spec_filepath_opt.replace(yaml_test_spec_path);
       }
-      if output_project_dir_opt.is_none() {
-        let _ = output_project_dir_opt.replace(temp_subdir_path);
-      }
     } else if inner_cli.local_api_spec_filepath_opt.is_none()
       && inner_cli.api_spec_url_opt.is_none()
     {
       return Err(ParameterError::APIUrlNeededIfNoLocalFile.into());
     }
-    Ok(Self {
+    if let Some(lib_name) = inner_cli.lib_name_opt.as_ref() {
+      parameters::validate_lib_name(lib_name)?;
+    }
+    if let Some(workspace_dir) = inner_cli.workspace_dir_opt.as_ref() {
+      // member paths are worked out from absolute dirs
+      let workspace_dir = workspaces::normalize_path(&CWD.join(workspace_dir));
+      let output_project_dir = match inner_cli.output_project_dir_opt.as_ref() {
+        Some(output_project_dir) => workspaces::normalize_path(&CWD.join(output_project_dir)),
+        None => workspace_dir.join(inner_cli.get_lib_name()),
+      };
+      inner_cli.workspace_dir_opt = Some(workspace_dir);
+      inner_cli.output_project_dir_opt = Some(output_project_dir);
+    }
+    if let Some(extra_tasks_filepath) = inner_cli.extra_tasks_filepath_opt.as_mut() {
+      // the recorded settings get used from within the crate
+      *extra_tasks_filepath = workspaces::normalize_path(&CWD.join(&extra_tasks_filepath));
+    }
+    let cli = Self {
       generation_timestamp: Utc::now(),
       inner_cli,
-    })
+      final_output_project_dir_opt: None,
+    };
+    tracing::debug!(?cli, "validated settings");
+    Ok(cli)
   }
 }
 
@@ -75,23 +140,53 @@ pub enum CLIError {
   #[error(transparent)]
   IOError(#[from] IOError),
   #[error(transparent)]
+  BatchError(#[from] BatchError),
+  #[error(transparent)]
   CargoConfigError(#[from] CargoConfigError),
   #[error(transparent)]
+  ChangelogGenerationError(#[from] ChangelogGenerationError),
+  #[error(transparent)]
   CrateScaffoldingError(#[from] CrateScaffoldingError),
   #[error(transparent)]
   MakefileGenerationError(#[from] MakefileGenerationError),
   #[error(transparent)]
   ParameterError(#[from] ParameterError),
   #[error(transparent)]
-  ProcessError(#[from] utils::ProcessError),
+  PostGenerationError(#[from] PostGenerationError),
+  #[error(transparent)]
+  ProcessError(#[from] ProcessError),
   #[error(transparent)]
   READMEGenerationError(#[from] READMEGenerationError),
   #[error(transparent)]
+  SelfUpdateError(#[from] SelfUpdateError),
+  #[error(transparent)]
   SerdeYAMLError(#[from] SerdeYAMLError),
   #[error(transparent)]
   YAMLGenerationError(#[from] YAMLGenerationError),
   #[error(transparent)]
   TestingError(#[from] testing::TestingError),
+  #[error(transparent)]
+  WizardError(#[from] WizardError),
+  #[error("Step `{}` failed: {source}", step.as_ref())]
+  StepFailed {
+    step: GenerationSteps,
+    source: Box<CLIError>,
+  },
+}
+impl CLIError {
+  /// Attribute this error to a generation step
+  pub fn in_step(
+    self,
+    step: GenerationSteps,
+  ) -> Self {
+    match self {
+      Self::StepFailed { .. } => self,
+      e => Self::StepFailed {
+        step,
+        source: Box::new(e),
+      },
+    }
+  }
 }
 
 /// Subcommands for the [InnerCli]
@@ -113,6 +208,238 @@ pub enum SubCommands {
     #[arg(short = 'u', long = "generator-crate-repo-url")]
     generator_crate_repo_url_opt: Option<Url>,
   },
+  /// Commands that take no generation settings
+  #[command(flatten)]
+  Tool(ToolCommands),
+}
+
+/// Commands that do not generate a crate of their own, so take no `--name` or `--api-url`
+#[derive(Clone, Debug, Deserialize, Serialize, Subcommand)]
+pub enum ToolCommands {
+  /// Generates a crate from every spec of the testing corpus and reports which stages pass
+  ///
+  /// Each spec is validated, generated, compiled and tested in turn. A stage only runs if the one
+  /// before it passed. Prints the results as a markdown table and exits with 1 if any stage failed.
+  #[command(verbatim_doc_comment)]
+  TestMatrix(TestMatrixArgs),
+  /// Generates a client for every API listed in a TOML manifest
+  ///
+  /// Each `[[apis]]` entry takes the settings of a generation in kebab case: `name`, `api-url`,
+  /// `spec-url` or `spec-file`, and optionally `lib-name`, `output`, `workspace`, `task-runner`,
+  /// `extra-tasks`, `readme-task-graph`, `authors`, `autogenerate`, `force`, `merge` and `keep-failed`. Paths are relative to the manifest, and a
+  /// client outside of a workspace is generated next to it by default. Prints a markdown table of
+  /// which APIs were generated and exits with 1 if any failed.
+  #[command(verbatim_doc_comment)]
+  Batch(BatchArgs),
+  /// Asks for the settings of a client, then generates it or saves them for later
+  ///
+  /// Each answer is checked before the next question, and the spec is read and summarized. The
+  /// settings are shown before generating the crate or adding them to a config that `olg batch`
+  /// can run.
+  #[command(verbatim_doc_comment)]
+  Init(InitArgs),
+  /// Brings the manifest, README and changelog of a generated crate up to par after code generation
+  ///
+  /// Run it in the crate dir. It reads the settings the generation recorded in the crate, and is
+  /// what the `generate-all` task of the crate runs last.
+  #[command(verbatim_doc_comment)]
+  PostGenerate,
+  /// Writes the task files of a generated crate again from the settings it was generated with
+  ///
+  /// Run it in the crate dir. Tasks written by an older version of olg are replaced with the ones of
+  /// this version.
+  #[command(verbatim_doc_comment)]
+  RegenerateTasks,
+  /// Moves a generated crate to the newest published version of olg
+  ///
+  /// Run it in the crate dir. The newest version is installed in the temp dir of the crate and
+  /// writes the task files again, then the olg dev-dependency of the crate is bumped. Prints which
+  /// tasks changed.
+  #[command(verbatim_doc_comment)]
+  SelfUpdate(SelfUpdateArgs),
+  /// Looks into the tasks of a generated crate
+  ///
+  /// Run it in the crate dir. The tasks are the ones of this version of olg for the settings the
+  /// crate was generated with, along with the tasks users added to its makefile.
+  #[command(verbatim_doc_comment, subcommand)]
+  Tasks(TasksCommands),
+}
+
+/// Subcommands of [ToolCommands::Tasks]
+#[derive(Clone, Debug, Deserialize, Serialize, Subcommand)]
+pub enum TasksCommands {
+  /// Renders the tasks and the tasks each of them runs first
+  Graph(TaskGraphArgs),
+}
+
+/// Settings of [TasksCommands::Graph]
+#[derive(Args, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TaskGraphArgs {
+  /// The format of the graph
+  #[arg(long = "format", value_enum, default_value_t = GraphFormats::Text)]
+  pub format: GraphFormats,
+}
+
+/// Settings of [ToolCommands::TestMatrix]
+#[derive(Args, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TestMatrixArgs {
+  /// Only test this spec of the corpus (repeatable)
+  #[arg(
+    long = "spec",
+    value_name = "NAME",
+    value_parser = PossibleValuesParser::new(testing::specs::get_corpus_spec_names())
+  )]
+  pub spec_names: Vec<String>,
+  /// The dir to generate the crates in. A spec's crate from an earlier run is replaced
+  #[arg(long = "output", value_name = "DIR")]
+  pub work_dir_opt: Option<PathBuf>,
+  /// Also write the markdown table to this file
+  #[arg(long = "report", value_name = "FILE")]
+  pub report_file_opt: Option<PathBuf>,
+  /// Kill any stage's subprocess that runs for longer than this many seconds
+  #[arg(long = "step-timeout", value_name = "SECONDS")]
+  pub step_timeout_secs_opt: Option<u64>,
+}
+impl TestMatrixArgs {
+  /// Get the dir the crates are generated in
+  pub fn get_work_dir(&self) -> PathBuf {
+    self
+      .work_dir_opt
+      .clone()
+      .unwrap_or_else(|| utils::get_temp_subdir().join("test-matrix"))
+  }
+  /// Get the corpus specs to test, all of them if none were picked
+  pub fn get_corpus_specs(&self) -> Vec<&'static testing::specs::CorpusSpec> {
+    testing::specs::SPEC_CORPUS
+      .iter()
+      .filter(|spec| self.spec_names.is_empty() || self.spec_names.iter().any(|n| n == spec.name))
+      .collect()
+  }
+  /// Get how long a stage's subprocess may run
+  pub fn get_step_timeout_opt(&self) -> Option<Duration> {
+    self.step_timeout_secs_opt.map(Duration::from_secs)
+  }
+}
+
+/// Settings of [ToolCommands::Batch]
+#[derive(Args, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BatchArgs {
+  /// The TOML manifest listing the APIs
+  #[arg(value_name = "MANIFEST")]
+  pub manifest_path: PathBuf,
+  /// How many APIs to generate at a time, as many as there are CPUs by default
+  #[arg(short = 'j', long = "jobs", value_name = "N")]
+  pub jobs_opt: Option<NonZeroUsize>,
+  /// Also write the markdown table to this file
+  #[arg(long = "report", value_name = "FILE")]
+  pub report_file_opt: Option<PathBuf>,
+  /// Kill any step's subprocess that runs for longer than this many seconds
+  #[arg(long = "step-timeout", value_name = "SECONDS")]
+  pub step_timeout_secs_opt: Option<u64>,
+}
+impl BatchArgs {
+  /// Get how many APIs to generate at a time
+  pub fn get_jobs(&self) -> usize {
+    self
+      .jobs_opt
+      .or_else(|| thread::available_parallelism().ok())
+      .map_or(1, NonZeroUsize::get)
+  }
+  /// Get how long a step's subprocess may run
+  pub fn get_step_timeout_opt(&self) -> Option<Duration> {
+    self.step_timeout_secs_opt.map(Duration::from_secs)
+  }
+}
+
+/// Settings of [ToolCommands::Init]
+#[derive(Args, Clone, Debug, Deserialize, Serialize)]
+pub struct InitArgs {
+  /// The config to add the settings to if not generating right away
+  #[arg(long = "config", value_name = "FILE", default_value = "olg.toml")]
+  pub config_path: PathBuf,
+}
+
+/// Settings of [ToolCommands::SelfUpdate]
+#[derive(Args, Clone, Debug, Deserialize, Serialize)]
+pub struct SelfUpdateArgs {
+  /// The index to look the newest version up in: the URL of a sparse index, or a local checkout of a
+  /// git index
+  #[arg(long = "index", value_name = "URL|DIR", default_value = CRATES_IO_INDEX_URL)]
+  pub index: String,
+  /// Only report whether a newer version is out
+  #[arg(long = "check")]
+  pub check_only: bool,
+}
+
+/// A command line running one of the [ToolCommands], read from the matches of the [InnerCli]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ToolCli {
+  pub command: ToolCommands,
+  pub output_format: OutputFormats,
+  pub verbose: u8,
+  pub quiet: u8,
+}
+impl ToolCli {
+  /// Get the terminal verbosity, `-v` count minus `-q` count
+  pub fn get_verbosity(&self) -> i8 {
+    get_verbosity(self.verbose, self.quiet)
+  }
+}
+
+/// What the command line asks for
+#[derive(Clone, Debug)]
+pub enum CommandLines {
+  /// Generate a crate
+  Generation(Box<InnerCli>),
+  /// Run one of the [ToolCommands]
+  Tool(ToolCli),
+}
+impl CommandLines {
+  /// Parse the command line, exiting with the usage on errors and for `--help`
+  pub fn parse_from<I, T>(args: I) -> Self
+  where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+  {
+    Self::try_parse_from(args).unwrap_or_else(|e| e.exit())
+  }
+  /// Parse the command line
+  pub fn try_parse_from<I, T>(args: I) -> Result<Self, clap::Error>
+  where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+  {
+    let mut command = InnerCli::command();
+    let matches = command.try_get_matches_from_mut(args)?;
+    Self::from_matches(&matches).map_err(|e| e.format(&mut command))
+  }
+  /// Read the command line from its matches
+  ///
+  /// A tool command lifts the requirement of the generation settings, so it is read on its own.
+  fn from_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+    match matches.subcommand_name() {
+      Some(name) if ToolCommands::has_subcommand(name) => Ok(Self::Tool(ToolCli {
+        command: ToolCommands::from_arg_matches(matches)?,
+        output_format: matches
+          .get_one::<OutputFormats>("output_format")
+          .copied()
+          .unwrap_or_default(),
+        verbose: matches.get_count("verbose"),
+        quiet: matches.get_count("quiet"),
+      })),
+      _ => {
+        InnerCli::from_arg_matches(matches).map(|inner_cli| Self::Generation(Box::new(inner_cli)))
+      }
+    }
+  }
+}
+
+/// Get how much to print from the counts of `-v` and `-q`
+fn get_verbosity(
+  verbose: u8,
+  quiet: u8,
+) -> i8 {
+  (verbose.min(i8::MAX as u8) as i8).saturating_sub(quiet.min(i8::MAX as u8) as i8)
 }
 
 /// =================== OpenAPI client  crate generator ====================
@@ -129,19 +456,26 @@ pub enum SubCommands {
 /// 1. Provide an OpenAPI-compliant spec file (yaml or json):
 ///   - (If you specified a `spec-url`): Run `cargo make spec-download-default`
 ///   - (If you want to specify a different url):  Run`cargo make spec-download [YOUR_URL]`
-/// 2. Run`cargo make generate-all`
+/// 2. Run`cargo make generate-all`, which needs `olg` on the PATH for its last step, `olg post-generate`
 ///
 /// The generated crate includes a `cargo-make` makefile with tasks for maintaining the crate.
 /// Cargo-make default tasks are also supported. For a full list, enter the generated crate and run
-/// `cargo make --list-all-steps`
-///
-/// This CLI will check for the presence of cargo make and try to install it if needed.
+/// `cargo make --list-all-steps`. Pass `--task-runner just` or `--task-runner xtask` to get the
+/// same tasks as a `justfile` or an `xtask` crate instead, then run them with `just <task>` or
+/// `cargo xtask <task>`. The task runner must be installed to generate the code.
 ///  _________       //=//  ___      _----_     ___  \\=\\       _________
 /// /  _____  \     //==\\  \ |_    /  __  \   _| /  //==\\     /  _____  \
 /// |  \___/   \___//=/\=\\__|  |__|  |__|  |_|  |__//=/\=\\___/   \___/   |
 ///  \_/\_/\_/\__/\__/  \_____/\____/\/\/\/\___/\_____/  \__/\__/\_/\__/\_/
 #[derive(Clone, Debug, Deserialize, Parser, Serialize)]
-#[command(author, version, about, verbatim_doc_comment)]
+#[command(
+  name = "olg",
+  author,
+  version,
+  about,
+  verbatim_doc_comment,
+  subcommand_negates_reqs = true
+)]
 pub struct InnerCli {
   /// The site or app name. Will be used to determine generated crate name
   #[arg(long = "name")]
@@ -158,31 +492,101 @@ pub struct InnerCli {
   /// API Spec as a local file. If provided, this overrides the [Self::api_spec_url_opt]
   #[arg(long = "spec-file")]
   pub local_api_spec_filepath_opt: Option<PathBuf>,
-  /// Optional library name to override default generated crate name
+  /// Optional library name to override default generated crate name. Must be a snake case Rust identifier
   #[arg(long = "lib_name")]
   pub lib_name_opt: Option<String>,
+  /// A local crates.io index checkout to fail early in if the library name is taken on crates.io
+  #[arg(long = "crates-index", value_name = "DIR")]
+  pub crates_index_dir_opt: Option<PathBuf>,
   /// Optional `;`—separated extra authors to add to list
   #[arg(long = "authors")]
   pub extra_authors: Option<String>,
   /// The optional output project dir
   #[arg(long = "output")]
-  output_project_dir_opt: Option<PathBuf>,
+  pub(crate) output_project_dir_opt: Option<PathBuf>,
+  /// Generate the crate as a member of the Cargo workspace in this dir. The output dir defaults to
+  /// `<DIR>/<lib_name>`
+  #[arg(long = "workspace", value_name = "DIR")]
+  pub workspace_dir_opt: Option<PathBuf>,
+  /// The task runner the tasks of the crate are written for
+  #[arg(long = "task-runner", value_enum, default_value_t = TaskRunners::CargoMake)]
+  #[serde(default)]
+  pub task_runner: TaskRunners,
+  /// A TOML file of extra `[env]` and `[tasks]` entries to merge into the `Makefile.toml`, like a
+  /// `Makefile.local.toml` in the crate
+  #[arg(long = "extra-tasks", value_name = "FILE")]
+  #[serde(default)]
+  pub extra_tasks_filepath_opt: Option<PathBuf>,
+  /// Describe the tasks of the crate in its README with a Mermaid graph of their dependencies
+  #[arg(long = "readme-task-graph")]
+  #[serde(default)]
+  pub readme_task_graph: bool,
+  /// Keep the staging dir of a failed or interrupted generation for debugging
+  #[arg(long = "keep-failed")]
+  pub keep_failed: bool,
+  /// Back up a non-empty output dir to a timestamped archive next to it, then replace it
+  #[arg(long = "force", conflicts_with = "merge")]
+  pub force: bool,
+  /// Write only the files this generator manages into a non-empty output dir and report conflicts,
+  /// exiting with code 7 if there are any. The code is then left to the `generate-all` task. An empty
+  /// output dir is generated into as usual.
+  #[arg(long = "merge")]
+  pub merge: bool,
+  /// Print the files that would change and the commands that would run, without doing either
+  #[arg(long = "dry-run")]
+  pub dry_run: bool,
+  /// Kill any step's subprocess that runs for longer than this many seconds
+  #[arg(long = "step-timeout", value_name = "SECONDS")]
+  pub step_timeout_secs_opt: Option<u64>,
+  /// How to print progress
+  #[arg(long = "output-format", value_enum, default_value_t = OutputFormats::Text, global = true)]
+  pub output_format: OutputFormats,
+  /// Log more to the terminal (repeat for more detail)
+  #[arg(short = 'v', long = "verbose", action = ArgAction::Count, global = true)]
+  pub verbose: u8,
+  /// Print less to the terminal (repeat to only print errors)
+  #[arg(
+    short = 'q',
+    long = "quiet",
+    action = ArgAction::Count,
+    conflicts_with = "verbose",
+    global = true
+  )]
+  pub quiet: u8,
   #[command(subcommand)]
   pub command: Option<SubCommands>,
 }
 impl InnerCli {
   /// Temp dir
   pub const TEMP_DIR_NAME: &'static str = "temp";
+  /// Get the terminal verbosity, `-v` count minus `-q` count
+  pub fn get_verbosity(&self) -> i8 {
+    get_verbosity(self.verbose, self.quiet)
+  }
+  /// Get how long a step's subprocess may run
+  pub fn get_step_timeout_opt(&self) -> Option<Duration> {
+    self.step_timeout_secs_opt.map(Duration::from_secs)
+  }
+  /// Get what to do with a non-empty output dir
+  pub fn get_output_dir_mode(&self) -> OutputDirModes {
+    if self.force {
+      OutputDirModes::Force
+    } else if self.merge {
+      OutputDirModes::Merge
+    } else {
+      OutputDirModes::RequireEmpty
+    }
+  }
   /// Get  authors strings
   pub fn get_extra_authors(&self) -> Vec<String> {
     Self::parse_authors_string(&self.extra_authors.clone().unwrap_or_default())
   }
-  /// Get a default project library name
+  /// Get a default project library name, normalized from the site or API name
   fn get_default_lib_name(&self) -> String {
     let Self {
       site_or_api_name, ..
     } = self;
-    format!("{site_or_api_name}_openapi_client")
+    parameters::normalize_lib_name(&format!("{site_or_api_name}_openapi_client"))
   }
   /// Get a default project spec file name
   fn get_default_spec_file_name(&self) -> String {
@@ -236,10 +640,15 @@ impl InnerCli {
       .map(|s| s.to_string())
       .collect()
   }
-  /// Get spec file name as specified by [Self::api_spec_url]
+  /// Get spec file name as specified by [Self::local_api_spec_filepath_opt] or [Self::api_spec_url_opt]
+  ///
+  /// This is the name of the spec file in the generated crate.
   pub fn try_get_spec_file_name(&self) -> Result<String, ParameterError> {
     if let Some(local_api_spec_filepath) = self.local_api_spec_filepath_opt.as_ref() {
-      Ok(local_api_spec_filepath.to_string_lossy().to_string())
+      local_api_spec_filepath
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .ok_or_else(|| ParameterError::SpecFileNeedsName(local_api_spec_filepath.clone()))
     } else {
       let api_spec_url = self.api_spec_url_opt.clone().expect("must get spec url");
       parameters::try_file_name_from_path_url(&api_spec_url).map(|mut s| {
@@ -252,6 +661,17 @@ impl InnerCli {
   }
 }
 
+/// What to do with a non-empty output dir
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum OutputDirModes {
+  /// Refuse to generate into it
+  RequireEmpty,
+  /// Archive it, then replace it
+  Force,
+  /// Only write managed files into it
+  Merge,
+}
+
 /// Common Paths
 #[derive(Clone, Copy, Debug, Error, strum::EnumProperty)]
 pub enum Paths {
@@ -264,13 +684,84 @@ pub enum Paths {
   #[error("Cargo Make make file")]
   #[strum(props(path = "Makefile.toml"))]
   CargoMakefile,
+  #[error("CHANGELOG.md file")]
+  #[strum(props(path = "CHANGELOG.md"))]
+  ChangelogMdFile,
   #[error("Cargo.toml file")]
   #[strum(props(path = "Cargo.toml"))]
   CargoTomlFile,
+  #[error("log file of the last generation runs")]
+  #[strum(props(path = "temp/olg.log"))]
+  LogFile,
+  #[error("config of the post-generation updates")]
+  #[strum(props(path = "post_generation.yaml"))]
+  PostGenerationConfigFile,
   #[error("README.md file")]
   #[strum(props(path = "README.md"))]
   ReadmeMdFile,
+  #[error("spec snapshot file used to find changes for the changelog")]
+  #[strum(props(path = "spec_snapshot.yaml"))]
+  SpecSnapshotFile,
   #[error("temp dir")]
   #[strum(props(path = "temp"))]
   TempDir,
 }
+
+#[cfg(test)]
+mod test_cli {
+  use super::*;
+  #[test]
+  fn tool_commands_take_the_global_flags_anywhere() {
+    let parse = |args: &[&str]| CommandLines::try_parse_from(["olg"].iter().chain(args));
+    for args in [
+      &["test-matrix", "-v"][..],
+      &["-v", "test-matrix"],
+      &["--quiet", "test-matrix"],
+      &["--output-format", "json", "batch", "x.toml"],
+      &["--output-format=json", "batch", "x.toml"],
+      &["tasks", "graph", "--format", "mermaid"],
+    ] {
+      assert!(
+        matches!(parse(args), Ok(CommandLines::Tool(_))),
+        "{args:?} must run a tool command"
+      );
+    }
+    let Ok(CommandLines::Tool(tool_cli)) =
+      parse(&["batch", "x.toml", "-vv", "--output-format=json"])
+    else {
+      panic!("must run a tool command");
+    };
+    assert!(matches!(tool_cli.command, ToolCommands::Batch(_)));
+    assert_eq!(tool_cli.get_verbosity(), 2);
+    assert_eq!(tool_cli.output_format, OutputFormats::Json);
+  }
+  #[test]
+  fn generations_still_need_their_settings() {
+    let parse = |args: &[&str]| CommandLines::try_parse_from(["olg"].iter().chain(args));
+    let Ok(CommandLines::Generation(inner_cli)) =
+      parse(&["-v", "--name", "batch", "--api-url", "https://x.example"])
+    else {
+      panic!("must run a generation");
+    };
+    assert_eq!(inner_cli.site_or_api_name, "batch");
+    assert_eq!(inner_cli.get_verbosity(), 1);
+    assert!(parse(&["-v"]).is_err());
+    assert!(parse(&["--name", "x"]).is_err());
+  }
+  #[test]
+  fn help_lists_the_tool_commands() {
+    let help = InnerCli::command().render_long_help().to_string();
+    for name in [
+      "test-generation",
+      "test-matrix",
+      "batch",
+      "init",
+      "post-generate",
+      "regenerate-tasks",
+      "self-update",
+      "tasks",
+    ] {
+      assert!(help.contains(name), "the help must list `{name}`");
+    }
+  }
+}
@@ -0,0 +1,663 @@
+//! Error diagnostics
+//!
+//! Turns a [CLIError] into a [Diagnostic]: what went wrong, in which [GenerationSteps], the command
+//! that failed with the tail of its output, a hint at how to fix it and an exit code for its
+//! [ErrorCategories].
+use crate::{
+  batch::BatchError,
+  builder::GenerationSteps,
+  cli::CLIError,
+  generate::{errors::*, makefiles::MakefileEnv},
+  process::ProcessFailure,
+  self_update::SelfUpdateError,
+  testing::TestingError,
+  wizard::WizardError,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Kinds of errors, each with its own exit code
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCategories {
+  /// Missing or invalid arguments
+  Usage,
+  /// The output dir cannot be used as asked
+  OutputDir,
+  /// A subprocess failed
+  Process,
+  /// Reading or writing files failed
+  IO,
+  /// A spec, manifest or generated config could not be read or written
+  Config,
+  /// The run was interrupted
+  Interrupted,
+}
+impl ErrorCategories {
+  /// Get the process exit code
+  pub fn get_exit_code(&self) -> u8 {
+    match self {
+      // same as clap's argument errors
+      Self::Usage => 2,
+      Self::OutputDir => 3,
+      Self::Process => 4,
+      Self::IO => 5,
+      Self::Config => 6,
+      // same as a shell reports for SIGINT
+      Self::Interrupted => 130,
+    }
+  }
+}
+
+/// The hint for a subprocess that was killed for running longer than `--step-timeout`
+const STEP_TIMEOUT_HINT: &str =
+  "Pass a longer `--step-timeout`, or leave it out to let steps run for as long as they take";
+
+/// Errors that can explain themselves
+pub trait Diagnose {
+  /// Get the kind of error
+  fn get_category(&self) -> ErrorCategories;
+  /// Get a suggested fix
+  fn get_hint(&self) -> Option<String> {
+    None
+  }
+  /// Get the failed subprocess behind this error
+  fn get_process_failure(&self) -> Option<&ProcessFailure> {
+    None
+  }
+}
+
+impl Diagnose for CLIError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::BatchError(e) => e.get_category(),
+      Self::CargoConfigError(e) => e.get_category(),
+      Self::ChangelogGenerationError(e) => e.get_category(),
+      Self::CrateScaffoldingError(e) => e.get_category(),
+      Self::MakefileGenerationError(e) => e.get_category(),
+      Self::ParameterError(e) => e.get_category(),
+      Self::PostGenerationError(e) => e.get_category(),
+      Self::ProcessError(e) => e.get_category(),
+      Self::READMEGenerationError(e) => e.get_category(),
+      Self::SelfUpdateError(e) => e.get_category(),
+      Self::SerdeYAMLError(_) => ErrorCategories::Config,
+      Self::YAMLGenerationError(e) => e.get_category(),
+      Self::TestingError(e) => e.get_category(),
+      Self::WizardError(e) => e.get_category(),
+      Self::StepFailed { source, .. } => source.get_category(),
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::IOError(_) => None,
+      Self::BatchError(e) => e.get_hint(),
+      Self::CargoConfigError(e) => e.get_hint(),
+      Self::ChangelogGenerationError(e) => e.get_hint(),
+      Self::CrateScaffoldingError(e) => e.get_hint(),
+      Self::MakefileGenerationError(e) => e.get_hint(),
+      Self::ParameterError(e) => e.get_hint(),
+      Self::PostGenerationError(e) => e.get_hint(),
+      Self::ProcessError(e) => e.get_hint(),
+      Self::READMEGenerationError(e) => e.get_hint(),
+      Self::SelfUpdateError(e) => e.get_hint(),
+      Self::SerdeYAMLError(_) => None,
+      Self::YAMLGenerationError(e) => e.get_hint(),
+      Self::TestingError(e) => e.get_hint(),
+      Self::WizardError(e) => e.get_hint(),
+      Self::StepFailed { source, .. } => source.get_hint(),
+    }
+  }
+  fn get_process_failure(&self) -> Option<&ProcessFailure> {
+    match self {
+      Self::CrateScaffoldingError(e) => e.get_process_failure(),
+      Self::ProcessError(e) => e.get_process_failure(),
+      Self::TestingError(e) => e.get_process_failure(),
+      Self::StepFailed { source, .. } => source.get_process_failure(),
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for BatchError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::TomlDeError(_) => ErrorCategories::Config,
+      Self::NoEntries(_) | Self::SharedOutputDir { .. } => ErrorCategories::Usage,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::TomlDeError(_) => Some(
+        "Check the manifest against `olg batch --help`: every API is an `[[apis]]` table"
+          .to_string(),
+      ),
+      Self::NoEntries(_) => Some(
+        "Add an `[[apis]]` table with `name`, `api-url` and `spec-url` or `spec-file`".to_string(),
+      ),
+      Self::SharedOutputDir { .. } => {
+        Some("Give one of them another `output` or `lib-name`".to_string())
+      }
+      Self::IOError(_) => None,
+    }
+  }
+}
+
+impl Diagnose for CargoConfigError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::WorkspaceError(e) => e.get_category(),
+      _ => ErrorCategories::Config,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::UpdateRustEditionError(_) => Some(
+        "Set `edition` in the generated `Cargo.toml` to \"2021\" or older, then rerun".to_string(),
+      ),
+      Self::CargoTomlError(_) => Some("Check that the generated `Cargo.toml` is valid".to_string()),
+      Self::WorkspaceError(e) => e.get_hint(),
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for ChangelogGenerationError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::ParameterError(e) => e.get_category(),
+      Self::SpecError(e) => e.get_category(),
+      _ => ErrorCategories::Config,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::ParameterError(e) => e.get_hint(),
+      Self::SpecError(e) => e.get_hint(),
+      Self::SerdeYAMLError(_) => Some(
+        "Delete the spec snapshot file in the crate to start the changelog diff over".to_string(),
+      ),
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for CrateScaffoldingError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::ProcessError(e) => e.get_category(),
+      Self::YAMLGenerationError(e) => e.get_category(),
+      Self::NonEmptyTargetDir(_) | Self::RefuseToDeleteOutsideTemp(_) => ErrorCategories::OutputDir,
+      Self::MissingCrateDir(_) => ErrorCategories::OutputDir,
+      Self::CargoInitFailed { .. } => ErrorCategories::Process,
+      Self::Interrupted => ErrorCategories::Interrupted,
+      Self::ParameterError(e) => e.get_category(),
+      Self::WorkspaceError(e) => e.get_category(),
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::ProcessError(e) => e.get_hint(),
+      Self::YAMLGenerationError(e) => e.get_hint(),
+      Self::NonEmptyTargetDir(_) => Some(
+        "Pass `--force` to archive and replace the dir, `--merge` to only update the files this \
+         generator manages, or choose another `--output` dir"
+          .to_string(),
+      ),
+      Self::RefuseToDeleteOutsideTemp(_) => Some(
+        "Test generations only delete dirs inside the system temp dir. Leave out `--output` or \
+         point it into the temp dir"
+          .to_string(),
+      ),
+      Self::MissingCrateDir(_) => {
+        Some("Check that the parent of the `--output` dir exists and is writable".to_string())
+      }
+      Self::CargoInitFailed { failure, .. } if failure.timeout_opt.is_some() => {
+        Some(STEP_TIMEOUT_HINT.to_string())
+      }
+      Self::CargoInitFailed { .. } => Some(
+        "Check that `cargo` is on the PATH and that the library name is a valid package name \
+         (override it with `--lib_name`)"
+          .to_string(),
+      ),
+      Self::Interrupted => {
+        Some("Pass `--keep-failed` to keep the partly generated crate for inspection".to_string())
+      }
+      Self::ParameterError(e) => e.get_hint(),
+      Self::WorkspaceError(e) => e.get_hint(),
+      Self::IOError(_) => None,
+    }
+  }
+  fn get_process_failure(&self) -> Option<&ProcessFailure> {
+    match self {
+      Self::ProcessError(e) => e.get_process_failure(),
+      Self::CargoInitFailed { failure, .. } => Some(failure),
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for MakefileGenerationError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::CargoConfigError(e) => e.get_category(),
+      Self::ChangelogGenerationError(e) => e.get_category(),
+      Self::ParameterError(e) => e.get_category(),
+      Self::READMEGenerationError(e) => e.get_category(),
+      Self::EnvNameConflict { .. }
+      | Self::TaskNameConflict { .. }
+      | Self::InvalidExtension { .. }
+      | Self::MissingExtension(_) => ErrorCategories::Usage,
+      Self::EnvMissingKey(_)
+      | Self::MakefileMigrationError(_)
+      | Self::MakefileValidationError(_)
+      | Self::SerdeYAMLError(_)
+      | Self::TomlDeError(_)
+      | Self::TomlSerError(_) => ErrorCategories::Config,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::MakefileMigrationError(MakefileMigrationError::NewerSchemaVersion { .. }) => Some(
+        "Update the generator, e.g. with `olg self-update`, to regenerate the tasks of this crate"
+          .to_string(),
+      ),
+      Self::EnvNameConflict { name, .. } | Self::TaskNameConflict { name, .. } => Some(format!(
+        "Rename `{name}`, the generator writes an entry of that name itself"
+      )),
+      Self::MakefileValidationError(_) => Some(format!(
+        "Fix the entries of your `{}` or `--extra-tasks` file if they are to blame, otherwise \
+         report the problems as a bug of the generator",
+        MakefileEnv::MAKEFILE_EXTENSION_NAME
+      )),
+      Self::MissingExtension(_) => {
+        Some("Pass an `--extra-tasks` path to a TOML file with `[env]` or `[tasks]`".to_string())
+      }
+      Self::TomlDeError(_) => Some(format!(
+        "Fix or remove the `{}` of the crate, it is read to keep the tasks added to it",
+        MakefileEnv::MAKEFILE_NAME
+      )),
+      Self::CargoConfigError(e) => e.get_hint(),
+      Self::ChangelogGenerationError(e) => e.get_hint(),
+      Self::ParameterError(e) => e.get_hint(),
+      Self::READMEGenerationError(e) => e.get_hint(),
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for ParameterError {
+  fn get_category(&self) -> ErrorCategories {
+    ErrorCategories::Usage
+  }
+  fn get_hint(&self) -> Option<String> {
+    Some(
+      match self {
+        Self::APIPathNeedsSegments(_) | Self::APIPathSegmentsNeedsLast => {
+          "Pass a `--spec-url` whose path ends in the spec file name, like \
+           `https://example.com/openapi.yaml`"
+        }
+        Self::APIUrlNeededIfNoLocalFile => {
+          "Pass `--spec-url <URL>` to download the spec or `--spec-file <PATH>` to use a local one"
+        }
+        Self::TestingYAMLSpecPathMissing => {
+          "Pass `--spec-file` or `--output` to the test generation"
+        }
+        Self::SpecFileNeedsName(_) => "Pass a `--spec-file` path that ends in a file name",
+        Self::InvalidLibName { suggestion, .. } => {
+          return Some(format!(
+            "Pass `--lib_name {suggestion}`, or leave it out to derive one from `--name`"
+          ));
+        }
+        Self::LibNameTaken { .. } => {
+          "Pass another `--lib_name`, or leave out `--crates-index` if the crate will not be published"
+        }
+        Self::NotACratesIndex(_) => {
+          "Pass the root of a crates.io index checkout to `--crates-index`, the dir with `config.json`"
+        }
+      }
+      .to_string(),
+    )
+  }
+}
+
+impl Diagnose for PostGenerationError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::SerdeYAMLError(_) => ErrorCategories::Config,
+      Self::CargoConfigError(e) => e.get_category(),
+      Self::ChangelogGenerationError(e) => e.get_category(),
+      Self::READMEGenerationError(e) => e.get_category(),
+      Self::ParameterError(e) => e.get_category(),
+      Self::MissingConfig(_) => ErrorCategories::Usage,
+      Self::MissingSettings(_) => ErrorCategories::Config,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::CargoConfigError(e) => e.get_hint(),
+      Self::ChangelogGenerationError(e) => e.get_hint(),
+      Self::READMEGenerationError(e) => e.get_hint(),
+      Self::ParameterError(e) => e.get_hint(),
+      Self::MissingConfig(_) => Some(
+        "Run it in the dir of a crate generated by this version of olg, or generate the crate again"
+          .to_string(),
+      ),
+      Self::MissingSettings(_) => {
+        Some("Generate the crate again with this version of olg".to_string())
+      }
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for ProcessError {
+  fn get_category(&self) -> ErrorCategories {
+    ErrorCategories::Process
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::IOError(_) => None,
+      Self::CouldNotStart { program, .. } => Some(format!(
+        "Check that `{program}` is installed and on the PATH"
+      )),
+      Self::Failure(failure) if failure.timeout_opt.is_some() => {
+        Some(STEP_TIMEOUT_HINT.to_string())
+      }
+      Self::Failure(failure) => Some(format!(
+        "Pass `--keep-failed` to keep the crate, then run `{}` in it to reproduce the failure",
+        failure.get_command_line()
+      )),
+    }
+  }
+  fn get_process_failure(&self) -> Option<&ProcessFailure> {
+    match self {
+      Self::Failure(failure) => Some(failure),
+      Self::IOError(_) | Self::CouldNotStart { .. } => None,
+    }
+  }
+}
+
+impl Diagnose for READMEGenerationError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::ParameterError(e) => e.get_category(),
+      Self::FromUtf8Error(_) | Self::UnterminatedSection(_) => ErrorCategories::Config,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::ParameterError(e) => e.get_hint(),
+      Self::UnterminatedSection(section) => Some(format!(
+        "Restore the end marker of the `{}` section in README.md or remove its start marker",
+        section.as_ref()
+      )),
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for SelfUpdateError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::SerdeJSONError(_) | Self::TomlError(_) => ErrorCategories::Config,
+      Self::NoGeneratorDependency { .. } | Self::NotInIndex { .. } => ErrorCategories::Usage,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::SerdeJSONError(_) | Self::NotInIndex { .. } => {
+        Some("Check that `--index` points at a crates.io index".to_string())
+      }
+      Self::NoGeneratorDependency { .. } => {
+        Some("Run it in the dir of a crate generated by olg".to_string())
+      }
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for SpecError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::SerdeYAMLError(_) => ErrorCategories::Config,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::SerdeYAMLError(_) => {
+        Some("Check that the spec is a valid OpenAPI or Swagger document".to_string())
+      }
+      Self::IOError(_) => None,
+    }
+  }
+}
+
+impl Diagnose for TestingError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::ProcessError(e) => e.get_category(),
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::ProcessError(e) => e.get_hint(),
+      Self::IOError(_) => None,
+    }
+  }
+  fn get_process_failure(&self) -> Option<&ProcessFailure> {
+    match self {
+      Self::ProcessError(e) => e.get_process_failure(),
+      Self::IOError(_) => None,
+    }
+  }
+}
+
+impl Diagnose for WizardError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::TomlDeError(_) | Self::TomlSerError(_) => ErrorCategories::Config,
+      Self::DuplicateName { .. } => ErrorCategories::Usage,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::TomlDeError(_) => Some(
+        "Fix the config against `olg batch --help`, or pick another one with `--config`"
+          .to_string(),
+      ),
+      Self::DuplicateName { .. } => {
+        Some("Give the API another name, or pick another config with `--config`".to_string())
+      }
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for YAMLGenerationError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::SerdeYAMLError(_) => ErrorCategories::Config,
+      Self::ParameterError(e) => e.get_category(),
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::ParameterError(e) => e.get_hint(),
+      _ => None,
+    }
+  }
+}
+
+impl Diagnose for WorkspaceError {
+  fn get_category(&self) -> ErrorCategories {
+    match self {
+      Self::IOError(_) => ErrorCategories::IO,
+      Self::TomlError(_) | Self::InvalidMakefile(_) => ErrorCategories::Config,
+      Self::NotAWorkspace(_) => ErrorCategories::Usage,
+      Self::OutsideWorkspace { .. } => ErrorCategories::OutputDir,
+    }
+  }
+  fn get_hint(&self) -> Option<String> {
+    match self {
+      Self::TomlError(_) => Some(
+        "Check that the `Cargo.toml` and `Makefile.toml` of the workspace are valid".to_string(),
+      ),
+      Self::NotAWorkspace(_) => Some(
+        "Pass the dir of a `Cargo.toml` with a `[workspace]` table to `--workspace`".to_string(),
+      ),
+      Self::OutsideWorkspace { .. } => Some(
+        "Leave out `--output` to generate into the workspace, or point it into the workspace dir"
+          .to_string(),
+      ),
+      Self::InvalidMakefile(_) => {
+        Some("Declare the tasks of the workspace makefile as `[tasks.<name>]` tables".to_string())
+      }
+      Self::IOError(_) => None,
+    }
+  }
+}
+
+/// A readable report of an error
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Diagnostic {
+  /// What went wrong
+  pub message: String,
+  /// The kind of error
+  pub category: ErrorCategories,
+  /// The exit code for the kind of error
+  pub exit_code: u8,
+  /// The generation step that failed
+  pub failed_step_opt: Option<GenerationSteps>,
+  /// The subprocess that failed
+  pub process_failure_opt: Option<ProcessFailure>,
+  /// A suggested fix
+  pub hint_opt: Option<String>,
+}
+impl From<&CLIError> for Diagnostic {
+  fn from(error: &CLIError) -> Self {
+    let (failed_step_opt, inner_error) = match error {
+      CLIError::StepFailed { step, source } => (Some(*step), source.as_ref()),
+      e => (None, e),
+    };
+    let category = error.get_category();
+    Self {
+      message: inner_error.to_string(),
+      category,
+      exit_code: category.get_exit_code(),
+      failed_step_opt,
+      process_failure_opt: error.get_process_failure().cloned(),
+      hint_opt: error.get_hint(),
+    }
+  }
+}
+impl fmt::Display for Diagnostic {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    writeln!(f, "error: {}", self.message)?;
+    if let Some(step) = self.failed_step_opt {
+      writeln!(f, "  step: {}", step.as_ref())?;
+    }
+    if let Some(failure) = self.process_failure_opt.as_ref() {
+      writeln!(f, "  command: {}", failure.get_command_line())?;
+      if let Some(cwd) = failure.cwd.as_ref() {
+        writeln!(f, "  in: {}", cwd.to_string_lossy())?;
+      }
+      if !failure.output_tail.is_empty() {
+        writeln!(f, "  output (last {} lines):", failure.output_tail.len())?;
+        for line in failure.output_tail.iter() {
+          writeln!(f, "    | {line}")?;
+        }
+      }
+    }
+    if let Some(hint) = self.hint_opt.as_ref() {
+      writeln!(f, "  hint: {hint}")?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test_diagnostics {
+  use super::*;
+  use crate::{
+    generate::{makefiles::TaskNames, task_runners::TaskRunners},
+    process::{self, ProcessError, ProcessSpec},
+  };
+  use std::path::PathBuf;
+  #[test]
+  fn diagnostic_names_step_category_and_hint() {
+    let error = CLIError::from(CrateScaffoldingError::NonEmptyTargetDir(PathBuf::from(
+      "out",
+    )))
+    .in_step(GenerationSteps::ScaffoldCrate);
+    let diagnostic = Diagnostic::from(&error);
+    assert_eq!(diagnostic.category, ErrorCategories::OutputDir);
+    assert_eq!(diagnostic.exit_code, 3);
+    assert_eq!(
+      diagnostic.failed_step_opt,
+      Some(GenerationSteps::ScaffoldCrate)
+    );
+    assert!(diagnostic
+      .hint_opt
+      .as_ref()
+      .is_some_and(|hint| hint.contains("--force")));
+    let rendered = diagnostic.to_string();
+    assert!(rendered.starts_with("error: Cannot scaffold"));
+    assert!(rendered.contains("  step: scaffold-crate\n"));
+  }
+  #[tokio::test]
+  async fn hint_names_the_program_that_could_not_start() {
+    let spec = ProcessSpec::new("olg-test-missing-program", &["--version"]);
+    let error = CLIError::from(
+      process::run(&spec, "Running a missing program.")
+        .await
+        .expect_err("must not start"),
+    );
+    let diagnostic = Diagnostic::from(&error);
+    assert_eq!(diagnostic.category, ErrorCategories::Process);
+    assert_eq!(
+      diagnostic.hint_opt.as_deref(),
+      Some("Check that `olg-test-missing-program` is installed and on the PATH")
+    );
+  }
+  #[test]
+  fn hint_reruns_a_failed_task_with_the_task_runner_of_the_crate() {
+    for (task_runner, command_line) in [
+      (TaskRunners::CargoMake, "cargo make generate-all"),
+      (TaskRunners::Just, "just generate-all"),
+      (TaskRunners::Xtask, "cargo xtask generate-all"),
+    ] {
+      let spec = task_runner.get_task_spec(TaskNames::GenerateAll.as_ref());
+      let failure = ProcessFailure {
+        program: spec.program.clone(),
+        args: spec.args.clone(),
+        cwd: None,
+        exit_code: Some(1),
+        timeout_opt: None,
+        output_tail: vec![],
+      };
+      let error = CLIError::from(ProcessError::from(Box::new(failure)));
+      let hint = Diagnostic::from(&error).hint_opt.expect("must hint");
+      assert!(
+        hint.contains(&format!("run `{command_line}` in it")),
+        "{hint}"
+      );
+    }
+  }
+}
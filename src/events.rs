@@ -0,0 +1,289 @@
+//! Generation progress events
+//!
+//! Generation steps report what they do as [GenerationEvent]s instead of printing. Events go to the
+//! [GenerationObserver] in scope (see [with_observer]), or are printed as text if there is none.
+//! Every event is also logged (see [crate::logging]).
+use crate::{builder::GenerationSteps, logging::EVENTS_TARGET};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, path::PathBuf, sync::Arc};
+
+/// Something that happened during generation
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum GenerationEvent {
+  /// A file was written
+  FileWritten {
+    path: PathBuf,
+    bytes: usize,
+    description: Option<String>,
+  },
+  /// A process was started
+  ProcessStarted {
+    program: String,
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+    description: String,
+  },
+  /// A process printed a line
+  ProcessOutput {
+    /// What the process is known as (see [ProcessSpec::get_label](crate::process::ProcessSpec::get_label))
+    label: String,
+    stream: OutputStreams,
+    line: String,
+  },
+  /// A process finished
+  ProcessFinished {
+    program: String,
+    args: Vec<String>,
+    success: bool,
+    exit_code: Option<i32>,
+    /// Whether the process was killed for running longer than its timeout
+    timed_out: bool,
+  },
+  /// A generation step completed
+  StepCompleted { step: GenerationSteps },
+  /// Something worth knowing
+  Message { message: String },
+  /// Something that did not stop generation but may need attention
+  Warning { message: String },
+}
+
+/// Output streams of a process
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputStreams {
+  Stdout,
+  Stderr,
+}
+
+impl GenerationEvent {
+  /// Log this event
+  fn log(&self) {
+    match self {
+      Self::FileWritten { path, bytes, .. } => {
+        tracing::info!(target: EVENTS_TARGET, ?path, bytes, "wrote file")
+      }
+      Self::ProcessStarted {
+        program, args, cwd, ..
+      } => tracing::info!(target: EVENTS_TARGET, program, ?args, ?cwd, "started process"),
+      Self::ProcessOutput {
+        label,
+        stream,
+        line,
+      } => tracing::debug!(target: EVENTS_TARGET, label, stream = stream.as_ref(), "{line}"),
+      Self::ProcessFinished {
+        program,
+        args,
+        success: true,
+        ..
+      } => tracing::info!(target: EVENTS_TARGET, program, ?args, "process succeeded"),
+      Self::ProcessFinished {
+        program,
+        args,
+        exit_code,
+        timed_out,
+        ..
+      } => {
+        tracing::error!(target: EVENTS_TARGET, program, ?args, ?exit_code, timed_out, "process failed")
+      }
+      Self::StepCompleted { step } => {
+        tracing::info!(target: EVENTS_TARGET, step = step.as_ref(), "completed step")
+      }
+      Self::Message { message } => tracing::info!(target: EVENTS_TARGET, "{message}"),
+      Self::Warning { message } => tracing::warn!(target: EVENTS_TARGET, "{message}"),
+    }
+  }
+}
+
+/// Receives [GenerationEvent]s
+pub trait GenerationObserver: Send + Sync {
+  /// Handle an event
+  fn on_event(
+    &self,
+    event: &GenerationEvent,
+  );
+}
+
+/// Prints events as human readable text
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextObserver {
+  /// Only print failures and warnings
+  pub quiet: bool,
+}
+impl GenerationObserver for TextObserver {
+  fn on_event(
+    &self,
+    event: &GenerationEvent,
+  ) {
+    match event {
+      GenerationEvent::ProcessFinished { success: false, .. } | GenerationEvent::Warning { .. } => {
+      }
+      _ if self.quiet => return,
+      _ => {}
+    }
+    match event {
+      GenerationEvent::FileWritten {
+        path,
+        bytes,
+        description,
+      } => {
+        let wrote_message = format!(" Wrote {bytes} bytes to {:?}", path.as_os_str());
+        match description {
+          Some(s) => println!("{s}: {wrote_message}"),
+          None => println!("{wrote_message}"),
+        }
+      }
+      GenerationEvent::ProcessStarted { description, .. } => println!("{description}"),
+      GenerationEvent::ProcessOutput {
+        label,
+        stream,
+        line,
+      } => match stream {
+        OutputStreams::Stdout => println!("[{label}] {line}"),
+        OutputStreams::Stderr => eprintln!("[{label}] {line}"),
+      },
+      GenerationEvent::ProcessFinished {
+        program,
+        args,
+        success: false,
+        timed_out: true,
+        ..
+      } => eprintln!("`{program} {}` timed out", args.join(" ")),
+      GenerationEvent::ProcessFinished {
+        program,
+        args,
+        success: false,
+        exit_code,
+        ..
+      } => {
+        let exit_code = exit_code.map_or_else(|| "a signal".to_string(), |c| c.to_string());
+        eprintln!("`{program} {}` failed with {exit_code}", args.join(" "));
+      }
+      GenerationEvent::ProcessFinished { .. } | GenerationEvent::StepCompleted { .. } => {}
+      GenerationEvent::Message { message } => println!("{message}"),
+      GenerationEvent::Warning { message } => eprintln!("Warning: {message}"),
+    }
+  }
+}
+
+/// Prints events as newline-delimited JSON
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonObserver;
+impl GenerationObserver for JsonObserver {
+  fn on_event(
+    &self,
+    event: &GenerationEvent,
+  ) {
+    if let Ok(line) = serde_json::to_string(event) {
+      println!("{line}");
+    }
+  }
+}
+
+/// Drops all events
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SilentObserver;
+impl GenerationObserver for SilentObserver {
+  fn on_event(
+    &self,
+    _event: &GenerationEvent,
+  ) {
+  }
+}
+
+/// How the CLI renders events
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormats {
+  /// Human readable text
+  #[default]
+  Text,
+  /// Newline-delimited JSON
+  Json,
+}
+impl OutputFormats {
+  /// Get the observer that renders this format at a verbosity (see [crate::cli::InnerCli::get_verbosity])
+  pub fn make_observer(
+    &self,
+    verbosity: i8,
+  ) -> Arc<dyn GenerationObserver> {
+    match self {
+      Self::Text => Arc::new(TextObserver {
+        quiet: verbosity < 0,
+      }),
+      Self::Json => Arc::new(JsonObserver),
+    }
+  }
+}
+
+tokio::task_local! {
+  static OBSERVER: Arc<dyn GenerationObserver>;
+}
+
+/// Run a future with events going to the given observer
+pub async fn with_observer<F: Future>(
+  observer: Arc<dyn GenerationObserver>,
+  f: F,
+) -> F::Output {
+  OBSERVER.scope(observer, f).await
+}
+
+/// Send an event to the observer in scope
+pub fn emit(event: GenerationEvent) {
+  event.log();
+  if OBSERVER
+    .try_with(|observer| observer.on_event(&event))
+    .is_err()
+  {
+    TextObserver::default().on_event(&event);
+  }
+}
+
+/// Send a [GenerationEvent::Message]
+pub fn message(message: impl Into<String>) {
+  emit(GenerationEvent::Message {
+    message: message.into(),
+  })
+}
+
+/// Send a [GenerationEvent::Warning]
+pub fn warning(message: impl Into<String>) {
+  emit(GenerationEvent::Warning {
+    message: message.into(),
+  })
+}
+
+#[cfg(test)]
+mod test_events {
+  use super::*;
+  use std::sync::Mutex;
+  #[derive(Default)]
+  struct CollectingObserver(Mutex<Vec<GenerationEvent>>);
+  impl GenerationObserver for CollectingObserver {
+    fn on_event(
+      &self,
+      event: &GenerationEvent,
+    ) {
+      self.0.lock().expect("must lock").push(event.clone());
+    }
+  }
+  #[tokio::test]
+  async fn events_go_to_observer_in_scope() {
+    let observer = Arc::new(CollectingObserver::default());
+    with_observer(observer.clone(), async {
+      message("hello");
+      warning("careful");
+    })
+    .await;
+    let events = observer.0.lock().expect("must lock");
+    assert!(matches!(
+      events.as_slice(),
+      [
+        GenerationEvent::Message { message },
+        GenerationEvent::Warning { .. },
+      ] if message == "hello"
+    ));
+  }
+}
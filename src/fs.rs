@@ -1,20 +1,743 @@
 //! Filesystem operations
+//!
+//! Generation goes through the [FileSystem] in scope (see [with_file_system]), or the disk if there
+//! is none. A [MemoryFileSystem] keeps every change in memory, for dry runs and tests.
+use crate::events::{self, GenerationEvent};
+use async_trait::async_trait;
+use flate2::{write::GzEncoder, Compression};
 use fs_err::tokio as fs;
-use std::{io::Error as IOError, path::Path};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt,
+  future::Future,
+  io::{Error as IOError, ErrorKind},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
 
-/// Write to file with logging
+/// Where generation reads and writes files
+#[async_trait]
+pub trait FileSystem: Send + Sync {
+  /// Read a file
+  async fn read(
+    &self,
+    path: &Path,
+  ) -> Result<Vec<u8>, IOError>;
+  /// Write a file, replacing it if it exists
+  async fn write(
+    &self,
+    path: &Path,
+    contents: &[u8],
+  ) -> Result<(), IOError>;
+  /// Create a dir and its missing parents
+  async fn create_dir_all(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError>;
+  /// Remove a file
+  async fn remove_file(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError>;
+  /// Remove an empty dir
+  async fn remove_dir(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError>;
+  /// Remove a dir and everything in it
+  async fn remove_dir_all(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError>;
+  /// Move a file or dir
+  async fn rename(
+    &self,
+    from: &Path,
+    to: &Path,
+  ) -> Result<(), IOError>;
+  /// Copy a file along with its permissions, replacing the copy if it exists
+  async fn copy(
+    &self,
+    from: &Path,
+    to: &Path,
+  ) -> Result<(), IOError>;
+  /// List the paths in a dir, sorted
+  async fn read_dir(
+    &self,
+    path: &Path,
+  ) -> Result<Vec<PathBuf>, IOError>;
+  /// Whether a dir exists
+  async fn is_dir(
+    &self,
+    path: &Path,
+  ) -> bool;
+  /// Whether a file exists
+  async fn is_file(
+    &self,
+    path: &Path,
+  ) -> bool;
+  /// Archive a dir into a `.tar.gz` whose entries start with the dir name, returning its size
+  async fn archive_dir(
+    &self,
+    dir_path: &Path,
+    archive_path: &Path,
+  ) -> Result<u64, IOError>;
+}
+
+/// The real filesystem
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiskFileSystem;
+#[async_trait]
+impl FileSystem for DiskFileSystem {
+  async fn read(
+    &self,
+    path: &Path,
+  ) -> Result<Vec<u8>, IOError> {
+    fs::read(path).await
+  }
+  async fn write(
+    &self,
+    path: &Path,
+    contents: &[u8],
+  ) -> Result<(), IOError> {
+    fs::write(path, contents).await
+  }
+  async fn create_dir_all(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    fs::create_dir_all(path).await
+  }
+  async fn remove_file(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    fs::remove_file(path).await
+  }
+  async fn remove_dir(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    fs::remove_dir(path).await
+  }
+  async fn remove_dir_all(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    fs::remove_dir_all(path).await
+  }
+  async fn rename(
+    &self,
+    from: &Path,
+    to: &Path,
+  ) -> Result<(), IOError> {
+    fs::rename(from, to).await
+  }
+  async fn copy(
+    &self,
+    from: &Path,
+    to: &Path,
+  ) -> Result<(), IOError> {
+    fs::copy(from, to).await.map(|_| ())
+  }
+  async fn read_dir(
+    &self,
+    path: &Path,
+  ) -> Result<Vec<PathBuf>, IOError> {
+    let mut entries = fs::read_dir(path).await?;
+    let mut paths = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+      paths.push(entry.path());
+    }
+    paths.sort();
+    Ok(paths)
+  }
+  async fn is_dir(
+    &self,
+    path: &Path,
+  ) -> bool {
+    fs::metadata(path).await.is_ok_and(|m| m.is_dir())
+  }
+  async fn is_file(
+    &self,
+    path: &Path,
+  ) -> bool {
+    fs::metadata(path).await.is_ok_and(|m| m.is_file())
+  }
+  /// Streams the dir into the archive, keeping the modes, mtimes, empty dirs and symlinks
+  async fn archive_dir(
+    &self,
+    dir_path: &Path,
+    archive_path: &Path,
+  ) -> Result<u64, IOError> {
+    let (dir_path, archive_path) = (dir_path.to_path_buf(), archive_path.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+      let archive_file = fs_err::File::create(&archive_path)?;
+      let mut builder = tar::Builder::new(GzEncoder::new(archive_file, Compression::default()));
+      builder.follow_symlinks(false);
+      builder.append_dir_all(get_dir_name(&dir_path), &dir_path)?;
+      let archive_file = builder.into_inner()?.finish()?;
+      Ok(archive_file.metadata()?.len())
+    })
+    .await
+    .map_err(IOError::other)?
+  }
+}
+
+/// Files and dirs held by a [MemoryFileSystem]
+#[derive(Debug, Default)]
+struct MemoryState {
+  files: BTreeMap<PathBuf, Vec<u8>>,
+  dirs: BTreeSet<PathBuf>,
+  /// Paths on disk that were removed or moved away
+  removed: BTreeSet<PathBuf>,
+}
+impl MemoryState {
+  /// Whether a path exists in memory as a dir
+  fn has_dir(
+    &self,
+    path: &Path,
+  ) -> bool {
+    self.dirs.iter().any(|dir| dir.starts_with(path))
+      || self
+        .files
+        .keys()
+        .any(|file| file != path && file.starts_with(path))
+  }
+}
+
+/// A filesystem that keeps all changes in memory
+///
+/// It can overlay the disk: reads fall through to the disk for anything not changed in memory, and
+/// nothing is ever written to it. [Self::get_changes] then tells what would change on disk.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+  overlay_disk: bool,
+  state: Mutex<MemoryState>,
+}
+impl MemoryFileSystem {
+  /// An empty filesystem
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// A filesystem that starts out like the disk
+  pub fn overlaying_disk() -> Self {
+    Self {
+      overlay_disk: true,
+      ..Self::default()
+    }
+  }
+  /// Lock the state
+  fn lock(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+    self.state.lock().expect("must lock memory filesystem")
+  }
+  /// Whether a path may be read from the disk
+  fn is_on_disk(
+    &self,
+    state: &MemoryState,
+    path: &Path,
+  ) -> bool {
+    self.overlay_disk
+      && !state
+        .removed
+        .iter()
+        .any(|removed| path.starts_with(removed))
+  }
+  /// Get the files written to memory
+  pub fn get_files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+    self.lock().files.clone()
+  }
+  /// Compare the files in memory to the disk
+  pub async fn get_changes(&self) -> Result<Vec<FileChange>, IOError> {
+    let (files, removed) = {
+      let state = self.lock();
+      (state.files.clone(), state.removed.clone())
+    };
+    let mut changes = BTreeMap::new();
+    for removed_path in removed.iter() {
+      let disk_files = match DiskFileSystem.is_dir(removed_path).await {
+        true => walk_files_in(&DiskFileSystem, removed_path).await?,
+        false => vec![removed_path.clone()],
+      };
+      for path in disk_files.into_iter().filter(|p| !files.contains_key(p)) {
+        if let Ok(before) = DiskFileSystem.read(&path).await {
+          changes.insert(path.clone(), FileChange::new(path, Some(&before), None));
+        }
+      }
+    }
+    for (path, after) in files.iter() {
+      let before_opt = match self.overlay_disk && !removed.iter().any(|r| path.starts_with(r)) {
+        true => DiskFileSystem.read(path).await.ok(),
+        false => None,
+      };
+      if before_opt.as_ref() != Some(after) {
+        let change = FileChange::new(path.clone(), before_opt.as_deref(), Some(after));
+        changes.insert(path.clone(), change);
+      }
+    }
+    Ok(changes.into_values().collect())
+  }
+}
+#[async_trait]
+impl FileSystem for MemoryFileSystem {
+  async fn read(
+    &self,
+    path: &Path,
+  ) -> Result<Vec<u8>, IOError> {
+    {
+      let state = self.lock();
+      if let Some(contents) = state.files.get(path) {
+        return Ok(contents.clone());
+      }
+      if !self.is_on_disk(&state, path) {
+        return Err(not_found(path));
+      }
+    }
+    DiskFileSystem.read(path).await
+  }
+  async fn write(
+    &self,
+    path: &Path,
+    contents: &[u8],
+  ) -> Result<(), IOError> {
+    self
+      .lock()
+      .files
+      .insert(path.to_path_buf(), contents.to_vec());
+    Ok(())
+  }
+  async fn create_dir_all(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    self.lock().dirs.insert(path.to_path_buf());
+    Ok(())
+  }
+  async fn remove_file(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    if !self.is_file(path).await {
+      return Err(not_found(path));
+    }
+    let mut state = self.lock();
+    state.files.remove(path);
+    if self.overlay_disk {
+      state.removed.insert(path.to_path_buf());
+    }
+    Ok(())
+  }
+  async fn remove_dir(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    if !self.read_dir(path).await?.is_empty() {
+      return Err(IOError::other(format!(
+        "Directory not empty: {}",
+        path.to_string_lossy()
+      )));
+    }
+    self.remove_dir_all(path).await
+  }
+  async fn remove_dir_all(
+    &self,
+    path: &Path,
+  ) -> Result<(), IOError> {
+    if !self.is_dir(path).await {
+      return Err(not_found(path));
+    }
+    let mut state = self.lock();
+    state.files.retain(|file, _| !file.starts_with(path));
+    state.dirs.retain(|dir| !dir.starts_with(path));
+    if self.overlay_disk {
+      state.removed.insert(path.to_path_buf());
+    }
+    Ok(())
+  }
+  async fn rename(
+    &self,
+    from: &Path,
+    to: &Path,
+  ) -> Result<(), IOError> {
+    if self.is_file(from).await {
+      let contents = self.read(from).await?;
+      self.write(to, &contents).await?;
+      let mut state = self.lock();
+      state.files.remove(from);
+      if self.overlay_disk {
+        state.removed.insert(from.to_path_buf());
+      }
+      return Ok(());
+    }
+    if !self.is_dir(from).await {
+      return Err(not_found(from));
+    }
+    for file in walk_files_in(self, from).await? {
+      let contents = self.read(&file).await?;
+      let relative = file.strip_prefix(from).expect("must be in dir");
+      self.write(&to.join(relative), &contents).await?;
+    }
+    let moved_dirs = self
+      .lock()
+      .dirs
+      .iter()
+      .filter_map(|dir| dir.strip_prefix(from).ok().map(|d| to.join(d)))
+      .collect::<Vec<_>>();
+    self.lock().dirs.extend(moved_dirs);
+    self.lock().dirs.insert(to.to_path_buf());
+    self.remove_dir_all(from).await
+  }
+  async fn copy(
+    &self,
+    from: &Path,
+    to: &Path,
+  ) -> Result<(), IOError> {
+    let contents = self.read(from).await?;
+    self.write(to, &contents).await
+  }
+  async fn read_dir(
+    &self,
+    path: &Path,
+  ) -> Result<Vec<PathBuf>, IOError> {
+    if !self.is_dir(path).await {
+      return Err(not_found(path));
+    }
+    let (mut paths, is_on_disk) = {
+      let state = self.lock();
+      let child_of = |p: &PathBuf| {
+        p.strip_prefix(path)
+          .ok()
+          .and_then(|rest| rest.components().next())
+          .map(|first| path.join(first))
+      };
+      let paths = state
+        .files
+        .keys()
+        .chain(state.dirs.iter())
+        .filter(|p| p.as_path() != path)
+        .filter_map(child_of)
+        .collect::<BTreeSet<_>>();
+      (paths, self.is_on_disk(&state, path))
+    };
+    if is_on_disk && DiskFileSystem.is_dir(path).await {
+      let state_removed = self.lock().removed.clone();
+      paths.extend(
+        DiskFileSystem
+          .read_dir(path)
+          .await?
+          .into_iter()
+          .filter(|p| !state_removed.iter().any(|r| p.starts_with(r))),
+      );
+    }
+    Ok(paths.into_iter().collect())
+  }
+  async fn is_dir(
+    &self,
+    path: &Path,
+  ) -> bool {
+    {
+      let state = self.lock();
+      if state.has_dir(path) {
+        return true;
+      }
+      if state.files.contains_key(path) || !self.is_on_disk(&state, path) {
+        return false;
+      }
+    }
+    DiskFileSystem.is_dir(path).await
+  }
+  async fn is_file(
+    &self,
+    path: &Path,
+  ) -> bool {
+    {
+      let state = self.lock();
+      if state.files.contains_key(path) {
+        return true;
+      }
+      if !self.is_on_disk(&state, path) {
+        return false;
+      }
+    }
+    DiskFileSystem.is_file(path).await
+  }
+  /// Memory has no modes or symlinks, so files are archived as `0o644` and dirs as `0o755`
+  async fn archive_dir(
+    &self,
+    dir_path: &Path,
+    archive_path: &Path,
+  ) -> Result<u64, IOError> {
+    let dir_name = get_dir_name(dir_path);
+    let mtime = chrono::Utc::now().timestamp().max(0) as u64;
+    let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    let mut dirs = vec![dir_path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+      let mut header = tar::Header::new_gnu();
+      header.set_entry_type(tar::EntryType::Directory);
+      header.set_size(0);
+      header.set_mode(0o755);
+      header.set_mtime(mtime);
+      let relative_dir = dir.strip_prefix(dir_path).unwrap_or(&dir);
+      builder.append_data(&mut header, dir_name.join(relative_dir), std::io::empty())?;
+      for path in self.read_dir(&dir).await? {
+        if self.is_dir(&path).await {
+          dirs.push(path);
+          continue;
+        }
+        let contents = self.read(&path).await?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        let relative_path = path.strip_prefix(dir_path).unwrap_or(&path);
+        builder.append_data(
+          &mut header,
+          dir_name.join(relative_path),
+          contents.as_slice(),
+        )?;
+      }
+    }
+    let archive_contents = builder.into_inner()?.finish()?;
+    self.write(archive_path, &archive_contents).await?;
+    Ok(archive_contents.len() as u64)
+  }
+}
+
+/// Make a not found error for a path
+fn not_found(path: &Path) -> IOError {
+  IOError::new(
+    ErrorKind::NotFound,
+    format!("No such file or directory: {}", path.to_string_lossy()),
+  )
+}
+
+/// Kinds of [FileChange]s
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum FileChangeKinds {
+  Added,
+  Modified,
+  Removed,
+}
+
+/// A file that changed, with a unified diff of the change
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileChange {
+  pub path: PathBuf,
+  pub kind: FileChangeKinds,
+  pub diff: String,
+}
+impl FileChange {
+  /// Diff the contents of a file before and after
+  pub fn new(
+    path: PathBuf,
+    before_opt: Option<&[u8]>,
+    after_opt: Option<&[u8]>,
+  ) -> Self {
+    let kind = match (before_opt, after_opt) {
+      (None, _) => FileChangeKinds::Added,
+      (Some(_), Some(_)) => FileChangeKinds::Modified,
+      (Some(_), None) => FileChangeKinds::Removed,
+    };
+    let path_string = path.to_string_lossy();
+    let (before, after) = (
+      before_opt.unwrap_or_default(),
+      after_opt.unwrap_or_default(),
+    );
+    let diff = match (std::str::from_utf8(before), std::str::from_utf8(after)) {
+      (Ok(before), Ok(after)) => TextDiff::from_lines(before, after)
+        .unified_diff()
+        .header(
+          before_opt.map_or("/dev/null", |_| &path_string),
+          after_opt.map_or("/dev/null", |_| &path_string),
+        )
+        .to_string(),
+      _ => format!("Binary file {path_string} differs\n"),
+    };
+    Self { path, kind, diff }
+  }
+}
+impl fmt::Display for FileChange {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    write!(f, "{}", self.diff)
+  }
+}
+
+tokio::task_local! {
+  static FILE_SYSTEM: Arc<dyn FileSystem>;
+}
+
+/// Run a future with files going through the given filesystem
+pub async fn with_file_system<F: Future>(
+  file_system: Arc<dyn FileSystem>,
+  f: F,
+) -> F::Output {
+  FILE_SYSTEM.scope(file_system, f).await
+}
+
+/// Get the filesystem in scope
+pub fn current() -> Arc<dyn FileSystem> {
+  FILE_SYSTEM
+    .try_with(Arc::clone)
+    .unwrap_or_else(|_| Arc::new(DiskFileSystem))
+}
+
+/// Write to file, reporting a [GenerationEvent::FileWritten]
+#[tracing::instrument(level = "debug", skip_all, fields(path = ?path.as_ref()))]
 pub async fn write(
   path: impl AsRef<Path>,
   contents: impl AsRef<[u8]>,
   message: Option<impl AsRef<str>>,
 ) -> Result<(), IOError> {
   let bytes = contents.as_ref().len();
-  let pp = path.as_ref().as_os_str();
-  fs::write(&path, contents).await?;
-  let wrote_message = format!(" Wrote {bytes} bytes to {pp:?}");
-  match message {
-    Some(s) => println!("{}: {wrote_message}", s.as_ref()),
-    None => println!("{wrote_message}"),
-  }
+  current().write(path.as_ref(), contents.as_ref()).await?;
+  events::emit(GenerationEvent::FileWritten {
+    path: path.as_ref().to_path_buf(),
+    bytes,
+    description: message.map(|s| s.as_ref().to_string()),
+  });
   Ok(())
 }
+
+/// Read a file
+pub async fn read(path: impl AsRef<Path>) -> Result<Vec<u8>, IOError> {
+  current().read(path.as_ref()).await
+}
+
+/// Read a UTF-8 file
+pub async fn read_to_string(path: impl AsRef<Path>) -> Result<String, IOError> {
+  String::from_utf8(read(path).await?).map_err(|e| IOError::new(ErrorKind::InvalidData, e))
+}
+
+/// Create a dir and its missing parents
+pub async fn create_dir_all(path: impl AsRef<Path>) -> Result<(), IOError> {
+  current().create_dir_all(path.as_ref()).await
+}
+
+/// Remove a file
+pub async fn remove_file(path: impl AsRef<Path>) -> Result<(), IOError> {
+  current().remove_file(path.as_ref()).await
+}
+
+/// Remove an empty dir
+pub async fn remove_dir(path: impl AsRef<Path>) -> Result<(), IOError> {
+  current().remove_dir(path.as_ref()).await
+}
+
+/// Remove a dir and everything in it
+pub async fn remove_dir_all(path: impl AsRef<Path>) -> Result<(), IOError> {
+  current().remove_dir_all(path.as_ref()).await
+}
+
+/// Move a file or dir
+pub async fn rename(
+  from: impl AsRef<Path>,
+  to: impl AsRef<Path>,
+) -> Result<(), IOError> {
+  current().rename(from.as_ref(), to.as_ref()).await
+}
+
+/// Copy a file along with its permissions, replacing the copy if it exists
+pub async fn copy(
+  from: impl AsRef<Path>,
+  to: impl AsRef<Path>,
+) -> Result<(), IOError> {
+  current().copy(from.as_ref(), to.as_ref()).await
+}
+
+/// List the paths in a dir, sorted
+pub async fn read_dir(path: impl AsRef<Path>) -> Result<Vec<PathBuf>, IOError> {
+  current().read_dir(path.as_ref()).await
+}
+
+/// Whether a dir exists
+pub async fn is_dir(path: impl AsRef<Path>) -> bool {
+  current().is_dir(path.as_ref()).await
+}
+
+/// Whether a file exists
+pub async fn is_file(path: impl AsRef<Path>) -> bool {
+  current().is_file(path.as_ref()).await
+}
+
+/// Archive a dir into a `.tar.gz`, reporting a [GenerationEvent::FileWritten]
+#[tracing::instrument(level = "debug", skip_all, fields(dir_path = ?dir_path.as_ref()))]
+pub async fn archive_dir(
+  dir_path: impl AsRef<Path>,
+  archive_path: impl AsRef<Path>,
+  message: Option<impl AsRef<str>>,
+) -> Result<(), IOError> {
+  let bytes = current()
+    .archive_dir(dir_path.as_ref(), archive_path.as_ref())
+    .await?;
+  events::emit(GenerationEvent::FileWritten {
+    path: archive_path.as_ref().to_path_buf(),
+    bytes: bytes as usize,
+    description: message.map(|s| s.as_ref().to_string()),
+  });
+  Ok(())
+}
+
+/// Get the name of a dir, which its archive entries start with
+fn get_dir_name(dir_path: &Path) -> PathBuf {
+  dir_path.file_name().map(PathBuf::from).unwrap_or_default()
+}
+
+/// Get the file paths in a dir, recursively and sorted
+pub async fn walk_files(dir_path: impl AsRef<Path>) -> Result<Vec<PathBuf>, IOError> {
+  walk_files_in(current().as_ref(), dir_path.as_ref()).await
+}
+
+/// Get the file paths in a dir of a filesystem, recursively and sorted
+async fn walk_files_in(
+  file_system: &dyn FileSystem,
+  dir_path: &Path,
+) -> Result<Vec<PathBuf>, IOError> {
+  let (mut files, mut dirs) = (vec![], vec![dir_path.to_path_buf()]);
+  while let Some(dir) = dirs.pop() {
+    for path in file_system.read_dir(&dir).await? {
+      if file_system.is_dir(&path).await {
+        dirs.push(path);
+      } else {
+        files.push(path);
+      }
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+#[cfg(test)]
+mod test_fs {
+  use super::*;
+  #[tokio::test]
+  async fn memory_file_system_moves_dirs() {
+    let file_system = MemoryFileSystem::new();
+    let (from, to) = (Path::new("/virtual/from"), Path::new("/virtual/to"));
+    file_system
+      .create_dir_all(&from.join("empty"))
+      .await
+      .expect("must create dir");
+    file_system
+      .write(&from.join("a/b.txt"), b"b")
+      .await
+      .expect("must write");
+    file_system.rename(from, to).await.expect("must rename");
+    assert!(!file_system.is_dir(from).await);
+    assert!(file_system.is_dir(&to.join("empty")).await);
+    assert_eq!(
+      file_system.read_dir(to).await.expect("must read dir"),
+      vec![to.join("a"), to.join("empty")]
+    );
+    assert_eq!(
+      file_system.get_files().into_keys().collect::<Vec<_>>(),
+      vec![to.join("a/b.txt")]
+    );
+  }
+}
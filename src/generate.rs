@@ -1,22 +1,36 @@
 //! Code generation
 pub mod cargos;
+pub mod changelogs;
 pub mod crate_scaffolds;
 pub mod makefiles;
+pub mod migrations;
 pub mod parameters;
+pub mod post_generation;
 pub mod readmes;
+pub mod specs;
+pub mod task_graphs;
+pub mod task_runners;
 
 pub mod utils;
+pub mod validations;
 // pub use utils::*;
+pub mod workspaces;
 pub mod yamls;
 
 pub mod errors {
   use super::*;
+  pub use crate::process::ProcessError;
   pub use cargos::CargoConfigError;
+  pub use changelogs::ChangelogGenerationError;
   pub use crate_scaffolds::CrateScaffoldingError;
   pub use makefiles::MakefileGenerationError;
+  pub use migrations::MakefileMigrationError;
   pub use parameters::ParameterError;
+  pub use post_generation::PostGenerationError;
   pub use readmes::READMEGenerationError;
-  pub use utils::ProcessError;
+  pub use specs::SpecError;
+  pub use validations::MakefileValidationError;
+  pub use workspaces::WorkspaceError;
   pub use yamls::YAMLGenerationError;
 }
 pub use errors::*;
@@ -2,12 +2,15 @@
 use crate::{
   cli::{Cli, InnerCli, Paths, SubCommands},
   fs,
-  generate::utils,
+  generate::{
+    utils,
+    workspaces::{WorkspaceError, WorkspaceManifest},
+  },
   vv,
 };
 use cargo_toml::{Edition, Error as CargoTomlError, Product, *};
 use serde::{Deserialize, Serialize};
-use std::io::Error as IOError;
+use std::{io::Error as IOError, path::PathBuf};
 use strum::EnumProperty;
 use thiserror::Error;
 use toml::ser::Error as TomlSerError;
@@ -23,6 +26,8 @@ pub enum CargoConfigError {
   UpdateRustEditionError(Edition),
   #[error(transparent)]
   IOError(#[from] IOError),
+  #[error(transparent)]
+  WorkspaceError(#[from] WorkspaceError),
 }
 
 /// Cargo toml modifier
@@ -40,6 +45,9 @@ pub struct CargoConfigurator {
   pub original_api_name: String,
   /// The cli subcommand if applicable
   pub subcommand_opt: Option<SubCommands>,
+  /// The dir of the workspace the crate is a member of, if any
+  #[serde(default)]
+  pub workspace_dir_opt: Option<PathBuf>,
 }
 impl CargoConfigurator {
   /// Instantiate
@@ -52,6 +60,7 @@ impl CargoConfigurator {
     let generation_timestamp_string = cli.get_generation_timestamp_string();
     let subcommand_opt = cli.inner_cli.command.clone();
     // dbg!(&subcommand_opt);
+    let workspace_dir_opt = cli.inner_cli.workspace_dir_opt.clone();
     Ok(Self {
       generation_timestamp_string,
       generation_authors,
@@ -59,6 +68,7 @@ impl CargoConfigurator {
       this_crate_ver,
       original_api_name,
       subcommand_opt,
+      workspace_dir_opt,
     })
   }
 
@@ -103,12 +113,10 @@ impl CargoConfigurator {
     }
   }
 
-  /// Update a cargo.toml file **AFTER** cargo check --edition
-  pub async fn update_cargo_manifest_post_fix_edition(&self) -> Result<(), CargoConfigError> {
-    let cargo_toml_path = Paths::CargoTomlFile
-      .get_str("path")
-      .expect("must get Cargo.toml path");
-    let cargo_manifest = &mut Manifest::<String>::from_path_with_metadata(cargo_toml_path)?;
+  /// Update the editions of a manifest **AFTER** cargo check --edition, describing the update
+  pub fn update_manifest_post_fix_edition(
+    cargo_manifest: &mut Manifest<String>
+  ) -> Result<String, CargoConfigError> {
     let Manifest { package, lib, .. } = cargo_manifest;
     let p = package.get_or_insert_with(Default::default);
     let package_update_desc = Self::update_edition("manifest package", p.edition.get_mut()?)?;
@@ -116,24 +124,33 @@ impl CargoConfigurator {
       .as_mut()
       .map(|Product { edition, .. }| Self::update_edition("manifest lib target", edition))
       .unwrap_or_else(|| Ok(Default::default()))?;
+    Ok(format!("{package_update_desc}, {lib_update_desc}"))
+  }
+
+  /// Update a cargo.toml file **AFTER** cargo check --edition
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn update_cargo_manifest_post_fix_edition(&self) -> Result<(), CargoConfigError> {
+    let cargo_toml_path = Paths::CargoTomlFile
+      .get_str("path")
+      .expect("must get Cargo.toml path");
+    let cargo_manifest = &mut Manifest::<String>::from_path_with_metadata(cargo_toml_path)?;
+    let update_desc = Self::update_manifest_post_fix_edition(cargo_manifest)?;
     fs::write(
       cargo_toml_path,
       toml::to_string_pretty(cargo_manifest)?,
       Some(&format!(
-        "updated cargo manifest edition post fix ({package_update_desc}, {lib_update_desc})"
+        "updated cargo manifest edition post fix ({update_desc})"
       )),
     )
     .await?;
     Ok(())
   }
 
-  /// Update a cargo.toml file **AFTER** code generation
-  pub async fn update_cargo_manifest_post_generation(&self) -> Result<(), CargoConfigError> {
-    // dbg!(self);
-    let cargo_toml_path = Paths::CargoTomlFile
-      .get_str("path")
-      .expect("must get Cargo.toml path");
-    let cargo_manifest = &mut Manifest::<String>::from_path_with_metadata(cargo_toml_path)?;
+  /// Update a manifest **AFTER** code generation
+  pub fn update_manifest_post_generation(
+    &self,
+    cargo_manifest: &mut Manifest<String>,
+  ) -> Result<(), CargoConfigError> {
     let Manifest {
       package,
       dev_dependencies,
@@ -152,7 +169,7 @@ impl CargoConfigurator {
       ));
     p.keywords
       .get_mut()?
-      .extend(vv![strings self.original_api_name.as_str(), "OpenAPI", "web",].into_iter());
+      .extend(vv![strings self.original_api_name.as_str(), "OpenAPI", "web",]);
     p.categories_mut()
       .extend(vv![strings "web-programming", "api-bindings", "authentication", ]);
     let mut this_crate_dependency: Dependency = Dependency::Detailed(DependencyDetail {
@@ -171,19 +188,30 @@ impl CargoConfigurator {
               .path
               .replace(generator_crate_local_path.to_string_lossy().to_string());
           }
-          None => match generator_crate_repo_url_opt {
-            Some(generator_crate_repo_url) => {
+          None => {
+            if let Some(generator_crate_repo_url) = generator_crate_repo_url_opt {
               this_crate_dependency
                 .detail_mut()
                 .git
                 .replace(generator_crate_repo_url.as_str().to_string());
             }
-            None => {}
-          },
+          }
         },
+        SubCommands::Tool(_) => {}
       }
     }
     dev_dependencies.insert(self.this_crate_name.to_string(), this_crate_dependency);
+    Ok(())
+  }
+
+  /// Update a cargo.toml file **AFTER** code generation
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn update_cargo_manifest_post_generation(&self) -> Result<(), CargoConfigError> {
+    let cargo_toml_path = Paths::CargoTomlFile
+      .get_str("path")
+      .expect("must get Cargo.toml path");
+    let cargo_manifest = &mut Manifest::<String>::from_path_with_metadata(cargo_toml_path)?;
+    self.update_manifest_post_generation(cargo_manifest)?;
     fs::write(
       cargo_toml_path,
       toml::to_string_pretty(cargo_manifest)?,
@@ -192,4 +220,37 @@ impl CargoConfigurator {
     .await?;
     Ok(())
   }
+
+  /// Move the registry dependencies of a workspace member's cargo.toml into the workspace
+  ///
+  /// Run this **LAST**: reading the manifest with [Manifest::from_path] inlines what it inherits.
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn hoist_dependencies_into_workspace(&self) -> Result<(), CargoConfigError> {
+    let Some(workspace_dir) = self.workspace_dir_opt.as_ref() else {
+      return Ok(());
+    };
+    let cargo_toml_path = Paths::CargoTomlFile
+      .get_str("path")
+      .expect("must get Cargo.toml path");
+    let mut member_document = fs::read_to_string(cargo_toml_path)
+      .await?
+      .parse()
+      .map_err(WorkspaceError::from)?;
+    let mut workspace_manifest = WorkspaceManifest::read(workspace_dir).await?;
+    let hoisted_names = workspace_manifest.hoist_dependencies(&mut member_document);
+    if hoisted_names.is_empty() {
+      return Ok(());
+    }
+    workspace_manifest.write().await?;
+    fs::write(
+      cargo_toml_path,
+      member_document.to_string(),
+      Some(&format!(
+        "moved dependencies into the workspace ({})",
+        hoisted_names.join(", ")
+      )),
+    )
+    .await?;
+    Ok(())
+  }
 }
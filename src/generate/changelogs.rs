@@ -0,0 +1,417 @@
+//! CHANGELOG file generation
+//!
+//! Each regeneration adds a dated entry in [Keep a Changelog](https://keepachangelog.com/en/1.1.0/)
+//! format. Operations and models are compared against a snapshot of the spec saved by the previous
+//! regeneration.
+use crate::fs::{self, write};
+use crate::{
+  cli::{Cli, Paths},
+  events,
+  generate::{errors::ParameterError, makefiles::MakefileEnv, specs::*, utils::*},
+  process::{self, ProcessSpec},
+};
+use cargo_toml::{Error as CargoTomlError, Manifest};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Error as SerdeYAMLError, Value};
+use std::{
+  collections::BTreeMap,
+  env,
+  io::{Error as IOError, ErrorKind},
+  string::FromUtf8Error,
+};
+use strum::EnumProperty;
+use thiserror::Error;
+
+/// Errors that can happen with changelog generation
+#[derive(Debug, Error)]
+pub enum ChangelogGenerationError {
+  #[error(transparent)]
+  CargoTomlError(#[from] CargoTomlError),
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  FromUtf8Error(#[from] FromUtf8Error),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+  #[error(transparent)]
+  SpecError(#[from] SpecError),
+}
+
+/// Fingerprints of the operations and models of a spec
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SpecSnapshot {
+  /// Operation fingerprints by operation key
+  pub operations: BTreeMap<String, String>,
+  /// Operation function names by operation key
+  pub operation_functions: BTreeMap<String, String>,
+  /// Model fingerprints by model name
+  pub models: BTreeMap<String, String>,
+  /// The generator versions the crate was generated with
+  #[serde(default)]
+  pub generator_versions: GeneratorVersions,
+}
+impl SpecSnapshot {
+  /// Take a snapshot of a spec from its contents
+  pub fn try_from_spec_str(spec_str: &str) -> Result<Self, ChangelogGenerationError> {
+    let spec = OpenAPISpec::try_from_str(spec_str)?;
+    let spec_value: Value = serde_yaml::from_str(spec_str)?;
+    let mut snapshot = Self::default();
+    for entry in spec.operations() {
+      let key = entry.get_key();
+      let path_value = &spec_value["paths"][entry.path];
+      let operation_value = match &path_value["parameters"] {
+        Value::Null => path_value[entry.method].clone(),
+        // the parameters of the path are part of each of its operations
+        parameters => Value::Sequence(vec![path_value[entry.method].clone(), parameters.clone()]),
+      };
+      snapshot
+        .operations
+        .insert(key.clone(), Self::fingerprint(&operation_value)?);
+      snapshot
+        .operation_functions
+        .insert(key, entry.get_function_name());
+    }
+    let models_value = match &spec_value["components"]["schemas"] {
+      Value::Null => &spec_value["definitions"],
+      schemas => schemas,
+    };
+    for name in spec.models().keys() {
+      snapshot.models.insert(
+        name.to_string(),
+        Self::fingerprint(&models_value[name.as_str()])?,
+      );
+    }
+    Ok(snapshot)
+  }
+  /// Fingerprint a spec value with 64-bit FNV-1a so snapshots stay stable between toolchains
+  fn fingerprint(value: &Value) -> Result<String, ChangelogGenerationError> {
+    let hash = serde_yaml::to_string(value)?
+      .bytes()
+      .fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+      });
+    Ok(format!("{hash:016x}"))
+  }
+  /// Compare against an earlier snapshot
+  pub fn diff(
+    &self,
+    earlier: &Self,
+  ) -> SpecChanges {
+    let describe_operation = |key: &String| match self
+      .operation_functions
+      .get(key)
+      .or_else(|| earlier.operation_functions.get(key))
+    {
+      Some(function_name) => format!("Operation `{key}` (`{function_name}`)"),
+      None => format!("Operation `{key}`"),
+    };
+    let describe_model = |name: &String| format!("Model `{name}`");
+    let mut changes = SpecChanges::default();
+    for (key, fingerprint) in self.operations.iter() {
+      match earlier.operations.get(key) {
+        None => changes.added.push(describe_operation(key)),
+        Some(earlier_fingerprint) if earlier_fingerprint != fingerprint => {
+          changes.changed.push(describe_operation(key))
+        }
+        _ => {}
+      }
+    }
+    for key in earlier.operations.keys() {
+      if !self.operations.contains_key(key) {
+        changes.removed.push(describe_operation(key));
+      }
+    }
+    for (name, fingerprint) in self.models.iter() {
+      match earlier.models.get(name) {
+        None => changes.added.push(describe_model(name)),
+        Some(earlier_fingerprint) if earlier_fingerprint != fingerprint => {
+          changes.changed.push(describe_model(name))
+        }
+        _ => {}
+      }
+    }
+    for name in earlier.models.keys() {
+      if !self.models.contains_key(name) {
+        changes.removed.push(describe_model(name));
+      }
+    }
+    changes
+  }
+}
+
+/// The versions of the generators a crate was generated with
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct GeneratorVersions {
+  /// The version of this crate
+  pub this_crate_ver: Option<String>,
+  /// The version of the OpenAPI Generator CLI
+  pub open_api_generator_version: Option<String>,
+}
+
+/// Changes between two spec snapshots
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SpecChanges {
+  pub added: Vec<String>,
+  pub removed: Vec<String>,
+  pub changed: Vec<String>,
+}
+impl SpecChanges {
+  /// Whether nothing changed
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+  }
+}
+
+/// Changelog generation
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChangelogGenerator {
+  /// The path of the spec file relative to the generated crate
+  spec_file_path: String,
+  /// The name of this crate
+  this_crate_name: String,
+  /// The version of this crate
+  this_crate_ver: String,
+  /// The timestamp of generation
+  generation_timestamp_string: String,
+}
+impl ChangelogGenerator {
+  /// Changelog preamble
+  const PREAMBLE: &'static str = "# Changelog
+
+All notable changes to this project will be documented in this file.
+
+The format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/).
+";
+  /// Instantiate
+  pub fn new(cli: &Cli) -> Result<Self, ChangelogGenerationError> {
+    Ok(Self {
+      spec_file_path: cli.try_get_spec_file_name()?,
+      this_crate_name: get_this_crate_name().to_string(),
+      this_crate_ver: get_this_crate_ver().to_string(),
+      generation_timestamp_string: cli.get_generation_timestamp_string(),
+    })
+  }
+  /// Get the generation date (`YYYY-MM-DD`)
+  fn get_generation_date(&self) -> &str {
+    self
+      .generation_timestamp_string
+      .get(0..10)
+      .unwrap_or(&self.generation_timestamp_string)
+  }
+  /// Try to get the version of the OpenAPI Generator CLI in use
+  async fn get_open_api_generator_version() -> String {
+    let cli_script = env::var("OPEN_API_GENERATOR_CLI_SCRIPT")
+      .unwrap_or_else(|_| MakefileEnv::OPEN_API_GENERATOR_CLI_SCRIPT.to_string());
+    let spec = ProcessSpec::new(cli_script, &["version"]);
+    match process::run(&spec, "Getting the version of the OpenAPI Generator CLI.").await {
+      Ok(output) => String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .last()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .unwrap_or_else(|| "unknown".to_string()),
+      _ => "unknown".to_string(),
+    }
+  }
+  /// Make a changelog entry
+  pub fn make_entry(
+    &self,
+    package_version: &str,
+    open_api_generator_version: &str,
+    changes: &SpecChanges,
+  ) -> String {
+    let Self {
+      this_crate_name,
+      this_crate_ver,
+      ..
+    } = self;
+    let generation_date = self.get_generation_date();
+    let mut entry = format!("## [{package_version}] - {generation_date}\n");
+    let SpecChanges {
+      added,
+      removed,
+      changed,
+    } = changes;
+    for (heading, lines) in [("Added", added), ("Removed", removed), ("Changed", changed)] {
+      let is_changed = heading == "Changed";
+      if lines.is_empty() && !is_changed {
+        continue;
+      }
+      entry.push_str(&format!("\n### {heading}\n\n"));
+      for line in lines.iter() {
+        entry.push_str(&format!("- {line}\n"));
+      }
+      if is_changed {
+        entry.push_str(&format!(
+          "- Regenerated with {this_crate_name} v{this_crate_ver} \
+          and OpenAPI Generator {open_api_generator_version}\n"
+        ));
+      }
+    }
+    entry
+  }
+  /// Insert an entry above the earlier entries of a changelog
+  pub fn insert_entry(
+    changelog_contents_opt: Option<String>,
+    entry: &str,
+  ) -> String {
+    let changelog_contents =
+      changelog_contents_opt.unwrap_or_else(|| format!("{}\n", Self::PREAMBLE));
+    match changelog_contents.find("\n## ") {
+      Some(i) => format!(
+        "{}\n{entry}\n{}",
+        &changelog_contents[..i + 1],
+        &changelog_contents[i + 1..]
+      ),
+      None => {
+        let mut changelog_contents = changelog_contents;
+        if !changelog_contents.ends_with("\n\n") {
+          changelog_contents.push('\n');
+        }
+        changelog_contents.push_str(entry);
+        changelog_contents
+      }
+    }
+  }
+  /// Add an entry to the changelog file and update the spec snapshot
+  ///
+  /// No entry is added if neither the spec nor the generator versions changed since the snapshot.
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn update_changelog_md_file(&self) -> Result<(), ChangelogGenerationError> {
+    let changelog_path = Paths::ChangelogMdFile
+      .get_str("path")
+      .expect("must get CHANGELOG.md path");
+    let snapshot_path = Paths::SpecSnapshotFile
+      .get_str("path")
+      .expect("must get spec snapshot path");
+    let cargo_toml_path = Paths::CargoTomlFile
+      .get_str("path")
+      .expect("must get Cargo.toml path");
+    let spec_str = fs::read_to_string(&self.spec_file_path).await?;
+    let open_api_generator_version = Self::get_open_api_generator_version().await;
+    let mut snapshot = SpecSnapshot::try_from_spec_str(&spec_str)?;
+    snapshot.generator_versions = GeneratorVersions {
+      this_crate_ver: Some(self.this_crate_ver.clone()),
+      open_api_generator_version: Some(open_api_generator_version.clone()),
+    };
+    let earlier_snapshot = match fs::read_to_string(snapshot_path).await {
+      Ok(s) => serde_yaml::from_str(&s)?,
+      Err(e) if e.kind() == ErrorKind::NotFound => SpecSnapshot::default(),
+      Err(e) => return Err(e.into()),
+    };
+    let changes = snapshot.diff(&earlier_snapshot);
+    if changes.is_empty() && snapshot.generator_versions == earlier_snapshot.generator_versions {
+      events::message("Spec and generators are unchanged, not adding a CHANGELOG.md entry");
+      return Ok(());
+    }
+    let package_version =
+      Manifest::<String>::from_slice_with_metadata(&fs::read(cargo_toml_path).await?)?
+        .package
+        .and_then(|p| p.version.get().ok().cloned())
+        .unwrap_or_else(|| "Unreleased".to_string());
+    let entry = self.make_entry(&package_version, &open_api_generator_version, &changes);
+    let changelog_contents_opt = match fs::read(changelog_path).await {
+      Ok(contents) => Some(String::from_utf8(contents)?),
+      Err(_) => None,
+    };
+    let changelog_contents = Self::insert_entry(changelog_contents_opt, &entry);
+    write(changelog_path, &changelog_contents, Some("CHANGELOG.md")).await?;
+    write(
+      snapshot_path,
+      serde_yaml::to_string(&snapshot)?,
+      Some("Spec snapshot"),
+    )
+    .await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test_changelogs {
+  use super::*;
+  use crate::{fs::MemoryFileSystem, process::RecordingProcessRunner, testing};
+  use std::sync::Arc;
+  #[tokio::test]
+  async fn gets_the_generator_version_through_the_runner() {
+    let process_runner = Arc::new(RecordingProcessRunner::default());
+    let version = process::with_process_runner(
+      process_runner.clone(),
+      ChangelogGenerator::get_open_api_generator_version(),
+    )
+    .await;
+    assert_eq!(version, "unknown");
+    assert_eq!(
+      process_runner.get_runs(),
+      vec![ProcessSpec::new(
+        MakefileEnv::OPEN_API_GENERATOR_CLI_SCRIPT,
+        &["version"]
+      )]
+    );
+  }
+  #[test]
+  fn changelog_entries_list_spec_changes() {
+    let snapshot =
+      SpecSnapshot::try_from_spec_str(testing::PETSTORE_YAML).expect("must take snapshot");
+    let mut earlier = snapshot.clone();
+    earlier.operations.remove("GET /pets");
+    earlier.models.insert("Owner".to_string(), "0".to_string());
+    earlier.models.insert("Pet".to_string(), "0".to_string());
+    let changes = snapshot.diff(&earlier);
+    assert_eq!(
+      changes,
+      SpecChanges {
+        added: vec!["Operation `GET /pets` (`list_pets`)".to_string()],
+        removed: vec!["Model `Owner`".to_string()],
+        changed: vec!["Model `Pet`".to_string()],
+      }
+    );
+    let changelog_generator = ChangelogGenerator {
+      spec_file_path: "spec.yaml".to_string(),
+      this_crate_name: "olg".to_string(),
+      this_crate_ver: "1.2.3".to_string(),
+      generation_timestamp_string: "2023-04-01T00:00:00+00:00".to_string(),
+    };
+    let entry = changelog_generator.make_entry("1.0.0", "6.5.0", &changes);
+    let first = ChangelogGenerator::insert_entry(None, &entry);
+    let second = ChangelogGenerator::insert_entry(Some(first.clone()), "## [1.0.1] - 2023-04-02\n");
+    assert!(first.starts_with(ChangelogGenerator::PREAMBLE));
+    assert!(first.contains("## [1.0.0] - 2023-04-01\n\n### Added\n\n- Operation `GET /pets`"));
+    assert!(first.contains("- Regenerated with olg v1.2.3 and OpenAPI Generator 6.5.0\n"));
+    assert!(second.find("## [1.0.1]") < second.find("## [1.0.0]"));
+  }
+  #[tokio::test]
+  async fn changelog_entries_are_only_added_for_changes() {
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let changelog_generator = ChangelogGenerator {
+      spec_file_path: "spec.yaml".to_string(),
+      this_crate_name: "olg".to_string(),
+      this_crate_ver: "1.2.3".to_string(),
+      generation_timestamp_string: "2023-04-01T00:00:00+00:00".to_string(),
+    };
+    let run = async {
+      write("spec.yaml", testing::PETSTORE_YAML, None::<&str>).await?;
+      write(
+        "Cargo.toml",
+        "[package]\nname = \"pets\"\nversion = \"1.0.0\"\n",
+        None::<&str>,
+      )
+      .await?;
+      changelog_generator.update_changelog_md_file().await?;
+      changelog_generator.update_changelog_md_file().await?;
+      let changelog = fs::read_to_string("CHANGELOG.md").await?;
+      write("spec_snapshot.yaml", "operations: [", None::<&str>).await?;
+      let corrupt_result = changelog_generator.update_changelog_md_file().await;
+      Ok::<_, ChangelogGenerationError>((changelog, corrupt_result))
+    };
+    let (changelog, corrupt_result) = fs::with_file_system(file_system, run)
+      .await
+      .expect("must update changelog");
+    assert_eq!(changelog.matches("## [1.0.0]").count(), 1);
+    assert!(matches!(
+      corrupt_result,
+      Err(ChangelogGenerationError::SerdeYAMLError(_))
+    ));
+  }
+}
@@ -1,19 +1,28 @@
 //! Set up target crate
 
 use crate::{
-  cli::{Cli, Paths, SubCommands},
+  cli::{Cli, OutputDirModes, Paths, SubCommands},
+  events, fs,
   generate::{
-    makefiles::TaskNames,
-    utils::{run_cargo_job, ProcessError},
+    makefiles::MakefileEnv,
+    parameters::ParameterError,
+    task_runners::TaskRunners,
+    utils::{get_temp_root_dir, get_this_crate_name, run_cargo_job},
+    workspaces::{self, WorkspaceError, WorkspaceManifest, WorkspaceSnapshot},
     yamls,
   },
+  process::{ProcessError, ProcessFailure},
 };
-use fs_err::tokio as fs;
+use chrono::Utc;
 use futures::future::TryFutureExt;
-use std::{io::Error as IOError, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+  env,
+  io::{Error as IOError, ErrorKind},
+  path::{Path, PathBuf},
+};
 use strum::EnumProperty;
 use thiserror::Error;
-use tokio::process;
 
 /// Crate scaffolding errors
 #[derive(Debug, Error)]
@@ -30,21 +39,451 @@ pub enum CrateScaffoldingError {
   NonEmptyTargetDir(PathBuf),
   #[error("Could not find crate dir at {0}")]
   MissingCrateDir(PathBuf),
-  #[error(transparent)]
-  CargoMakeTaskFailed(#[from] TaskNames),
-  #[error("Cargo init project at `{crate_dir}` failed with `{error_string}`  ")]
+  #[error("Cargo init project at `{crate_dir}` failed: {failure}")]
   CargoInitFailed {
     crate_dir: PathBuf,
-    error_string: String,
+    failure: Box<ProcessFailure>,
   },
-  #[error("Installing `cargo-make` failed with `{error_string}`  ")]
-  CargoMakeInstallFailed { error_string: String },
+  #[error("Generation was interrupted")]
+  Interrupted,
+  #[error("Refusing to delete {0} because it is outside of the system temp dir")]
+  RefuseToDeleteOutsideTemp(PathBuf),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
+  #[error(transparent)]
+  WorkspaceError(#[from] WorkspaceError),
+}
+
+/// Result of merging managed files into a non-empty output dir
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MergeReport {
+  /// Managed files that were missing and have been written
+  pub written: Vec<PathBuf>,
+  /// Managed files that were already up to date
+  pub unchanged: Vec<PathBuf>,
+  /// Managed files that differ; the generated version is written next to them
+  pub conflicts: Vec<PathBuf>,
+}
+
+/// A crate scaffolded in a staging dir and moved into the output dir only once generation succeeds
+#[derive(Debug)]
+pub struct ScaffoldTransaction {
+  /// The cli that builds in the staging dir
+  staged_cli: Cli,
+  /// The dir the crate is moved to on success
+  target_dir: PathBuf,
+  /// The dir the crate is built in
+  staging_dir: PathBuf,
+  /// Whether the target dir may be replaced
+  is_test: bool,
+  /// What to do if the target dir is not empty
+  output_dir_mode: OutputDirModes,
+  /// Whether to keep the staging dir on failure
+  keep_failed: bool,
+  /// Whether the managed files get merged into an existing crate in the target dir
+  is_merging: bool,
+  /// Whether the staging dir is in the system temp dir, as the parent of the target dir could not
+  /// be written to
+  is_staged_in_temp_dir: bool,
+  /// The root files of the workspace the crate joins, as they were before
+  workspace_snapshot_opt: Option<WorkspaceSnapshot>,
+}
+impl ScaffoldTransaction {
+  /// Get a hidden dir next to the target dir, for a purpose
+  fn get_sibling_dir(
+    target_dir: &Path,
+    purpose: &str,
+  ) -> PathBuf {
+    let dir_name = target_dir
+      .file_name()
+      .map(|s| s.to_string_lossy().to_string())
+      .unwrap_or_default();
+    target_dir
+      .parent()
+      .map(Path::to_path_buf)
+      .unwrap_or_default()
+      .join(format!(".{dir_name}.{}-{purpose}", get_this_crate_name()))
+  }
+  /// Get the staging dir next to the target dir so the final move stays on the same filesystem
+  fn get_staging_dir(target_dir: &Path) -> PathBuf {
+    Self::get_sibling_dir(target_dir, "staging")
+  }
+  /// Get a staging dir in the system temp dir, for when the parent of the target dir is read-only
+  fn get_temp_staging_dir(cli: &Cli) -> PathBuf {
+    get_temp_root_dir().join(format!(
+      "{}-{}-{}-staging",
+      get_this_crate_name(),
+      cli.get_lib_name(),
+      cli.generation_timestamp.format("%Y%m%dT%H%M%S%fZ")
+    ))
+  }
+  /// Suffix for the generated version of a managed file that conflicts with an existing one
+  pub const CONFLICT_SUFFIX: &'static str = "olg-new";
+  /// Get the names of the files this generator manages in the crate
+  pub fn get_managed_file_names(cli: &Cli) -> Result<Vec<String>, CrateScaffoldingError> {
+    let path_of = |p: Paths| p.get_str("path").expect("must get path").to_string();
+    let mut file_names = cli.task_runner.get_file_names();
+    file_names.extend([
+      path_of(Paths::GitignoreFile),
+      path_of(Paths::PostGenerationConfigFile),
+      MakefileEnv::OPEN_API_GENERATOR_CONFIG_FILE.to_string(),
+      cli.try_get_spec_file_name()?,
+    ]);
+    Ok(file_names)
+  }
+  /// Check whether a dir exists and has anything in it
+  async fn is_non_empty_dir(dir_path: &Path) -> Result<bool, CrateScaffoldingError> {
+    Ok(fs::is_dir(dir_path).await && !fs::read_dir(dir_path).await?.is_empty())
+  }
+  /// Check the target and set up a fresh staging dir
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn begin(cli: &Cli) -> Result<Self, CrateScaffoldingError> {
+    let target_dir = cli.get_output_project_dir();
+    let is_test = matches!(
+      cli.inner_cli.command,
+      Some(SubCommands::TestGeneration { .. })
+    );
+    let output_dir_mode = cli.get_output_dir_mode();
+    let is_target_non_empty = Self::is_non_empty_dir(&target_dir).await?;
+    if is_test {
+      ensure_in_temp_dir(&target_dir)?;
+    } else if output_dir_mode == OutputDirModes::RequireEmpty && is_target_non_empty {
+      return Err(CrateScaffoldingError::NonEmptyTargetDir(target_dir));
+    }
+    let staging_dir = Self::get_staging_dir(&target_dir);
+    let workspace_snapshot_opt = match cli.workspace_dir_opt.as_ref() {
+      Some(workspace_dir) => {
+        WorkspaceManifest::read(workspace_dir).await?;
+        workspaces::get_member_path(workspace_dir, &target_dir)?;
+        Some(WorkspaceSnapshot::take(workspace_dir).await?)
+      }
+      None => None,
+    };
+    if fs::is_dir(&staging_dir).await {
+      fs::remove_dir_all(&staging_dir).await?;
+    }
+    let (staging_dir, is_staged_in_temp_dir) = match fs::create_dir_all(&staging_dir).await {
+      Ok(()) => (staging_dir, false),
+      // a workspace member has to be staged in the workspace
+      Err(e)
+        if matches!(
+          e.kind(),
+          ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+        ) && workspace_snapshot_opt.is_none() =>
+      {
+        let temp_staging_dir = Self::get_temp_staging_dir(cli);
+        fs::create_dir_all(&temp_staging_dir).await?;
+        events::message(format!(
+          "Cannot write next to `{}`, staging the crate in `{}` instead",
+          target_dir.to_string_lossy(),
+          temp_staging_dir.to_string_lossy()
+        ));
+        (temp_staging_dir, true)
+      }
+      Err(e) => return Err(e.into()),
+    };
+    Ok(Self {
+      staged_cli: cli.staged(staging_dir.clone()),
+      target_dir,
+      staging_dir,
+      is_test,
+      output_dir_mode,
+      keep_failed: cli.inner_cli.keep_failed,
+      is_merging: !is_test && output_dir_mode == OutputDirModes::Merge && is_target_non_empty,
+      is_staged_in_temp_dir,
+      workspace_snapshot_opt,
+    })
+  }
+  /// Get the cli that builds in the staging dir
+  pub fn get_staged_cli(&self) -> &Cli {
+    &self.staged_cli
+  }
+  /// Whether the managed files get merged into an existing crate instead of moving the crate
+  pub fn is_merging(&self) -> bool {
+    self.is_merging
+  }
+  /// Archive a dir into a timestamped `.tar.gz` next to it
+  async fn archive_dir(dir_path: &Path) -> Result<PathBuf, CrateScaffoldingError> {
+    let dir_name = dir_path
+      .file_name()
+      .map(|s| s.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+    let archive_path = dir_path.with_file_name(format!("{dir_name}.{timestamp}.tar.gz"));
+    fs::archive_dir(dir_path, &archive_path, Some("Backup")).await?;
+    Ok(archive_path)
+  }
+  /// Copy the managed files from the staging dir into the target dir without overwriting changes
+  async fn merge_managed_files(&self) -> Result<MergeReport, CrateScaffoldingError> {
+    let mut report = MergeReport::default();
+    for file_name in Self::get_managed_file_names(&self.staged_cli)? {
+      let staged_path = self.staging_dir.join(&file_name);
+      let target_path = self.target_dir.join(&file_name);
+      let staged_contents = match fs::read(&staged_path).await {
+        Ok(contents) => contents,
+        Err(_) => continue,
+      };
+      // the files of an xtask crate are in subdirs
+      if let Some(target_parent_dir) = target_path.parent() {
+        fs::create_dir_all(target_parent_dir).await?;
+      }
+      match fs::read(&target_path).await {
+        Err(_) => {
+          fs::write(&target_path, &staged_contents, Some("Merge")).await?;
+          report.written.push(target_path);
+        }
+        Ok(target_contents) if target_contents == staged_contents => {
+          report.unchanged.push(target_path);
+        }
+        Ok(_) => {
+          let conflict_path = self
+            .target_dir
+            .join(format!("{file_name}.{}", Self::CONFLICT_SUFFIX));
+          fs::write(&conflict_path, &staged_contents, Some("Merge conflict")).await?;
+          report.conflicts.push(target_path);
+        }
+      }
+    }
+    Ok(report)
+  }
+  /// Move the staged crate into the target dir, and into the workspace members if it joins one
+  ///
+  /// The workspace is edited before the move, and the transaction is rolled back if either fails.
+  /// Returns a report of the merge if the target dir was merged into.
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn commit(self) -> Result<Option<MergeReport>, CrateScaffoldingError> {
+    let result = match self.register_in_workspace().await {
+      Ok(()) => self.move_or_merge().await,
+      Err(e) => Err(e),
+    };
+    if result.is_err() {
+      self.rollback().await?;
+    }
+    result
+  }
+  /// List the target dir instead of the staging dir in the workspace members
+  async fn register_in_workspace(&self) -> Result<(), CrateScaffoldingError> {
+    let Some(workspace_dir) = self.staged_cli.workspace_dir_opt.as_ref() else {
+      return Ok(());
+    };
+    let mut manifest = WorkspaceManifest::read(workspace_dir).await?;
+    manifest.remove_member(&workspaces::get_member_path(
+      workspace_dir,
+      &self.staging_dir,
+    )?);
+    let member_path = workspaces::get_member_path(workspace_dir, &self.target_dir)?;
+    if !manifest.has_member(&member_path) {
+      manifest.add_member(&member_path);
+    }
+    manifest.write().await?;
+    workspaces::register_client_task(
+      workspace_dir,
+      &member_path,
+      &self.staged_cli.get_lib_name(),
+      self.staged_cli.task_runner,
+    )
+    .await?;
+    events::message(format!(
+      "Registered `{member_path}` in the workspace at `{}`",
+      workspace_dir.to_string_lossy()
+    ));
+    Ok(())
+  }
+  /// Move the staged crate into the target dir, or merge its managed files into it
+  async fn move_or_merge(&self) -> Result<Option<MergeReport>, CrateScaffoldingError> {
+    let Self {
+      target_dir,
+      staging_dir,
+      is_test,
+      output_dir_mode,
+      ..
+    } = self;
+    if *is_test {
+      if fs::is_dir(target_dir).await {
+        ensure_in_temp_dir(target_dir)?;
+        clear_dir(target_dir).await?;
+      }
+    } else if Self::is_non_empty_dir(target_dir).await? {
+      match output_dir_mode {
+        OutputDirModes::RequireEmpty => {
+          return Err(CrateScaffoldingError::NonEmptyTargetDir(target_dir.clone()));
+        }
+        OutputDirModes::Force => {
+          let archive_path = Self::archive_dir(target_dir).await?;
+          events::message(format!(
+            "Backed up `{}` to `{}`",
+            target_dir.to_string_lossy(),
+            archive_path.to_string_lossy()
+          ));
+          if !self.is_staged_in_temp_dir {
+            self.swap_staged_crate().await?;
+            events::message(format!(
+              "Replaced the crate in `{}` with the generated one",
+              target_dir.to_string_lossy()
+            ));
+            return Ok(None);
+          }
+          // a crate staged on another filesystem cannot be renamed into place
+          clear_dir(target_dir).await?;
+        }
+        OutputDirModes::Merge => {
+          let report = self.merge_managed_files().await?;
+          for path in report.written.iter() {
+            events::message(format!("Wrote `{}`", path.to_string_lossy()));
+          }
+          for path in report.conflicts.iter() {
+            events::warning(format!(
+              "Conflict: `{}` differs from the generated version, which was written to `{}.{}`",
+              path.to_string_lossy(),
+              path.to_string_lossy(),
+              Self::CONFLICT_SUFFIX
+            ));
+          }
+          fs::remove_dir_all(staging_dir).await?;
+          return Ok(Some(report));
+        }
+      }
+    }
+    self.move_staged_crate().await?;
+    events::message(format!(
+      "Moved generated crate into `{}`",
+      target_dir.to_string_lossy()
+    ));
+    Ok(None)
+  }
+  /// Move the staged crate into the target dir
+  ///
+  /// An existing target dir is kept and filled, as it may be the current dir of a process, this one
+  /// included. A crate staged in the system temp dir may be on another filesystem, so it is copied.
+  async fn move_staged_crate(&self) -> Result<(), CrateScaffoldingError> {
+    let Self {
+      target_dir,
+      staging_dir,
+      is_staged_in_temp_dir,
+      ..
+    } = self;
+    if *is_staged_in_temp_dir {
+      copy_dir(staging_dir, target_dir).await?;
+      fs::remove_dir_all(staging_dir).await?;
+    } else if fs::is_dir(target_dir).await {
+      for staged_path in fs::read_dir(staging_dir).await? {
+        let file_name = staged_path.file_name().unwrap_or_default();
+        fs::rename(&staged_path, target_dir.join(file_name)).await?;
+      }
+      fs::remove_dir(staging_dir).await?;
+    } else {
+      fs::rename(staging_dir, target_dir).await?;
+    }
+    Ok(())
+  }
+  /// Swap the staged crate in for the crate in the target dir
+  ///
+  /// The old crate is renamed aside and the staged one renamed into place, so that an interruption
+  /// leaves either one whole. The old crate is removed afterwards.
+  async fn swap_staged_crate(&self) -> Result<(), CrateScaffoldingError> {
+    let Self {
+      target_dir,
+      staging_dir,
+      ..
+    } = self;
+    let replaced_dir = Self::get_sibling_dir(target_dir, "replaced");
+    if fs::is_dir(&replaced_dir).await {
+      fs::remove_dir_all(&replaced_dir).await?;
+    }
+    fs::rename(target_dir, &replaced_dir).await?;
+    if let Err(e) = fs::rename(staging_dir, target_dir).await {
+      fs::rename(&replaced_dir, target_dir).await?;
+      return Err(e.into());
+    }
+    // this process would be left in the removed dir if it ran in the old crate
+    if env::current_dir().is_ok_and(|dir| dir == replaced_dir) {
+      env::set_current_dir(target_dir)?;
+    }
+    fs::remove_dir_all(&replaced_dir).await?;
+    Ok(())
+  }
+  /// Clean up the staging dir unless it should be kept, and restore the workspace root files
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn rollback(self) -> Result<(), CrateScaffoldingError> {
+    let Self {
+      staging_dir,
+      keep_failed,
+      workspace_snapshot_opt,
+      ..
+    } = &self;
+    if let Some(workspace_snapshot) = workspace_snapshot_opt {
+      workspace_snapshot.restore().await?;
+    }
+    if *keep_failed {
+      events::warning(format!(
+        "Kept the failed generation at `{}`",
+        staging_dir.to_string_lossy()
+      ));
+    } else if fs::is_dir(staging_dir).await {
+      fs::remove_dir_all(staging_dir).await?;
+      events::warning(format!(
+        "Removed the failed generation at `{}`",
+        staging_dir.to_string_lossy()
+      ));
+    }
+    Ok(())
+  }
+}
+
+/// Remove everything in a dir, but not the dir itself
+async fn clear_dir(dir_path: &Path) -> Result<(), CrateScaffoldingError> {
+  for path in fs::read_dir(dir_path).await? {
+    if fs::is_dir(&path).await {
+      fs::remove_dir_all(&path).await?;
+    } else {
+      fs::remove_file(&path).await?;
+    }
+  }
+  Ok(())
+}
+
+/// Copy a dir and everything in it, keeping the permissions of files
+async fn copy_dir(
+  from: &Path,
+  to: &Path,
+) -> Result<(), CrateScaffoldingError> {
+  let mut dirs = vec![(from.to_path_buf(), to.to_path_buf())];
+  while let Some((from_dir, to_dir)) = dirs.pop() {
+    fs::create_dir_all(&to_dir).await?;
+    for from_path in fs::read_dir(&from_dir).await? {
+      let to_path = to_dir.join(from_path.file_name().unwrap_or_default());
+      if fs::is_dir(&from_path).await {
+        dirs.push((from_path, to_path));
+      } else {
+        fs::copy(&from_path, &to_path).await?;
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Make sure a dir that is about to be deleted without asking is inside of the system temp dir
+fn ensure_in_temp_dir(dir_path: &Path) -> Result<(), CrateScaffoldingError> {
+  let canonicalize = |p: &Path| std::fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+  let temp_root_dir = canonicalize(&get_temp_root_dir());
+  let dir_path_in_parent = match (dir_path.parent(), dir_path.file_name()) {
+    (Some(parent), Some(file_name)) => canonicalize(parent).join(file_name),
+    _ => canonicalize(dir_path),
+  };
+  if dir_path_in_parent.starts_with(&temp_root_dir) && dir_path_in_parent != temp_root_dir {
+    Ok(())
+  } else {
+    Err(CrateScaffoldingError::RefuseToDeleteOutsideTemp(
+      dir_path.to_path_buf(),
+    ))
+  }
 }
 
 /// Create the test generation folder
 async fn create_testing_folder(cli: &Cli) -> Result<(), CrateScaffoldingError> {
   let temp_dir_path = &cli.get_output_project_dir();
-  if temp_dir_path.is_dir() {
+  if fs::is_dir(temp_dir_path).await {
+    ensure_in_temp_dir(temp_dir_path)?;
     fs::remove_dir_all(&temp_dir_path).await?;
   }
   fs::create_dir_all(&temp_dir_path).await?;
@@ -54,7 +493,7 @@ async fn create_testing_folder(cli: &Cli) -> Result<(), CrateScaffoldingError> {
 async fn create_crate_folder_and_check_empty(cli: &Cli) -> Result<(), CrateScaffoldingError> {
   let dir_path = &cli.get_output_project_dir();
   fs::create_dir_all(dir_path).await?;
-  if fs::read_dir(dir_path).await?.next_entry().await?.is_some() {
+  if !fs::read_dir(dir_path).await?.is_empty() {
     Err(CrateScaffoldingError::NonEmptyTargetDir(dir_path.clone()))
   } else {
     Ok(())
@@ -65,66 +504,42 @@ async fn create_crate_folder_and_check_empty(cli: &Cli) -> Result<(), CrateScaff
 async fn init_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
   let dir_path = &cli.get_output_project_dir();
   // dbg!(cli);
-  async { Ok(dir_path.is_dir()) }
+  async { Ok(fs::is_dir(dir_path).await) }
     .and_then(|is_dir| async move {
       if !is_dir {
         Err(CrateScaffoldingError::MissingCrateDir(dir_path.clone()))
       } else {
         let dir_path_string = dir_path.to_string_lossy().to_string();
-        process::Command::new("cargo")
-          .args(&[
-            "init".to_string(),
-            "--lib".to_string(),
-            "--color".to_string(),
-            "always".to_string(),
-            dir_path_string.to_string(),
-          ])
-          .output()
-          .await
-          .map_err(CrateScaffoldingError::from)
-          .and_then(|output| {
-            if output.status.success() {
-              let success_string = String::from_utf8(output.stdout).unwrap_or_default();
-              println!("Initialized crate at `{dir_path_string}` with output  {success_string}");
-              Ok(())
-            } else {
-              let e = CrateScaffoldingError::CargoInitFailed {
-                crate_dir: dir_path.clone(),
-                error_string: format!("{output:#?}"),
-              };
-              eprintln!("{e:?}");
-              Err(e)
-            }
-          })
+        let args = vec![
+          "init".to_string(),
+          "--lib".to_string(),
+          // the staging dir name is not a usable package name
+          "--name".to_string(),
+          cli.get_lib_name(),
+          dir_path_string.to_string(),
+        ];
+        run_cargo_job(
+          &args,
+          Option::<&str>::None,
+          Some(format!("Initializing crate at `{dir_path_string}`")),
+        )
+        .await
+        .map_err(|e| match e {
+          ProcessError::Failure(failure) => CrateScaffoldingError::CargoInitFailed {
+            crate_dir: dir_path.clone(),
+            failure,
+          },
+          e => e.into(),
+        })?;
+        events::message(format!("Initialized crate at `{dir_path_string}`"));
+        Ok(())
       }
     })
     .await
 }
 
-/// Attempt to install cargo make
-pub async fn install_cargo_make() -> Result<(), CrateScaffoldingError> {
-  run_cargo_job(
-    &["install", "--force", "cargo-make"],
-    Option::<&str>::None,
-    None,
-  )
-  .await
-  .map_err(CrateScaffoldingError::from)
-  .and_then(|output| {
-    if output.status.success() {
-      println!("Installed cargo make");
-      Ok(())
-    } else {
-      let e = CrateScaffoldingError::CargoMakeInstallFailed {
-        error_string: format!("{output:#?}"),
-      };
-      eprintln!("{e:?}");
-      Err(e)
-    }
-  })
-}
-
 /// Do all crate scaffolding jobs
+#[tracing::instrument(level = "debug", skip_all)]
 pub async fn scaffold_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
   let is_test = if let Some(SubCommands::TestGeneration { .. }) = cli.inner_cli.command.as_ref() {
     create_testing_folder(cli).await?;
@@ -134,6 +549,7 @@ pub async fn scaffold_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
     false
   };
   init_crate(cli).await?;
+  join_workspace(cli).await?;
   setup_tree_in_crate(cli).await?;
   setup_git_in_crate(cli).await?;
   if is_test {
@@ -142,6 +558,22 @@ pub async fn scaffold_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
   Ok(())
 }
 
+/// List the crate in the workspace members, so that cargo builds it as a member
+///
+/// Recent versions of `cargo init` list it themselves.
+async fn join_workspace(cli: &Cli) -> Result<(), CrateScaffoldingError> {
+  let Some(workspace_dir) = cli.workspace_dir_opt.as_ref() else {
+    return Ok(());
+  };
+  let mut manifest = WorkspaceManifest::read(workspace_dir).await?;
+  let member_path = workspaces::get_member_path(workspace_dir, &cli.get_output_project_dir())?;
+  if !manifest.has_member(&member_path) {
+    manifest.add_member(&member_path);
+    manifest.write().await?;
+  }
+  Ok(())
+}
+
 /// Setup file trees in crate
 async fn setup_tree_in_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
   // let crate_dir_path = cli.get_output_project_dir();
@@ -157,6 +589,131 @@ async fn setup_git_in_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
     .get_str("path")
     .expect("must get temp dir path");
   let gitignore_path = cli.get_output_project_subpath(&Paths::GitignoreFile);
-  fs::write(&gitignore_path, &format!("\n/{crate_temp_dir_str}")).await?;
+  let mut gitignore = format!("\n/{crate_temp_dir_str}");
+  // the xtask crate is a workspace of its own, so builds into its own target dir
+  if cli.task_runner == TaskRunners::Xtask {
+    gitignore.push_str("\n/xtask/target");
+  }
+  fs::write(&gitignore_path, gitignore, Some(".gitignore")).await?;
   Ok(())
 }
+
+#[cfg(test)]
+mod test_crate_scaffolds {
+  use super::*;
+  use crate::{
+    builder::GeneratorBuilder, generate::utils::get_temp_subdir, process::RecordingProcessRunner,
+    testing,
+  };
+  use std::sync::Arc;
+  use url::Url;
+
+  /// Get an empty dir on disk for a test
+  async fn get_test_dir(test_name: &str) -> PathBuf {
+    let dir_path = get_temp_subdir().join(format!("{test_name}-{}", std::process::id()));
+    if fs::is_dir(&dir_path).await {
+      fs::remove_dir_all(&dir_path)
+        .await
+        .expect("must clear test dir");
+    }
+    fs::create_dir_all(&dir_path)
+      .await
+      .expect("must create test dir");
+    dir_path
+  }
+
+  /// Generate into a dir on disk without running subprocesses
+  async fn generate(
+    spec_path: &Path,
+    output_dir: &Path,
+    output_dir_mode: OutputDirModes,
+  ) {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file(spec_path)
+      .output_dir(output_dir)
+      .autogenerate(false)
+      .output_dir_mode(output_dir_mode)
+      .build()
+      .expect("must build request")
+      .with_process_runner(Arc::new(RecordingProcessRunner::default()))
+      .run()
+      .await
+      .expect("must generate");
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn force_swaps_the_generated_crate_into_the_output_dir() {
+    let test_dir = get_test_dir("force-swap").await;
+    let (spec_path, output_dir) = (test_dir.join("spec.yaml"), test_dir.join("out"));
+    fs::write(&spec_path, testing::PETSTORE_YAML, None::<&str>)
+      .await
+      .expect("must write spec");
+    fs::create_dir_all(&output_dir)
+      .await
+      .expect("must create output dir");
+    fs::write(output_dir.join("old.txt"), "old", None::<&str>)
+      .await
+      .expect("must write old file");
+    generate(&spec_path, &output_dir, OutputDirModes::Force).await;
+    assert!(!fs::is_file(output_dir.join("old.txt")).await);
+    assert!(fs::is_file(output_dir.join("Makefile.toml")).await);
+    let left_over_names = fs::read_dir(&test_dir)
+      .await
+      .expect("must list test dir")
+      .iter()
+      .map(|path| {
+        path
+          .file_name()
+          .unwrap_or_default()
+          .to_string_lossy()
+          .to_string()
+      })
+      .filter(|name| !name.ends_with(".tar.gz"))
+      .collect::<Vec<_>>();
+    assert_eq!(left_over_names.len(), 2, "{left_over_names:?}");
+    fs::remove_dir_all(&test_dir).await.expect("must clean up");
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn archive_keeps_modes_empty_dirs_and_symlinks() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    let test_dir = get_test_dir("archive-round-trip").await;
+    let crate_dir = test_dir.join("crate");
+    let script_path = crate_dir.join("bin/openapitools");
+    fs::create_dir_all(crate_dir.join("bin"))
+      .await
+      .expect("must create bin dir");
+    fs::create_dir_all(crate_dir.join("empty"))
+      .await
+      .expect("must create empty dir");
+    fs::write(&script_path, "#!/bin/sh\n", None::<&str>)
+      .await
+      .expect("must write script");
+    std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755))
+      .expect("must make script executable");
+    symlink("bin/openapitools", crate_dir.join("openapitools")).expect("must link script");
+    let archive_path = ScaffoldTransaction::archive_dir(&crate_dir)
+      .await
+      .expect("must archive");
+    let unpacked_dir = test_dir.join("unpacked");
+    let archive_file = std::fs::File::open(&archive_path).expect("must open archive");
+    tar::Archive::new(flate2::read::GzDecoder::new(archive_file))
+      .unpack(&unpacked_dir)
+      .expect("must unpack");
+    let unpacked_crate_dir = unpacked_dir.join("crate");
+    let script_mode = std::fs::metadata(unpacked_crate_dir.join("bin/openapitools"))
+      .expect("must stat script")
+      .permissions()
+      .mode();
+    assert_eq!(script_mode & 0o777, 0o755);
+    assert!(fs::is_dir(unpacked_crate_dir.join("empty")).await);
+    assert_eq!(
+      std::fs::read_link(unpacked_crate_dir.join("openapitools")).expect("must read link"),
+      Path::new("bin/openapitools")
+    );
+    fs::remove_dir_all(&test_dir).await.expect("must clean up");
+  }
+}
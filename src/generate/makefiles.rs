@@ -1,40 +1,59 @@
 //! Makefile tasks
+//!
+//! The tasks of a generated crate are modeled as [TaskSpec]s, independent of the task runner. The
+//! cargo-make `Makefile.toml` is written here, and the other [TaskRunners] in [task_runners]. An
+//! existing `Makefile.toml` can be read back into a [MakefileSpec], after the [migrations] upgraded
+//! what users added to it. What users add to the makefile, in it or as a [MakefileExtension], is merged into the
+//! generated tasks.
 use crate::{
   cli::{Cli, InnerCli, Paths, SubCommands},
-  fs,
-  generate::{errors::*, utils::*, *},
+  events, fs,
+  generate::{errors::*, task_runners::TaskRunners, utils::*, *},
   vv,
 };
 use cargo_make::types::*;
 use cli as cargo_make;
-use futures::TryFutureExt;
 use once_cell::sync::Lazy;
-use proc_macro2::Span;
 // use quote::quote;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Error as SerdeYAMLError;
-use std::{collections::HashMap, io::Error as IOError};
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  io::{Error as IOError, ErrorKind},
+  path::{Path, PathBuf},
+};
 use strum::EnumProperty;
-use syn::Ident;
 use thiserror::Error;
-use toml::ser::Error as TomlSerError;
+use toml::{de::Error as TomlDeError, ser::Error as TomlSerError, Table, Value};
 
 /// The makefile specification
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MakefileSpec {
-  env: MakefileEnv,
-  tasks: HashMap<TaskNames, Task>,
+  pub env: MakefileEnv,
+  pub tasks: BTreeMap<TaskNames, TaskSpec>,
+  /// Env entries users added to the makefile themselves, as they wrote them
+  #[serde(default)]
+  pub user_env: BTreeMap<String, Value>,
+  /// Tasks users added to the makefile themselves, as they wrote them
+  #[serde(default)]
+  pub user_tasks: BTreeMap<String, Value>,
+  /// Which of the user entries were merged in from a [MakefileExtension], as `env.<name>` or
+  /// `tasks.<name>`. They get merged in anew from the extensions whenever the makefile is written
+  #[serde(default)]
+  pub extension_entries: BTreeSet<String>,
 }
 impl TryFrom<&Cli> for MakefileSpec {
   type Error = MakefileGenerationError;
   fn try_from(cli: &Cli) -> Result<Self, Self::Error> {
-    MakefileEnv::try_from(cli).and_then(|env| {
+    MakefileEnv::try_from(cli).map(|env| {
       let mut named_tasks = vec![
         NamedTask::make_cargo_fix_task(),
         NamedTask::make_crate_scaffold_task(),
-        NamedTask::make_generate_all_task(cli)?,
+        NamedTask::make_generate_all_task(cli),
         NamedTask::make_lib_code_generator_task(None),
         NamedTask::make_lib_code_generator_task(Some(true)),
+        NamedTask::make_olg_install_task(cli),
+        NamedTask::make_olg_self_update_task(cli),
         NamedTask::make_openapi_cli_check_task(),
         NamedTask::make_openapi_cli_install_task(),
         NamedTask::make_output_dir_clean_task(),
@@ -45,33 +64,355 @@ impl TryFrom<&Cli> for MakefileSpec {
       if cli.inner_cli.api_spec_url_opt.is_some() {
         named_tasks.push(NamedTask::make_spec_download_default_task());
       }
-      Ok(Self {
+      Self {
         env,
-        tasks: HashMap::from_iter(
+        tasks: BTreeMap::from_iter(
           named_tasks
             .drain(0..)
             .map(|NamedTask { name, task }| (name, task)),
         ),
-      })
+        user_env: BTreeMap::new(),
+        user_tasks: BTreeMap::new(),
+        extension_entries: BTreeSet::new(),
+      }
     })
   }
 }
 impl MakefileSpec {
-  /// Write makefile to makefile
-  pub async fn write_to_makefile(
+  /// Read a cargo-make `Makefile.toml`, upgrading what users added to it first if an older version
+  /// of this crate wrote it
+  ///
+  /// The generated tasks are read as they were written, whatever version wrote them.
+  pub fn from_makefile(contents: &str) -> Result<Self, MakefileGenerationError> {
+    /// The layout cargo-make reads
+    #[derive(Deserialize)]
+    struct CargoMakefile {
+      env: Table,
+      #[serde(default)]
+      tasks: BTreeMap<String, Value>,
+    }
+    let mut makefile: Table = toml::from_str(contents)?;
+    migrations::migrate_makefile(&mut makefile)?;
+    let CargoMakefile { mut env, tasks } = Value::Table(makefile).try_into()?;
+    let extension_entries = match env.remove(MakefileEnv::EXTENSION_ENTRIES_KEY) {
+      Some(Value::Array(entries)) => entries
+        .iter()
+        .filter_map(|entry| entry.as_str().map(ToString::to_string))
+        .collect(),
+      _ => BTreeSet::new(),
+    };
+    let makefile_env: MakefileEnv = Value::Table(env.clone()).try_into()?;
+    let env_names = makefile_env.get_names()?;
+    let mut spec = Self {
+      env: makefile_env,
+      tasks: BTreeMap::new(),
+      user_env: env
+        .into_iter()
+        .filter(|(name, _)| !env_names.contains(name))
+        .collect(),
+      user_tasks: BTreeMap::new(),
+      extension_entries,
+    };
+    for (name, value) in tasks {
+      match TaskNames::from_name(&name) {
+        Some(task_name) => {
+          let task: Task = value.try_into()?;
+          spec
+            .tasks
+            .insert(task_name, TaskSpec::from_cargo_make_task(&task));
+        }
+        None => {
+          spec.user_tasks.insert(name, value);
+        }
+      }
+    }
+    Ok(spec)
+  }
+  /// Merge in what users added to the makefile
+  ///
+  /// The entries users added to the `Makefile.toml` the crate already has are kept, then the ones of
+  /// the `--extra-tasks` file and of the `Makefile.local.toml` of the crate are merged in, the later
+  /// ones replacing the earlier ones of the same name. The entries an earlier write merged in from
+  /// the extensions are not kept, so that the ones removed from an extension go away.
+  pub async fn merge_user_additions(
+    mut self,
+    cli: &Cli,
+  ) -> Result<Self, MakefileGenerationError> {
+    let crate_dir = cli.get_final_output_project_dir();
+    let local_extension_path = crate_dir.join(MakefileEnv::MAKEFILE_EXTENSION_NAME);
+    if cli.task_runner != TaskRunners::CargoMake {
+      if cli.extra_tasks_filepath_opt.is_some() || fs::is_file(&local_extension_path).await {
+        events::warning(format!(
+          "Extra tasks are only merged into a cargo-make {}, not into the tasks of {}",
+          MakefileEnv::MAKEFILE_NAME,
+          cli.task_runner.as_ref()
+        ));
+      }
+      return Ok(self);
+    }
+    if let Some(contents) = read_to_string_opt(&crate_dir.join(MakefileEnv::MAKEFILE_NAME)).await? {
+      let Self {
+        mut user_env,
+        mut user_tasks,
+        extension_entries,
+        ..
+      } = Self::from_makefile(&contents)?;
+      user_env.retain(|name, _| !extension_entries.contains(&get_extension_entry("env", name)));
+      user_tasks.retain(|name, _| !extension_entries.contains(&get_extension_entry("tasks", name)));
+      self.user_env = user_env;
+      self.user_tasks = user_tasks;
+    }
+    if let Some(path) = cli.extra_tasks_filepath_opt.as_ref() {
+      let extension = MakefileExtension::read_opt(path)
+        .await?
+        .ok_or_else(|| MakefileGenerationError::MissingExtension(path.clone()))?;
+      self.merge_extension(extension, path)?;
+    }
+    if let Some(extension) = MakefileExtension::read_opt(&local_extension_path).await? {
+      self.merge_extension(extension, &local_extension_path)?;
+    }
+    Ok(self)
+  }
+  /// Merge in the entries of an extension, which must not have the names of generated ones
+  pub fn merge_extension(
+    &mut self,
+    extension: MakefileExtension,
+    path: &Path,
+  ) -> Result<(), MakefileGenerationError> {
+    let env_names = self.env.get_names()?;
+    for (name, value) in extension.env {
+      if env_names.contains(&name) {
+        return Err(MakefileGenerationError::EnvNameConflict {
+          name,
+          path: path.to_path_buf(),
+        });
+      }
+      self
+        .extension_entries
+        .insert(get_extension_entry("env", &name));
+      self.user_env.insert(name, value);
+    }
+    for (name, value) in extension.tasks {
+      if TaskNames::from_name(&name).is_some() {
+        return Err(MakefileGenerationError::TaskNameConflict {
+          name,
+          path: path.to_path_buf(),
+        });
+      }
+      self
+        .extension_entries
+        .insert(get_extension_entry("tasks", &name));
+      self.user_tasks.insert(name, value);
+    }
+    Ok(())
+  }
+  /// Write the tasks for the task runner of the crate
+  #[tracing::instrument(level = "debug", skip_all, fields(task_runner = cli.task_runner.as_ref()))]
+  pub async fn write_task_files(
     &self,
     cli: &Cli,
   ) -> Result<(), MakefileGenerationError> {
-    async { toml::to_string_pretty(self).map_err(MakefileGenerationError::from) }
-      .and_then(|toml_string| async {
-        let output_dir_path = cli.get_output_project_dir();
-        let output_file_name = MakefileEnv::MAKEFILE_NAME;
-        let output_file_path = output_dir_path.join(output_file_name);
-        fs::write(&output_file_path, toml_string, Some("Makefile")).await?;
-        // println!("Wrote makefile to `{output_file_path:?}`");
-        Ok(())
+    let output_dir_path = cli.get_output_project_dir();
+    for (file_name, contents) in self.render(cli)? {
+      let output_file_path = output_dir_path.join(file_name);
+      if let Some(parent_dir) = output_file_path.parent() {
+        fs::create_dir_all(parent_dir).await?;
+      }
+      fs::write(&output_file_path, contents, Some("Tasks")).await?;
+    }
+    Ok(())
+  }
+  /// Render the task files of the task runner of the crate, by name relative to the crate
+  ///
+  /// The tasks get [validated](validations) before they are returned.
+  pub fn render(
+    &self,
+    cli: &Cli,
+  ) -> Result<Vec<(String, String)>, MakefileGenerationError> {
+    let mut problems = validations::check_spec(self)?;
+    let task_files = match cli.task_runner {
+      TaskRunners::CargoMake => {
+        let makefile = self.to_makefile()?;
+        problems.extend(validations::check_makefile(&makefile));
+        vec![(MakefileEnv::MAKEFILE_NAME.to_string(), makefile)]
+      }
+      TaskRunners::Just => vec![(
+        task_runners::JUSTFILE_NAME.to_string(),
+        task_runners::render_justfile(self, cli)?,
+      )],
+      TaskRunners::Xtask => task_runners::render_xtask(self, cli)?,
+    };
+    validations::ensure_valid(problems)?;
+    Ok(task_files)
+  }
+  /// Render the cargo-make `Makefile.toml`
+  pub fn to_makefile(&self) -> Result<String, MakefileGenerationError> {
+    /// The layout cargo-make reads
+    #[derive(Serialize)]
+    struct CargoMakefile<'a> {
+      env: CargoMakefileEnv<'a>,
+      tasks: CargoMakefileTasks<'a>,
+    }
+    /// The generated env, then the env of users and which of it came from extensions
+    #[derive(Serialize)]
+    struct CargoMakefileEnv<'a> {
+      #[serde(flatten)]
+      env: &'a MakefileEnv,
+      #[serde(flatten)]
+      user_env: &'a BTreeMap<String, Value>,
+      #[serde(flatten)]
+      extension_entries: BTreeMap<&'static str, &'a BTreeSet<String>>,
+    }
+    /// The generated tasks, then the tasks of users
+    #[derive(Serialize)]
+    struct CargoMakefileTasks<'a> {
+      #[serde(flatten)]
+      tasks: BTreeMap<TaskNames, Task>,
+      #[serde(flatten)]
+      user_tasks: &'a BTreeMap<String, Value>,
+    }
+    let makefile = CargoMakefile {
+      env: CargoMakefileEnv {
+        env: &self.env,
+        user_env: &self.user_env,
+        extension_entries: BTreeMap::from_iter(
+          (!self.extension_entries.is_empty())
+            .then_some((MakefileEnv::EXTENSION_ENTRIES_KEY, &self.extension_entries)),
+        ),
+      },
+      tasks: CargoMakefileTasks {
+        tasks: self
+          .tasks
+          .iter()
+          .map(|(name, task)| (*name, task.to_cargo_make_task()))
+          .collect(),
+        user_tasks: &self.user_tasks,
+      },
+    };
+    Ok(toml::to_string_pretty(&makefile)?)
+  }
+}
+
+/// Get how an entry merged in from an extension is listed, by the section it is in and its name
+fn get_extension_entry(
+  section: &str,
+  name: &str,
+) -> String {
+  format!("{section}.{name}")
+}
+
+/// Read a file, if it exists
+async fn read_to_string_opt(path: &Path) -> Result<Option<String>, IOError> {
+  match fs::read_to_string(path).await {
+    Ok(contents) => Ok(Some(contents)),
+    Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+    Err(e) => Err(e),
+  }
+}
+
+/// Extra `[env]` and `[tasks]` entries of users for the makefile, in the cargo-make layout
+///
+/// They are read from the `Makefile.local.toml` of a crate, and from the file passed with
+/// `--extra-tasks`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MakefileExtension {
+  #[serde(default)]
+  pub env: BTreeMap<String, Value>,
+  #[serde(default)]
+  pub tasks: BTreeMap<String, Value>,
+}
+impl MakefileExtension {
+  /// Read an extension, if there is one at the path
+  pub async fn read_opt(path: &Path) -> Result<Option<Self>, MakefileGenerationError> {
+    let Some(contents) = read_to_string_opt(path).await? else {
+      return Ok(None);
+    };
+    toml::from_str(&contents).map(Some).map_err(|source| {
+      MakefileGenerationError::InvalidExtension {
+        path: path.to_path_buf(),
+        source,
+      }
+    })
+  }
+}
+
+/// What a task does once its dependencies ran
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskActions {
+  /// Run a program. `${NAME}` in the program or args stands for an env value, and an arg of `${@}`
+  /// for the args the task was run with
+  Command { program: String, args: Vec<String> },
+  /// Run a bash script
+  Script(Vec<String>),
+}
+
+/// A task, whichever task runner it is written for
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TaskSpec {
+  pub description: String,
+  /// Tasks to run first, in order
+  pub dependencies: Vec<TaskNames>,
+  /// A bash script that skips the task if it fails
+  pub condition_script_opt: Option<Vec<String>>,
+  pub action_opt: Option<TaskActions>,
+}
+impl TaskSpec {
+  /// Make the cargo-make task
+  pub fn to_cargo_make_task(&self) -> Task {
+    let mut task = Task {
+      category: NamedTask::default_category(),
+      description: Some(self.description.clone()),
+      condition_script: self.condition_script_opt.clone(),
+      ..Default::default()
+    };
+    if !self.dependencies.is_empty() {
+      task.dependencies = Some(
+        self
+          .dependencies
+          .iter()
+          .map(|name| DependencyIdentifier::Name(name.as_ref().to_string()))
+          .collect(),
+      );
+    }
+    match self.action_opt.as_ref() {
+      Some(TaskActions::Command { program, args }) => {
+        task.command = Some(program.clone());
+        task.args = (!args.is_empty()).then(|| args.clone());
+      }
+      Some(TaskActions::Script(lines)) => task.script = Some(ScriptValue::Text(lines.clone())),
+      None => {}
+    }
+    task
+  }
+  /// Make a task from a cargo-make task
+  ///
+  /// What a [TaskSpec] cannot express, like a script runner, is left out.
+  pub fn from_cargo_make_task(task: &Task) -> Self {
+    let dependencies = task
+      .dependencies
+      .iter()
+      .flatten()
+      .filter_map(|dependency| match dependency {
+        DependencyIdentifier::Name(name) => TaskNames::from_name(name),
+        DependencyIdentifier::Definition(TaskIdentifier { name, .. }) => TaskNames::from_name(name),
       })
-      .await
+      .collect();
+    let action_opt = match (&task.command, &task.script) {
+      (Some(program), _) => Some(TaskActions::Command {
+        program: program.clone(),
+        args: task.args.clone().unwrap_or_default(),
+      }),
+      (None, Some(ScriptValue::Text(lines))) => Some(TaskActions::Script(lines.clone())),
+      (None, Some(ScriptValue::SingleLine(line))) => Some(TaskActions::Script(vec![line.clone()])),
+      _ => None,
+    };
+    Self {
+      description: task.description.clone().unwrap_or_default(),
+      dependencies,
+      condition_script_opt: task.condition_script.clone(),
+      action_opt,
+    }
   }
 }
 
@@ -80,23 +421,41 @@ impl MakefileSpec {
 pub enum MakefileGenerationError {
   #[error("Env missing key {0}")]
   EnvMissingKey(String),
+  #[error("The env entry `{name}` of `{path}` has the name of a generated one")]
+  EnvNameConflict { name: String, path: PathBuf },
+  #[error("The task `{name}` of `{path}` has the name of a built-in task")]
+  TaskNameConflict { name: String, path: PathBuf },
+  #[error("`{path}` is not a valid makefile extension: {source}")]
+  InvalidExtension { path: PathBuf, source: TomlDeError },
+  #[error("No makefile extension at `{0}`")]
+  MissingExtension(PathBuf),
   #[error(transparent)]
   IOError(#[from] IOError),
   #[error(transparent)]
   CargoConfigError(#[from] CargoConfigError),
   #[error(transparent)]
+  ChangelogGenerationError(#[from] ChangelogGenerationError),
+  #[error(transparent)]
   ParameterError(#[from] ParameterError),
   #[error(transparent)]
   READMEGenerationError(#[from] READMEGenerationError),
   #[error(transparent)]
   SerdeYAMLError(#[from] SerdeYAMLError),
   #[error(transparent)]
+  MakefileMigrationError(#[from] MakefileMigrationError),
+  #[error(transparent)]
+  MakefileValidationError(#[from] MakefileValidationError),
+  #[error(transparent)]
+  TomlDeError(#[from] TomlDeError),
+  #[error(transparent)]
   TomlSerError(#[from] TomlSerError),
 }
 /// Makefile env
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub struct MakefileEnv {
+  /// The version of the layout of the makefile, see [migrations]
+  pub makefile_schema_version: EnvValue,
   pub api_url: EnvValue,
   pub api_name: EnvValue,
   pub lib_name: EnvValue,
@@ -125,13 +484,14 @@ impl TryFrom<&Cli> for MakefileEnv {
     } = &cli.inner_cli;
     let lib_name = cli.get_lib_name();
     let spec_file_name = cli.try_get_spec_file_name()?;
-    let output_project_dir_string = cli.get_output_project_dir_string();
+    let output_project_dir_string = cli.get_final_output_project_dir_string();
     let output_project_temp_dir_string = Paths::TempDir.get_str("path").expect("must get temp dir");
     let api_spec_url_string = api_spec_url_opt
       .as_ref()
       .map(|api_url| api_url.to_string())
       .unwrap_or_default();
     Ok(Self {
+      makefile_schema_version: EnvValue::Value(Self::SCHEMA_VERSION.to_string()),
       api_url: EnvValue::Value(api_url.to_string()),
       api_name: EnvValue::Value(site_or_api_name.to_string()),
       lib_name: EnvValue::Value(lib_name.to_string()),
@@ -167,6 +527,16 @@ impl TryFrom<&Cli> for MakefileEnv {
   }
 }
 impl MakefileEnv {
+  /// Get the names of the env entries
+  pub fn get_names(&self) -> Result<BTreeSet<String>, MakefileGenerationError> {
+    match Value::try_from(self)? {
+      Value::Table(table) => Ok(table.into_iter().map(|(name, _)| name).collect()),
+      _ => Err(MakefileGenerationError::EnvMissingKey("env".to_string())),
+    }
+  }
+  /// The version of the layout of the makefiles this crate writes. Bump it along with a new
+  /// migration whenever the layout changes
+  pub const SCHEMA_VERSION: u32 = 3;
   /// Default config file name for OpenAPI Generator
   pub const OPEN_API_GENERATOR_CONFIG_FILE: &'static str = "generator_config.yaml";
   /// Default download url for OpenAPI Generator CLI artifact
@@ -177,19 +547,25 @@ impl MakefileEnv {
   pub const OPEN_API_GENERATOR_CLI_SCRIPT: &'static str = "openapi-generator-cli";
   /// Default Makefile name
   pub const MAKEFILE_NAME: &'static str = "Makefile.toml";
+  /// The name of the file of a crate with extra makefile entries of users
+  pub const MAKEFILE_EXTENSION_NAME: &'static str = "Makefile.local.toml";
+  /// The env key that lists which entries of the makefile were merged in from extensions
+  pub const EXTENSION_ENTRIES_KEY: &'static str = "MAKEFILE_EXTENSION_ENTRIES";
   /// Default Makefile task category
+  #[allow(clippy::declare_interior_mutable_const)]
   pub const DEFAULT_TASK_CATEGORY: Lazy<String> =
     Lazy::new(|| format!("{} {}", get_this_crate_name(), get_this_crate_ver_pretty()));
 }
 
-/// A named [Task] specification
+/// A named [TaskSpec]
 #[derive(Deserialize, Serialize)]
 pub struct NamedTask {
   pub name: TaskNames,
-  pub task: Task,
+  pub task: TaskSpec,
 }
 impl NamedTask {
   /// Code generator options
+  #[allow(clippy::declare_interior_mutable_const)]
   const CODE_GENERATION_OPTS: Lazy<Vec<String>> = Lazy::new(|| {
     vv![strings
       "generate",
@@ -202,6 +578,7 @@ impl NamedTask {
   });
 
   /// Get default category
+  #[allow(clippy::borrow_interior_mutable_const)]
   pub fn default_category() -> Option<String> {
     Some(MakefileEnv::DEFAULT_TASK_CATEGORY.to_string())
   }
@@ -210,21 +587,22 @@ impl NamedTask {
   pub fn make_cargo_fix_task() -> Self {
     Self {
       name: TaskNames::CargoFixGenerated,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(r#"Fix ${LIB_NAME} project generated code'."#.to_string()),
-        command: Some("cargo".to_string()),
-        args: Some(vv![strings
-          "fix",
-          "--broken-code",
-          "--edition",
-          "--edition-idioms",
-          "--allow-dirty",
-          "--all-targets",
-          "--all-features",
-          "--verbose",
-          "--verbose",
-        ]),
+      task: TaskSpec {
+        description: r#"Fix ${LIB_NAME} project generated code'."#.to_string(),
+        action_opt: Some(TaskActions::Command {
+          program: "cargo".to_string(),
+          args: vv![strings
+            "fix",
+            "--broken-code",
+            "--edition",
+            "--edition-idioms",
+            "--allow-dirty",
+            "--all-targets",
+            "--all-features",
+            "--verbose",
+            "--verbose",
+          ],
+        }),
         ..Default::default()
       },
     }
@@ -233,116 +611,164 @@ impl NamedTask {
   pub fn make_crate_scaffold_task() -> Self {
     Self {
       name: TaskNames::CrateScaffold,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(r#"Setup ${LIB_NAME} project'."#.to_string()),
-        dependencies: Some(vv![ as_ref dep_names
-          TaskNames::OutputDirCreate,
-          TaskNames::OutputDirClean,
-        ]),
+      task: TaskSpec {
+        description: r#"Setup ${LIB_NAME} project'."#.to_string(),
+        dependencies: vec![TaskNames::OutputDirCreate, TaskNames::OutputDirClean],
         ..Default::default()
       },
     }
   }
 
   /// Makes a task that does all of the generation steps
-  pub fn make_generate_all_task(cli: &Cli) -> Result<Self, MakefileGenerationError> {
-    // dbg!(cli);
-    let name = TaskNames::GenerateAll;
-    let cargo_configurator = cargos::CargoConfigurator::new(cli)?;
-    let cargo_configurator_yaml = serde_yaml::to_string(&cargo_configurator)?;
-    let readme_generator = readmes::READMEGenerator::new(cli)?;
-    let readme_generator_yaml = serde_yaml::to_string(&readme_generator)?;
-    let this_crate_name = cargo_configurator.this_crate_name.to_string();
-    let this_crate_ident = Ident::new(&this_crate_name, Span::call_site());
-    let this_crate_ver = cargo_configurator.this_crate_ver.to_string();
-    // let cargo_toml_path  = Paths::CargoTomlFile.get_str("path").expect("must get cargo toml path");
-    let default_crate_dependency_string =
-      format!("{this_crate_name} = {{ version = \"{this_crate_ver}\" }}");
-    let this_crate_dependency_string = if let Some(SubCommands::TestGeneration {
-      generator_crate_local_path_opt: generator_path_opt,
-      generator_crate_repo_url_opt: generator_repo_opt,
-      ..
-    }) = &cli.inner_cli.command
-    {
-      match generator_path_opt {
-        Some(generator_path) => {
-          let this_crate_path_str = generator_path.to_string_lossy();
-          format!(
-            "{this_crate_name} = \
-            {{ version = \"{this_crate_ver}\", \
-            path = \"{this_crate_path_str}\" }} "
-          )
+  ///
+  /// The post-generation updates run with `olg post-generate`.
+  pub fn make_generate_all_task(cli: &Cli) -> Self {
+    Self {
+      name: TaskNames::GenerateAll,
+      task: TaskSpec {
+        description: "Generate ${LIB_NAME} code and try to get it up to par".to_string(),
+        dependencies: vec![
+          TaskNames::LibCodeGenerate,
+          TaskNames::CargoFixGenerated,
+          TaskNames::OlgInstall,
+        ],
+        action_opt: Some(Self::make_olg_action(cli, &["post-generate"])),
+        ..Default::default()
+      },
+    }
+  }
+
+  /// Makes a task that moves the crate to the newest published version of this generator
+  pub fn make_olg_self_update_task(cli: &Cli) -> Self {
+    Self {
+      name: TaskNames::OlgSelfUpdate,
+      task: TaskSpec {
+        description: format!(
+          "Update {} to its newest version and regenerate the tasks with it. Pass `--index` to \
+           look versions up elsewhere than crates.io",
+          get_this_crate_name()
+        ),
+        dependencies: vec![TaskNames::OlgInstall],
+        action_opt: Some(Self::make_olg_action(cli, &["self-update", "${@}"])),
+        ..Default::default()
+      },
+    }
+  }
+
+  /// Get the dir the tasks install `olg` in, unless they run a local copy of the generator crate
+  ///
+  /// A crate runs the version of `olg` its generator dev-dependency is pinned to, which is the
+  /// version that wrote its tasks. `olg self-update` installs a newer version in the same layout.
+  fn get_olg_install_root_opt(cli: &Cli) -> Option<String> {
+    match &cli.inner_cli.command {
+      Some(SubCommands::TestGeneration {
+        generator_crate_local_path_opt: Some(_),
+        ..
+      }) => None,
+      Some(SubCommands::TestGeneration {
+        generator_crate_repo_url_opt: Some(_),
+        ..
+      }) => Some("${OUTPUT_TEMP_DIR}/olg-git".to_string()),
+      _ => Some(format!("${{OUTPUT_TEMP_DIR}}/olg-{}", get_this_crate_ver())),
+    }
+  }
+
+  /// Get the manifest of the local copy of the generator crate the tasks run, if any
+  fn get_generator_manifest_path_opt(cli: &Cli) -> Option<PathBuf> {
+    match &cli.inner_cli.command {
+      Some(SubCommands::TestGeneration {
+        generator_crate_local_path_opt: Some(generator_path),
+        ..
+      }) => Some(generator_path.join(Paths::CargoTomlFile.get_str("path").expect("must get path"))),
+      _ => None,
+    }
+  }
+
+  /// Makes a task that installs the `olg` the other tasks run
+  ///
+  /// The pinned version is installed once, a git repo whenever it changed, and a local copy of the
+  /// generator crate is built.
+  pub fn make_olg_install_task(cli: &Cli) -> Self {
+    let crate_name = get_this_crate_name();
+    let action = match &cli.inner_cli.command {
+      Some(SubCommands::TestGeneration {
+        generator_crate_local_path_opt: Some(_),
+        ..
+      }) => {
+        let manifest_path =
+          Self::get_generator_manifest_path_opt(cli).expect("must get generator manifest");
+        let mut args = vv![strings "build", "--quiet", "--manifest-path",];
+        args.push(manifest_path.to_string_lossy().to_string());
+        args.extend(vv![strings "--bin", "olg",]);
+        TaskActions::Command {
+          program: "cargo".to_string(),
+          args,
         }
-        None => match generator_repo_opt {
-          Some(generator_repo) => {
-            let this_crate_repo_str = generator_repo.as_str();
-            format!(
-              "{this_crate_name} = \
-                {{ version = \"{this_crate_ver}\", \
-                git = \"{this_crate_repo_str}\" }} "
-            )
-          }
-          None => default_crate_dependency_string,
-        },
       }
-    } else {
-      default_crate_dependency_string
+      Some(SubCommands::TestGeneration {
+        generator_crate_repo_url_opt: Some(repo_url),
+        ..
+      }) => TaskActions::Command {
+        program: "cargo".to_string(),
+        args: vv![strings
+          "install", "--quiet", "--git", repo_url, crate_name,
+          "--root", "${OUTPUT_TEMP_DIR}/olg-git", "--bin", "olg",
+        ],
+      },
+      _ => {
+        let install_root = Self::get_olg_install_root_opt(cli).expect("must get install root");
+        TaskActions::Script(trim_lines_vec(&format!(
+          r#"
+          #!/bin/bash
+          # install the version of the generator the crate is pinned to, once
+          if [ ! -x "{install_root}/bin/olg" ]; then
+            cargo install --quiet {crate_name} --version ={} --root "{install_root}" --bin olg
+          fi
+          "#,
+          get_this_crate_ver()
+        )))
+      }
     };
-    let mut script_lines = trim_lines_vec(&format!(
-      r##"
-      //! ```cargo
-      //! [dependencies]
-      //! {this_crate_dependency_string}
-      //! serde_yaml = {{ version = "0.9.19" }}
-      //! tokio = {{ version = "1.26.0", features = ["full"] }}
-      //! ```
-    "##
-    ));
-    let mut script_body = trim_lines_vec(&format! {r###"
-      use {this_crate_ident}::{{ 
-        cli::CLIError, 
-        generate::{{
-          cargos::CargoConfigurator,
-          readmes::READMEGenerator 
-        }} 
-      }};
-      mod yaml_specs {{
-        pub const CARGO_CONFIGURATOR_YAML: &'static str = r#"{cargo_configurator_yaml}"#;
-        pub const README_GENERATOR_YAML: &'static str = r#"{readme_generator_yaml}"#;
-      }}
-      #[tokio::main]
-      async fn main() -> Result<(), CLIError> {{
-        let cargo_configurator: CargoConfigurator = serde_yaml::from_str(yaml_specs::CARGO_CONFIGURATOR_YAML)?;
-        cargo_configurator.update_cargo_manifest_post_generation().await?;
-        cargo_configurator.update_cargo_manifest_post_fix_edition().await?;
-        let readme_generator: READMEGenerator = serde_yaml::from_str(yaml_specs::README_GENERATOR_YAML)?;
-        readme_generator.update_readme_md_file().await?;
-        println!("updates complete");
-        Ok(())
-      }}
-    "###});
-    script_lines.extend(script_body.drain(0..));
-    Ok(Self {
-      name,
-      task: Task {
-        category: Self::default_category(),
-        description: Some("Generate ${LIB_NAME} code and try to get it up to par".to_string()),
-        dependencies: Some(vv![as_ref dep_names
-          TaskNames::LibCodeGenerate,
-          TaskNames::CargoFixGenerated,
-        ]),
-        script_runner: Some("@rust".to_string()),
-        script: Some(ScriptValue::Text(script_lines)),
-        // run_task: Some(RunTaskInfo::Routing(vec![
-        //   Self::make_named_run_task_routing_info("cargo-fix-generated", None),
-        // ])),
+    Self {
+      name: TaskNames::OlgInstall,
+      task: TaskSpec {
+        description: format!("Install the {crate_name} the tasks run"),
+        action_opt: Some(action),
         ..Default::default()
       },
-    })
+    }
+  }
+
+  /// Makes an action that runs the `olg` installed by the [TaskNames::OlgInstall] task, or the
+  /// generator crate itself when testing a local copy of it
+  fn make_olg_action(
+    cli: &Cli,
+    olg_args: &[&str],
+  ) -> TaskActions {
+    let olg_args = olg_args.iter().map(ToString::to_string);
+    match Self::get_generator_manifest_path_opt(cli) {
+      Some(manifest_path) => {
+        let mut args = vv![strings "run", "--quiet", "--manifest-path",];
+        args.push(manifest_path.to_string_lossy().to_string());
+        args.extend(vv![strings "--bin", "olg", "--",]);
+        args.extend(olg_args);
+        TaskActions::Command {
+          program: "cargo".to_string(),
+          args,
+        }
+      }
+      None => TaskActions::Command {
+        program: format!(
+          "{}/bin/olg",
+          Self::get_olg_install_root_opt(cli).expect("must get install root")
+        ),
+        args: olg_args.collect(),
+      },
+    }
   }
 
   /// Makes a task that generates the code lib from the openapi spec
+  #[allow(clippy::borrow_interior_mutable_const)]
   pub fn make_lib_code_generator_task(is_dry_run: Option<bool>) -> Self {
     let mut args = Self::CODE_GENERATION_OPTS.clone();
     let mut name = TaskNames::LibCodeGenerate;
@@ -352,25 +778,26 @@ impl NamedTask {
     }
     Self {
       name,
-      task: Task {
-        category: Self::default_category(),
-        description: Some("Generate ${LIB_NAME} code".to_string()),
-        condition_script: Some(trim_lines_vec(
+      task: TaskSpec {
+        description: "Generate ${LIB_NAME} code".to_string(),
+        dependencies: vec![],
+        condition_script_opt: Some(trim_lines_vec(
           r#"
           #!/bin/bash
           # check if openapi cli command exists
-          if command -v ${OPEN_API_GENERATOR_CLI_SCRIPT} >& /dev/null ;  then
+          if command -v ${OPEN_API_GENERATOR_CLI_SCRIPT} > /dev/null 2>&1 ;  then
             echo "Found OpenAPI CLI command."
             exit 0
           else 
-            echo "Missing OpenAPI CLI command. Try running `cargo make openapi-cli-bash-install`"
+            echo 'Missing OpenAPI CLI command. Try running `cargo make openapi-cli-bash-install`'
             exit 1
           fi
           "#,
         )),
-        command: Some("${OPEN_API_GENERATOR_CLI_SCRIPT}".to_string()),
-        args: Some(args),
-        ..Default::default()
+        action_opt: Some(TaskActions::Command {
+          program: "${OPEN_API_GENERATOR_CLI_SCRIPT}".to_string(),
+          args,
+        }),
       },
     }
   }
@@ -395,11 +822,12 @@ impl NamedTask {
   pub fn make_openapi_cli_check_task() -> Self {
     Self {
       name: TaskNames::OpenapiCliCheck,
-      task: Task {
-        category: Self::default_category(),
-        description: Some("Check that openapi cli generator tool is installed".to_string()),
-        command: Some("command".to_string()),
-        args: Some(vv![strings "-v", "${OPEN_API_GENERATOR_CLI_SCRIPT}",]),
+      task: TaskSpec {
+        description: "Check that openapi cli generator tool is installed".to_string(),
+        action_opt: Some(TaskActions::Command {
+          program: "command".to_string(),
+          args: vv![strings "-v", "${OPEN_API_GENERATOR_CLI_SCRIPT}",],
+        }),
         ..Default::default()
       },
     }
@@ -409,10 +837,9 @@ impl NamedTask {
   pub fn make_openapi_cli_install_task() -> Self {
     Self {
       name: TaskNames::OpenapiCliBashInstall,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(r#"Install Open API generator CLI'."#.to_string()),
-        script: Some(ScriptValue::Text(trim_lines_vec(
+      task: TaskSpec {
+        description: r#"Install Open API generator CLI'."#.to_string(),
+        action_opt: Some(TaskActions::Script(trim_lines_vec(
           r#"
           #!/bin/bash
           # enable the downloaded cli artifact file 
@@ -442,8 +869,8 @@ impl NamedTask {
           {
               chmod u+x $CLI_PATH
               line_to_add="export PATH=\$PATH:$CLI_SUBDIR/"
-              if ! grep -q "$line_to_add" "${PROFILE_FILE}" ; then 
-                  echo "Adding \"$line_to_add\" to ${PROFILE_FILE}."
+              if ! grep -q "$line_to_add" "$PROFILE_FILE" ; then 
+                  echo "Adding \"$line_to_add\" to $PROFILE_FILE."
                   echo "\# OpenAPI Generator CLI" >> $PROFILE_FILE
                   echo "$line_to_add" >> $PROFILE_FILE
               else 
@@ -495,11 +922,12 @@ impl NamedTask {
   pub fn make_output_dir_clean_task() -> Self {
     Self {
       name: TaskNames::OutputDirClean,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(r#"Setup ${LIB_NAME} output dir at ${OUTPUT_DIR}'."#.to_string()),
-        command: Some("rm".to_string()),
-        args: Some(vv![strings "-rf", "${OUTPUT_DIR}/*", ]),
+      task: TaskSpec {
+        description: r#"Setup ${LIB_NAME} output dir at ${OUTPUT_DIR}'."#.to_string(),
+        action_opt: Some(TaskActions::Command {
+          program: "rm".to_string(),
+          args: vv![strings "-rf", "${OUTPUT_DIR}/*", ],
+        }),
         ..Default::default()
       },
     }
@@ -509,11 +937,12 @@ impl NamedTask {
   pub fn make_output_dir_create_task() -> Self {
     Self {
       name: TaskNames::OutputDirCreate,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(r#"Create ${LIB_NAME} output dir at ${OUTPUT_DIR}'."#.to_string()),
-        command: Some("mkdir".to_string()),
-        // args: Some(vv![strings  "-p", "${OUTPUT_DIR}",  ]),
+      task: TaskSpec {
+        description: r#"Create ${LIB_NAME} output dir at ${OUTPUT_DIR}'."#.to_string(),
+        action_opt: Some(TaskActions::Command {
+          program: "mkdir".to_string(),
+          args: vv![strings "-p", "${OUTPUT_DIR}",],
+        }),
         ..Default::default()
       },
     }
@@ -523,13 +952,13 @@ impl NamedTask {
   pub fn make_spec_download_default_task() -> Self {
     Self {
       name: TaskNames::SpecDownloadDefault,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(
-          r#"Downloads ${API_NAME} Open API specification from '${API_URL}'."#.to_string(),
-        ),
-        command: Some("wget".to_string()),
-        args: Some(vv![ strings "${SPEC_FILE_URL}", "-O", "${SPEC_FILE_PATH}", ]),
+      task: TaskSpec {
+        description: r#"Downloads ${API_NAME} Open API specification from '${API_URL}'."#
+          .to_string(),
+        action_opt: Some(TaskActions::Command {
+          program: "wget".to_string(),
+          args: vv![ strings "${SPEC_FILE_URL}", "-O", "${SPEC_FILE_PATH}", ],
+        }),
         ..Default::default()
       },
     }
@@ -538,13 +967,13 @@ impl NamedTask {
   pub fn make_spec_download_task() -> Self {
     Self {
       name: TaskNames::SpecDownload,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(
-          r#"Downloads ${API_NAME} Open API specification from specified vararg'."#.to_string(),
-        ),
-        command: Some("wget".to_string()),
-        args: Some(vv![ strings "${@}", "-O", "${SPEC_FILE_PATH}", ]),
+      task: TaskSpec {
+        description: r#"Downloads ${API_NAME} Open API specification from specified vararg'."#
+          .to_string(),
+        action_opt: Some(TaskActions::Command {
+          program: "wget".to_string(),
+          args: vv![ strings "${@}", "-O", "${SPEC_FILE_PATH}", ],
+        }),
         ..Default::default()
       },
     }
@@ -552,14 +981,15 @@ impl NamedTask {
   /// Makes a task that validates the spec
   pub fn make_spec_validate_task() -> Self {
     let name = TaskNames::SpecValidate;
-    let description = Some(format!("{name}"));
+    let description = format!("{name}");
     Self {
       name,
-      task: Task {
-        category: Self::default_category(),
+      task: TaskSpec {
         description,
-        command: Some("${OPEN_API_GENERATOR_CLI_SCRIPT}".to_string()),
-        args: Some(vv![strings "validate", "--input-spec", "${SPEC_FILE_PATH}", "--recommend",]),
+        action_opt: Some(TaskActions::Command {
+          program: "${OPEN_API_GENERATOR_CLI_SCRIPT}".to_string(),
+          args: vv![strings "validate", "--input-spec", "${SPEC_FILE_PATH}", "--recommend",],
+        }),
         ..Default::default()
       },
     }
@@ -594,6 +1024,10 @@ pub enum TaskNames {
   LibCodeGenerate,
   #[error("A task that does a DRY RUN of generating the code lib from the openapi spec")]
   LibCodeGenerateDryRun,
+  #[error("A task that installs the generator the tasks run")]
+  OlgInstall,
+  #[error("A task that updates the generator of the crate")]
+  OlgSelfUpdate,
   #[error("A task that checks openapi-generator cli artifact")]
   OpenapiCliCheck,
   #[error("A task that installs openapi-generator cli artifact")]
@@ -609,3 +1043,146 @@ pub enum TaskNames {
   #[error("A task that validates the spec")]
   SpecValidate,
 }
+impl TaskNames {
+  /// Get the task of a name, if it is one of these
+  pub fn from_name(name: &str) -> Option<Self> {
+    Value::String(name.to_string()).try_into().ok()
+  }
+}
+
+#[cfg(test)]
+mod test_makefiles {
+  use super::*;
+  use crate::{
+    builder::GeneratorBuilder,
+    fs::{FileSystem, MemoryFileSystem},
+    testing,
+  };
+  use std::sync::Arc;
+  use url::Url;
+
+  /// Merge the `Makefile.local.toml` of a crate into its generated makefile, and write it
+  async fn merge_local_extension(
+    file_system: Arc<MemoryFileSystem>,
+    extension: &str,
+  ) -> Result<MakefileSpec, MakefileGenerationError> {
+    let cli = testing::get_test_cli();
+    fs::with_file_system(file_system, async {
+      fs::create_dir_all(cli.get_output_project_dir()).await?;
+      fs::write(
+        cli
+          .get_output_project_dir()
+          .join(MakefileEnv::MAKEFILE_EXTENSION_NAME),
+        extension,
+        Some("Makefile extension"),
+      )
+      .await?;
+      let spec = MakefileSpec::try_from(&cli)?
+        .merge_user_additions(&cli)
+        .await?;
+      spec.write_task_files(&cli).await?;
+      Ok(spec)
+    })
+    .await
+  }
+
+  #[tokio::test]
+  async fn merges_the_extension_of_a_crate() {
+    let spec = merge_local_extension(
+      Arc::new(MemoryFileSystem::new()),
+      r#"
+      [env]
+      REGISTRY = "internal"
+
+      [tasks.publish]
+      command = "cargo"
+      args = ["publish", "--registry", "${REGISTRY}"]
+      dependencies = ["generate-all"]
+      "#,
+    )
+    .await
+    .expect("must merge extension");
+    let makefile = spec.to_makefile().expect("must render makefile");
+    let read_back = MakefileSpec::from_makefile(&makefile).expect("must read makefile");
+    assert_eq!(read_back.user_env.keys().collect::<Vec<_>>(), ["REGISTRY"]);
+    assert_eq!(read_back.user_tasks.keys().collect::<Vec<_>>(), ["publish"]);
+  }
+
+  #[tokio::test]
+  async fn drops_the_entries_removed_from_the_extension() {
+    let file_system = Arc::new(MemoryFileSystem::new());
+    merge_local_extension(
+      file_system.clone(),
+      "[env]\nREGISTRY = \"internal\"\n[tasks.publish]\ncommand = \"true\"\n",
+    )
+    .await
+    .expect("must merge extension");
+    let makefile_path = Path::new("/virtual/petstore").join(MakefileEnv::MAKEFILE_NAME);
+    let makefile = file_system
+      .read(&makefile_path)
+      .await
+      .expect("must read makefile");
+    let makefile = String::from_utf8(makefile).expect("must be utf-8");
+    file_system
+      .write(
+        &makefile_path,
+        format!("{makefile}\n[tasks.lint]\ncommand = \"true\"\n").as_bytes(),
+      )
+      .await
+      .expect("must add a task to the makefile");
+    let spec = merge_local_extension(file_system, "[tasks.docs]\ncommand = \"true\"\n")
+      .await
+      .expect("must merge extension");
+    assert!(spec.user_env.is_empty());
+    assert_eq!(spec.user_tasks.keys().collect::<Vec<_>>(), ["docs", "lint"]);
+    assert_eq!(
+      spec.extension_entries.iter().collect::<Vec<_>>(),
+      ["tasks.docs"]
+    );
+  }
+
+  #[tokio::test]
+  async fn refuses_extensions_with_built_in_names() {
+    let task_conflict = merge_local_extension(
+      Arc::new(MemoryFileSystem::new()),
+      "[tasks.generate-all]\ncommand = \"true\"\n",
+    )
+    .await;
+    assert!(matches!(
+      task_conflict,
+      Err(MakefileGenerationError::TaskNameConflict { name, .. }) if name == "generate-all"
+    ));
+    let env_conflict = merge_local_extension(
+      Arc::new(MemoryFileSystem::new()),
+      "[env]\nLIB_NAME = \"other\"\n",
+    )
+    .await;
+    assert!(matches!(
+      env_conflict,
+      Err(MakefileGenerationError::EnvNameConflict { name, .. }) if name == "LIB_NAME"
+    ));
+  }
+
+  #[test]
+  fn tasks_install_olg_from_the_repo_under_test() {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let repo_url = Url::parse("file:///work/olg").expect("must parse repo url");
+    let cli = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .test_generation(None, Some(repo_url))
+      .build()
+      .expect("must build request")
+      .get_cli()
+      .clone();
+    let spec = MakefileSpec::try_from(&cli).expect("must make makefile");
+    assert!(matches!(
+      &spec.tasks[&TaskNames::OlgInstall].action_opt,
+      Some(TaskActions::Command { program, args })
+        if program == "cargo" && args[..4] == ["install", "--quiet", "--git", "file:///work/olg"]
+    ));
+    assert!(matches!(
+      &spec.tasks[&TaskNames::GenerateAll].action_opt,
+      Some(TaskActions::Command { program, args })
+        if program == "${OUTPUT_TEMP_DIR}/olg-git/bin/olg" && args == &["post-generate"]
+    ));
+  }
+}
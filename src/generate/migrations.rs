@@ -0,0 +1,232 @@
+//! Makefile migrations
+//!
+//! A generated `Makefile.toml` records the version of its layout as [SCHEMA_VERSION_KEY] in its env.
+//! Makefiles without one predate the versioning and are at version 1. A makefile gets upgraded by
+//! running the [MIGRATIONS] from its version on, each of which moves it one version up.
+//!
+//! Only the env entries and tasks users added themselves are kept when the tasks get written again,
+//! the generated ones are written anew from the settings. So the migrations only upgrade what users
+//! added, and leave the generated entries as the older version wrote them.
+use crate::{
+  events,
+  generate::makefiles::{MakefileEnv, TaskNames},
+};
+use thiserror::Error;
+use toml::{Table, Value};
+
+/// The env key of the schema version of a makefile
+pub const SCHEMA_VERSION_KEY: &str = "MAKEFILE_SCHEMA_VERSION";
+
+/// A migration of a makefile from a schema version to the next
+pub type Migration = fn(&mut Table);
+
+/// The migrations by the schema version they upgrade from, starting at version 1
+pub const MIGRATIONS: &[Migration] = &[migrate_from_v1, migrate_from_v2];
+
+/// Makefile migration errors
+#[derive(Debug, Error)]
+pub enum MakefileMigrationError {
+  #[error("Makefile schema version `{0}` is not a number")]
+  InvalidSchemaVersion(String),
+  #[error(
+    "Makefile schema version {found} is newer than the version {current} this generator writes"
+  )]
+  NewerSchemaVersion { found: u32, current: u32 },
+}
+
+/// Get the schema version of a makefile
+pub fn get_schema_version(makefile: &Table) -> Result<u32, MakefileMigrationError> {
+  match makefile
+    .get("env")
+    .and_then(|env| env.get(SCHEMA_VERSION_KEY))
+  {
+    None => Ok(1),
+    Some(Value::String(version)) => version
+      .parse()
+      .map_err(|_| MakefileMigrationError::InvalidSchemaVersion(version.clone())),
+    Some(value) => Err(MakefileMigrationError::InvalidSchemaVersion(
+      value.to_string(),
+    )),
+  }
+}
+
+/// Upgrade a makefile to the current schema version
+///
+/// Returns the version the makefile was at.
+pub fn migrate_makefile(makefile: &mut Table) -> Result<u32, MakefileMigrationError> {
+  let version = get_schema_version(makefile)?;
+  let current = MakefileEnv::SCHEMA_VERSION;
+  if version > current {
+    return Err(MakefileMigrationError::NewerSchemaVersion {
+      found: version,
+      current,
+    });
+  }
+  for migration in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+    migration(makefile);
+  }
+  if version < current {
+    events::message(format!(
+      "Upgraded {} from schema version {version} to {current}",
+      MakefileEnv::MAKEFILE_NAME
+    ));
+  }
+  Ok(version)
+}
+
+/// Stamp the schema version into a makefile
+fn set_schema_version(
+  makefile: &mut Table,
+  version: u32,
+) {
+  if let Value::Table(env) = makefile
+    .entry("env")
+    .or_insert_with(|| Value::Table(Table::new()))
+  {
+    env.insert(
+      SCHEMA_VERSION_KEY.to_string(),
+      Value::String(version.to_string()),
+    );
+  }
+}
+
+/// Rename a task users added that has the name of a task a newer version generates, along with
+/// the dependencies of the other tasks on it, whether by name or `{ name = "..." }`
+fn rename_user_task(
+  makefile: &mut Table,
+  name: TaskNames,
+) {
+  let Some(Value::Table(tasks)) = makefile.get_mut("tasks") else {
+    return;
+  };
+  let Some(task) = tasks.remove(name.as_ref()) else {
+    return;
+  };
+  let new_name = format!("{}-user", name.as_ref());
+  for (_, other_task) in tasks.iter_mut() {
+    if let Some(Value::Array(dependencies)) = other_task.get_mut("dependencies") {
+      for dependency in dependencies.iter_mut() {
+        match dependency {
+          Value::String(dependency_name) if dependency_name == name.as_ref() => {
+            *dependency_name = new_name.clone();
+          }
+          // a dependency with a path is on a task of another makefile
+          Value::Table(definition)
+            if definition.get("name").and_then(Value::as_str) == Some(name.as_ref())
+              && !definition.contains_key("path") =>
+          {
+            definition.insert("name".to_string(), Value::String(new_name.clone()));
+          }
+          _ => {}
+        }
+      }
+    }
+  }
+  events::warning(format!(
+    "Renamed the task `{}` of {} to `{new_name}`, now that a task of that name is generated",
+    name.as_ref(),
+    MakefileEnv::MAKEFILE_NAME
+  ));
+  tasks.insert(new_name, task);
+}
+
+/// Version 2 runs the post-generation updates with `olg post-generate` instead of an embedded
+/// rust-script, which only changed generated tasks
+fn migrate_from_v1(makefile: &mut Table) {
+  set_schema_version(makefile, 2);
+}
+
+/// Version 3 generates an `olg-install` task, so a task of that name users added is renamed
+fn migrate_from_v2(makefile: &mut Table) {
+  rename_user_task(makefile, TaskNames::OlgInstall);
+  set_schema_version(makefile, 3);
+}
+
+#[cfg(test)]
+mod test_migrations {
+  use super::*;
+  use crate::generate::makefiles::MakefileSpec;
+
+  /// A makefile of version 1, with a task a user added
+  const MAKEFILE_V1: &str = include_str!("../testing/fixtures/Makefile.v1.toml");
+
+  #[test]
+  fn has_a_migration_up_to_every_version() {
+    assert_eq!(MIGRATIONS.len() as u32 + 1, MakefileEnv::SCHEMA_VERSION);
+  }
+
+  #[test]
+  fn upgrades_an_old_makefile_and_keeps_user_tasks() {
+    let mut makefile: Table = toml::from_str(MAKEFILE_V1).expect("must parse makefile");
+    assert_eq!(
+      migrate_makefile(&mut makefile).expect("must get version"),
+      1
+    );
+    assert_eq!(
+      makefile
+        .get("env")
+        .and_then(|env| env.get(SCHEMA_VERSION_KEY)),
+      Some(&Value::String("3".to_string()))
+    );
+    assert_eq!(
+      makefile["tasks"]["docs"].get("dependencies"),
+      Some(&Value::Array(vec![Value::String(
+        "generate-all".to_string()
+      )]))
+    );
+    let spec = MakefileSpec::from_makefile(MAKEFILE_V1).expect("must read makefile");
+    assert!(spec.user_tasks.contains_key("docs"));
+  }
+
+  #[test]
+  fn renames_user_tasks_that_got_a_generated_name() {
+    let mut makefile: Table = toml::from_str(MAKEFILE_V1).expect("must parse makefile");
+    set_schema_version(&mut makefile, 2);
+    let user_tasks: Table = toml::from_str(
+      r#"
+      olg-install = { command = "cargo", args = ["install", "olg"] }
+      docs = { command = "cargo", args = ["doc"], dependencies = ["olg-install"] }
+      docs-open = { command = "open", dependencies = [{ name = "olg-install" }] }
+      docs-shared = { command = "open", dependencies = [{ name = "olg-install", path = "shared" }] }
+      "#,
+    )
+    .expect("must parse tasks");
+    if let Some(Value::Table(tasks)) = makefile.get_mut("tasks") {
+      tasks.extend(user_tasks);
+    }
+    let spec =
+      MakefileSpec::from_makefile(&toml::to_string(&makefile).expect("must write makefile"))
+        .expect("must read makefile");
+    assert!(spec.user_tasks.contains_key("olg-install-user"));
+    assert!(!spec.user_tasks.contains_key("olg-install"));
+    assert_eq!(
+      spec.user_tasks["docs"].get("dependencies"),
+      Some(&Value::Array(vec![Value::String(
+        "olg-install-user".to_string()
+      )]))
+    );
+    let get_dependency_name = |task: &str| {
+      spec.user_tasks[task]["dependencies"][0]["name"]
+        .as_str()
+        .map(ToString::to_string)
+    };
+    assert_eq!(
+      get_dependency_name("docs-open").as_deref(),
+      Some("olg-install-user")
+    );
+    assert_eq!(
+      get_dependency_name("docs-shared").as_deref(),
+      Some("olg-install")
+    );
+  }
+
+  #[test]
+  fn refuses_a_newer_makefile() {
+    let mut makefile: Table = toml::from_str(MAKEFILE_V1).expect("must parse makefile");
+    set_schema_version(&mut makefile, MakefileEnv::SCHEMA_VERSION + 1);
+    assert!(matches!(
+      migrate_makefile(&mut makefile),
+      Err(MakefileMigrationError::NewerSchemaVersion { .. })
+    ));
+  }
+}
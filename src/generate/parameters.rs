@@ -1,5 +1,9 @@
 //! Generator errors
-// use std::{path::{Path}};
+use crate::{cli::CLIError, fs, generate::utils::to_snake_case};
+use std::{
+  io::ErrorKind,
+  path::{Path, PathBuf},
+};
 
 use thiserror::Error;
 use url::Url;
@@ -15,6 +19,170 @@ pub enum ParameterError {
   APIUrlNeededIfNoLocalFile,
   #[error("Must provide a path to create the testing yaml spec")]
   TestingYAMLSpecPathMissing,
+  #[error("Spec file path has no file name {0}")]
+  SpecFileNeedsName(PathBuf),
+  #[error("Library name `{lib_name}` {problem}. Try `{suggestion}`")]
+  InvalidLibName {
+    lib_name: String,
+    problem: LibNameProblems,
+    suggestion: String,
+  },
+  #[error("Library name `{lib_name}` is taken by the crate `{crate_name}` on crates.io")]
+  LibNameTaken {
+    lib_name: String,
+    crate_name: String,
+  },
+  #[error("Not a crates.io index {0}")]
+  NotACratesIndex(PathBuf),
+}
+
+/// Why a library name cannot be used as is
+#[derive(Clone, Copy, Debug, Eq, Error, PartialEq)]
+pub enum LibNameProblems {
+  #[error("is empty")]
+  Empty,
+  #[error("has non-ASCII characters")]
+  NonAscii,
+  #[error("has characters other than letters, digits and underscores")]
+  InvalidCharacters,
+  #[error("does not start with a letter")]
+  NoLeadingLetter,
+  #[error("is longer than {MAX_LIB_NAME_LEN} characters")]
+  TooLong,
+  #[error("is not snake case")]
+  NotSnakeCase,
+  #[error("is a Rust keyword")]
+  Keyword,
+  #[error("is the name of a built-in crate")]
+  BuiltInCrate,
+}
+
+/// The longest crate name crates.io accepts
+pub const MAX_LIB_NAME_LEN: usize = 64;
+/// Strict and reserved keywords of every edition, which cargo refuses as package names
+const RUST_KEYWORDS: &[&str] = &[
+  "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do",
+  "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+  "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+  "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof", "unsafe",
+  "unsized", "use", "virtual", "where", "while", "yield",
+];
+/// Crates that ship with Rust, which cargo refuses as package names
+const BUILT_IN_CRATES: &[&str] = &["alloc", "core", "proc_macro", "std", "test"];
+
+/// Get what keeps a name from being both a valid crate name and a Rust identifier
+pub fn get_lib_name_problem(lib_name: &str) -> Option<LibNameProblems> {
+  let problem = if lib_name.is_empty() {
+    LibNameProblems::Empty
+  } else if !lib_name.is_ascii() {
+    LibNameProblems::NonAscii
+  } else if !lib_name
+    .chars()
+    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+  {
+    LibNameProblems::InvalidCharacters
+  } else if !lib_name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+    LibNameProblems::NoLeadingLetter
+  } else if lib_name.len() > MAX_LIB_NAME_LEN {
+    LibNameProblems::TooLong
+  } else if to_snake_case(lib_name) != lib_name {
+    LibNameProblems::NotSnakeCase
+  } else if RUST_KEYWORDS.contains(&lib_name) {
+    LibNameProblems::Keyword
+  } else if BUILT_IN_CRATES.contains(&lib_name) {
+    LibNameProblems::BuiltInCrate
+  } else {
+    return None;
+  };
+  Some(problem)
+}
+
+/// Turn any name into a snake case name that is both a valid crate name and a Rust identifier
+///
+/// Characters other than ASCII letters and digits separate words. A name without a leading letter
+/// gets a `lib_` prefix and a keyword or built-in crate name a `_lib` suffix.
+pub fn normalize_lib_name(name: &str) -> String {
+  let ascii_name = name
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect::<String>();
+  let mut lib_name = to_snake_case(&ascii_name);
+  if lib_name.is_empty() {
+    lib_name = "openapi_client".to_string();
+  }
+  if !lib_name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+    lib_name.insert_str(0, "lib_");
+  }
+  if RUST_KEYWORDS.contains(&lib_name.as_str()) || BUILT_IN_CRATES.contains(&lib_name.as_str()) {
+    lib_name.push_str("_lib");
+  }
+  lib_name.truncate(MAX_LIB_NAME_LEN);
+  lib_name.trim_end_matches('_').to_string()
+}
+
+/// Check that a library name can be used as is, suggesting one that can if not
+pub fn validate_lib_name(lib_name: &str) -> Result<(), ParameterError> {
+  match get_lib_name_problem(lib_name) {
+    Some(problem) => Err(ParameterError::InvalidLibName {
+      lib_name: lib_name.to_string(),
+      problem,
+      suggestion: normalize_lib_name(lib_name),
+    }),
+    None => Ok(()),
+  }
+}
+
+/// Get the dir of a crates.io index that lists a crate name, as cargo lays it out
+pub fn get_crates_index_subdir(crate_name: &str) -> PathBuf {
+  match crate_name.len() {
+    1 => PathBuf::from("1"),
+    2 => PathBuf::from("2"),
+    3 => PathBuf::from("3").join(&crate_name[..1]),
+    _ => PathBuf::from(&crate_name[..2]).join(&crate_name[2..4]),
+  }
+}
+
+/// Fail if a local crates.io index mirror has a crate named like the library
+///
+/// crates.io tells names apart regardless of case and of `-` versus `_`, so both spellings are looked up.
+#[tracing::instrument(level = "debug", skip_all, fields(%lib_name, index_dir = ?index_dir.as_ref()))]
+pub async fn check_lib_name_availability(
+  lib_name: &str,
+  index_dir: impl AsRef<Path>,
+) -> Result<(), CLIError> {
+  let index_dir = index_dir.as_ref();
+  if !fs::is_file(index_dir.join("config.json")).await {
+    return Err(ParameterError::NotACratesIndex(index_dir.to_path_buf()).into());
+  }
+  let canonical_name = lib_name.to_lowercase().replace('-', "_");
+  let mut subdirs = [
+    get_crates_index_subdir(&canonical_name),
+    get_crates_index_subdir(&canonical_name.replace('_', "-")),
+  ]
+  .to_vec();
+  subdirs.dedup();
+  for subdir in subdirs {
+    let crate_paths = match fs::read_dir(index_dir.join(subdir)).await {
+      Ok(crate_paths) => crate_paths,
+      Err(e) if e.kind() == ErrorKind::NotFound => continue,
+      Err(e) => return Err(e.into()),
+    };
+    let taken_name_opt = crate_paths
+      .iter()
+      .filter_map(|path| path.file_name())
+      .map(|file_name| file_name.to_string_lossy().to_string())
+      .find(|crate_name| crate_name.to_lowercase().replace('-', "_") == canonical_name);
+    if let Some(crate_name) = taken_name_opt {
+      return Err(
+        ParameterError::LibNameTaken {
+          lib_name: lib_name.to_string(),
+          crate_name,
+        }
+        .into(),
+      );
+    }
+  }
+  Ok(())
 }
 
 /// Get file name from path
@@ -22,10 +190,87 @@ pub fn try_file_name_from_path_url(path_url: &Url) -> Result<String, ParameterEr
   path_url
     .path_segments()
     .ok_or_else(|| ParameterError::APIPathNeedsSegments(path_url.clone()))
-    .and_then(|path_segments| {
+    .and_then(|mut path_segments| {
       path_segments
-        .last()
-        .ok_or_else(|| ParameterError::APIPathSegmentsNeedsLast)
+        .next_back()
+        .ok_or(ParameterError::APIPathSegmentsNeedsLast)
         .map(ToString::to_string)
     })
 }
+
+#[cfg(test)]
+mod test_parameters {
+  use super::*;
+  use crate::fs::MemoryFileSystem;
+  use std::sync::Arc;
+  #[test]
+  fn lib_names_are_normalized() {
+    let (long_name, truncated_name) = ("a".repeat(70), "a".repeat(MAX_LIB_NAME_LEN));
+    for (name, expected) in [
+      ("PetShoppe_openapi_client", "pet_shoppe_openapi_client"),
+      ("pet store-api", "pet_store_api"),
+      ("42 pets", "lib_42_pets"),
+      ("Café Menu", "caf_menu"),
+      ("日本", "openapi_client"),
+      ("type", "type_lib"),
+      ("std", "std_lib"),
+      (long_name.as_str(), truncated_name.as_str()),
+    ] {
+      let lib_name = normalize_lib_name(name);
+      assert_eq!(lib_name, expected);
+      assert_eq!(get_lib_name_problem(&lib_name), None, "{lib_name}");
+    }
+  }
+  #[test]
+  fn invalid_lib_names_suggest_a_correction() {
+    for (name, problem) in [
+      ("", LibNameProblems::Empty),
+      ("pet-store", LibNameProblems::InvalidCharacters),
+      ("1pets", LibNameProblems::NoLeadingLetter),
+      ("PetStore", LibNameProblems::NotSnakeCase),
+      ("fn", LibNameProblems::Keyword),
+      ("core", LibNameProblems::BuiltInCrate),
+    ] {
+      assert_eq!(get_lib_name_problem(name), Some(problem), "{name}");
+    }
+    assert_eq!(
+      validate_lib_name("pet-store").map_err(|e| e.to_string()),
+      Err(
+        "Library name `pet-store` has characters other than letters, digits and underscores. Try \
+         `pet_store`"
+          .to_string()
+      )
+    );
+  }
+  #[tokio::test]
+  async fn crates_index_lookup_ignores_case_and_dashes() {
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let index_dir = Path::new("/virtual/crates.io-index");
+    for path in ["config.json", "pe/t-/pet-store", "3/a/abc"] {
+      crate::fs::FileSystem::write(file_system.as_ref(), &index_dir.join(path), b"{}")
+        .await
+        .expect("must write index file");
+    }
+    let check = |lib_name: &'static str| {
+      fs::with_file_system(
+        file_system.clone(),
+        check_lib_name_availability(lib_name, index_dir),
+      )
+    };
+    assert!(matches!(
+      check("pet_store").await,
+      Err(CLIError::ParameterError(ParameterError::LibNameTaken { crate_name, .. }))
+        if crate_name == "pet-store"
+    ));
+    assert!(check("abc").await.is_err());
+    assert!(check("pet_shop").await.is_ok());
+    assert!(matches!(
+      fs::with_file_system(
+        file_system.clone(),
+        check_lib_name_availability("abc", "/virtual")
+      )
+      .await,
+      Err(CLIError::ParameterError(ParameterError::NotACratesIndex(_)))
+    ));
+  }
+}
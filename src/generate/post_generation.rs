@@ -0,0 +1,467 @@
+//! Post-generation updates of a crate
+//!
+//! After the OpenAPI generator and `cargo fix` ran, the manifest, README and changelog of the crate
+//! get brought up to par. What they need from the generation is recorded in the crate as a
+//! [PostGenerationConfig], which `olg post-generate` reads when the `generate-all` task runs it. The
+//! [GenerationSettings] are recorded along with it, so that `olg regenerate-tasks` can write the
+//! task files again, e.g. with a newer version of this crate. The configs older versions recorded
+//! lack them, so they are rebuilt from the env of the `Makefile.toml` of the crate.
+use crate::{
+  builder::GeneratorBuilder,
+  cli::{CLIError, Cli, Paths, SubCommands},
+  fs,
+  generate::{
+    cargos::{CargoConfigError, CargoConfigurator},
+    changelogs::{ChangelogGenerationError, ChangelogGenerator},
+    makefiles::{MakefileEnv, MakefileGenerationError, MakefileSpec},
+    parameters::ParameterError,
+    readmes::{READMEGenerationError, READMEGenerator},
+    task_graphs::TaskGraph,
+    task_runners::TaskRunners,
+    workspaces,
+  },
+};
+use cargo_make::types::EnvValue;
+use chrono::DateTime;
+use cli as cargo_make;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Error as SerdeYAMLError;
+use std::{
+  env,
+  io::{Error as IOError, ErrorKind},
+  path::{Path, PathBuf},
+};
+use strum::EnumProperty;
+use thiserror::Error;
+use url::Url;
+
+/// Errors
+#[derive(Debug, Error)]
+pub enum PostGenerationError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+  #[error(transparent)]
+  CargoConfigError(#[from] CargoConfigError),
+  #[error(transparent)]
+  ChangelogGenerationError(#[from] ChangelogGenerationError),
+  #[error(transparent)]
+  READMEGenerationError(#[from] READMEGenerationError),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
+  #[error("No post-generation config at `{0}`")]
+  MissingConfig(PathBuf),
+  #[error("The post-generation config has no settings to write the tasks with, and there is no `{0}` to get them from")]
+  MissingSettings(PathBuf),
+}
+
+/// The settings of a generation that hold wherever its crate is moved to
+///
+/// Paths are relative to the crate. How the generation was run, e.g. `--force` or `--dry-run`, is
+/// left out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GenerationSettings {
+  pub site_or_api_name: String,
+  pub api_url: Url,
+  pub api_spec_url_opt: Option<Url>,
+  /// The spec file the crate keeps
+  pub spec_file_name: String,
+  pub lib_name: String,
+  pub extra_authors: Option<String>,
+  pub task_runner: TaskRunners,
+  pub extra_tasks_filepath_opt: Option<PathBuf>,
+  pub workspace_dir_opt: Option<PathBuf>,
+  pub readme_task_graph: bool,
+}
+impl GenerationSettings {
+  /// Get the settings of a generation into a crate dir
+  pub fn new(
+    cli: &Cli,
+    crate_dir: &Path,
+  ) -> Result<Self, ParameterError> {
+    let get_relative_path = |path: &PathBuf| workspaces::get_path_relative_to(path, crate_dir);
+    Ok(Self {
+      site_or_api_name: cli.site_or_api_name.clone(),
+      api_url: cli.api_url.clone(),
+      api_spec_url_opt: cli.api_spec_url_opt.clone(),
+      spec_file_name: cli.try_get_spec_file_name()?,
+      lib_name: cli.get_lib_name(),
+      extra_authors: cli.extra_authors.clone(),
+      task_runner: cli.task_runner,
+      extra_tasks_filepath_opt: cli.extra_tasks_filepath_opt.as_ref().map(get_relative_path),
+      workspace_dir_opt: cli.workspace_dir_opt.as_ref().map(get_relative_path),
+      readme_task_graph: cli.readme_task_graph,
+    })
+  }
+  /// Get a builder of the generation as if it had been generated into a crate dir
+  pub fn get_builder(
+    &self,
+    crate_dir: &Path,
+  ) -> GeneratorBuilder {
+    let get_path = |path: &PathBuf| workspaces::normalize_path(&crate_dir.join(path));
+    let mut builder = GeneratorBuilder::new(&self.site_or_api_name, self.api_url.clone())
+      .spec_file(crate_dir.join(&self.spec_file_name))
+      .lib_name(&self.lib_name)
+      .output_dir(crate_dir)
+      .task_runner(self.task_runner)
+      .readme_task_graph(self.readme_task_graph);
+    if let Some(api_spec_url) = self.api_spec_url_opt.clone() {
+      builder = builder.spec_url(api_spec_url);
+    }
+    if let Some(extra_authors) = self.extra_authors.as_ref() {
+      builder = builder.extra_authors(&[extra_authors]);
+    }
+    if let Some(extra_tasks_filepath) = self.extra_tasks_filepath_opt.as_ref() {
+      builder = builder.extra_tasks(get_path(extra_tasks_filepath));
+    }
+    if let Some(workspace_dir) = self.workspace_dir_opt.as_ref() {
+      builder = builder.workspace(get_path(workspace_dir));
+    }
+    builder
+  }
+}
+
+/// What the post-generation updates need to know about the generation
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PostGenerationConfig {
+  /// The settings of the generation, if the config was recorded with them
+  #[serde(default)]
+  pub settings: Option<GenerationSettings>,
+  pub cargo_configurator: CargoConfigurator,
+  pub readme_generator: READMEGenerator,
+  pub changelog_generator: ChangelogGenerator,
+}
+impl PostGenerationConfig {
+  /// Instantiate for the generation of a crate with these tasks
+  pub fn new(
+    cli: &Cli,
+    makefile_spec: &MakefileSpec,
+  ) -> Result<Self, PostGenerationError> {
+    let settings = GenerationSettings::new(cli, &cli.get_final_output_project_dir())?;
+    let mut readme_generator = READMEGenerator::new(cli)?;
+    if cli.readme_task_graph {
+      readme_generator =
+        readme_generator.with_task_graph(TaskGraph::from(makefile_spec).to_mermaid());
+    }
+    Ok(Self {
+      settings: Some(settings),
+      cargo_configurator: CargoConfigurator::new(cli)?,
+      readme_generator,
+      changelog_generator: ChangelogGenerator::new(cli)?,
+    })
+  }
+  /// Get the path of the config, relative to the crate
+  fn get_relative_path() -> &'static str {
+    Paths::PostGenerationConfigFile
+      .get_str("path")
+      .expect("must get post-generation config path")
+  }
+  /// Record the config in the crate
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn write_to_yaml_file(
+    &self,
+    cli: &Cli,
+  ) -> Result<(), PostGenerationError> {
+    fs::write(
+      cli.get_output_project_subpath(&Paths::PostGenerationConfigFile),
+      serde_yaml::to_string(self)?,
+      Some("Post-generation config"),
+    )
+    .await?;
+    Ok(())
+  }
+  /// Read the config recorded in the crate of the current dir
+  pub async fn read_from_yaml_file() -> Result<Self, PostGenerationError> {
+    let path = Self::get_relative_path();
+    let contents = fs::read(path).await.map_err(|e| match e.kind() {
+      ErrorKind::NotFound => PostGenerationError::MissingConfig(PathBuf::from(path)),
+      _ => e.into(),
+    })?;
+    Ok(serde_yaml::from_slice(&contents)?)
+  }
+  /// Get the settings of the generation for the crate in a dir, wherever it was generated
+  pub async fn get_crate_cli(
+    &self,
+    crate_dir: &Path,
+  ) -> Result<Cli, CLIError> {
+    let settings = match self.settings.as_ref() {
+      Some(settings) => settings.clone(),
+      None => self.rebuild_settings(crate_dir).await?,
+    };
+    let mut builder = settings.get_builder(crate_dir);
+    if let Some(SubCommands::TestGeneration {
+      generator_crate_local_path_opt,
+      generator_crate_repo_url_opt,
+    }) = self.cargo_configurator.subcommand_opt.clone()
+    {
+      builder =
+        builder.test_generation(generator_crate_local_path_opt, generator_crate_repo_url_opt);
+    }
+    let mut cli = builder.build()?.get_cli().clone();
+    if let Ok(generation_timestamp) =
+      DateTime::parse_from_rfc3339(&self.cargo_configurator.generation_timestamp_string)
+    {
+      cli.generation_timestamp = generation_timestamp.into();
+    }
+    Ok(cli)
+  }
+  /// Rebuild the settings of the generation of the crate in a dir from the env of its makefile
+  ///
+  /// What the env does not have is left to the defaults.
+  async fn rebuild_settings(
+    &self,
+    crate_dir: &Path,
+  ) -> Result<GenerationSettings, CLIError> {
+    let makefile_path = crate_dir.join(MakefileEnv::MAKEFILE_NAME);
+    let contents = fs::read_to_string(&makefile_path)
+      .await
+      .map_err(|e| match e.kind() {
+        ErrorKind::NotFound => PostGenerationError::MissingSettings(makefile_path.clone()),
+        _ => e.into(),
+      })?;
+    let env = MakefileSpec::from_makefile(&contents)?.env;
+    let api_url = get_env_value("API_URL", &env.api_url)?;
+    let api_url = Url::parse(&api_url)
+      .map_err(|_| MakefileGenerationError::EnvMissingKey("API_URL".to_string()))?;
+    let spec_url = get_env_value("SPEC_FILE_URL", &env.spec_file_url)?;
+    Ok(GenerationSettings {
+      site_or_api_name: get_env_value("API_NAME", &env.api_name)?,
+      api_url,
+      api_spec_url_opt: Url::parse(&spec_url).ok(),
+      spec_file_name: get_env_value("SPEC_FILE_NAME", &env.spec_file_name)?,
+      lib_name: get_env_value("LIB_NAME", &env.lib_name)?,
+      extra_authors: None,
+      task_runner: TaskRunners::CargoMake,
+      extra_tasks_filepath_opt: None,
+      workspace_dir_opt: self
+        .cargo_configurator
+        .workspace_dir_opt
+        .as_ref()
+        .map(|workspace_dir| workspaces::get_path_relative_to(workspace_dir, crate_dir)),
+      readme_task_graph: false,
+    })
+  }
+  /// Update the manifest, README and changelog of the crate in the current dir
+  #[tracing::instrument(level = "debug", skip_all)]
+  pub async fn run(&self) -> Result<(), PostGenerationError> {
+    self
+      .cargo_configurator
+      .update_cargo_manifest_post_generation()
+      .await?;
+    self
+      .cargo_configurator
+      .update_cargo_manifest_post_fix_edition()
+      .await?;
+    self.readme_generator.update_readme_md_file().await?;
+    self.changelog_generator.update_changelog_md_file().await?;
+    self
+      .cargo_configurator
+      .hoist_dependencies_into_workspace()
+      .await?;
+    Ok(())
+  }
+}
+
+/// Get the value of an env entry of a makefile, which the generator writes as a plain string
+fn get_env_value(
+  name: &str,
+  env_value: &EnvValue,
+) -> Result<String, MakefileGenerationError> {
+  match env_value {
+    EnvValue::Value(value) => Ok(value.clone()),
+    _ => Err(MakefileGenerationError::EnvMissingKey(name.to_string())),
+  }
+}
+
+/// Run the post-generation updates of the crate in the current dir from its recorded config
+pub async fn run_post_generation() -> Result<(), PostGenerationError> {
+  PostGenerationConfig::read_from_yaml_file()
+    .await?
+    .run()
+    .await
+}
+
+/// Write the task files of the crate in the current dir again from its recorded settings, merging
+/// in what users added to its makefile, then record the config of this version of the generator
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn regenerate_task_files() -> Result<(), CLIError> {
+  let cli = read_crate_cli().await?;
+  let makefile_spec = MakefileSpec::try_from(&cli)?
+    .merge_user_additions(&cli)
+    .await?;
+  makefile_spec.write_task_files(&cli).await?;
+  PostGenerationConfig::new(&cli, &makefile_spec)?
+    .write_to_yaml_file(&cli)
+    .await?;
+  Ok(())
+}
+
+/// Get the settings the crate in the current dir was generated with
+pub async fn read_crate_cli() -> Result<Cli, CLIError> {
+  let crate_dir = env::current_dir()?;
+  PostGenerationConfig::read_from_yaml_file()
+    .await?
+    .get_crate_cli(&crate_dir)
+    .await
+}
+
+#[cfg(test)]
+mod test_post_generation {
+  use super::*;
+  use crate::{cli::OutputDirModes, fs::MemoryFileSystem, testing};
+  use std::{sync::Arc, time::Duration};
+
+  /// A config recorded by a version of this crate that did not record the settings
+  const CONFIG_WITHOUT_SETTINGS: &str =
+    include_str!("../testing/fixtures/post_generation.without_settings.yaml");
+  /// The makefile that version wrote along with it
+  const MAKEFILE_V1: &str = include_str!("../testing/fixtures/Makefile.v1.toml");
+
+  #[tokio::test]
+  async fn records_a_config_it_can_read_back() {
+    let cli = testing::get_test_cli();
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let (missing, recorded) = fs::with_file_system(file_system, async {
+      let missing = PostGenerationConfig::read_from_yaml_file().await;
+      fs::create_dir_all(cli.get_output_project_dir())
+        .await
+        .expect("must create crate dir");
+      let makefile_spec = MakefileSpec::try_from(&cli).expect("must make makefile");
+      PostGenerationConfig::new(&cli, &makefile_spec)
+        .expect("must make config")
+        .write_to_yaml_file(&cli)
+        .await
+        .expect("must write config");
+      let path = cli.get_output_project_subpath(&Paths::PostGenerationConfigFile);
+      (missing, fs::read(path).await.expect("must read config"))
+    })
+    .await;
+    assert!(matches!(
+      missing,
+      Err(PostGenerationError::MissingConfig(_))
+    ));
+    let config: PostGenerationConfig =
+      serde_yaml::from_slice(&recorded).expect("must parse config");
+    assert_eq!(
+      config.cargo_configurator.original_api_name,
+      testing::TEST_API_NAME
+    );
+  }
+
+  #[tokio::test]
+  async fn finds_the_extra_tasks_relative_to_the_crate() {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let cli = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file("/virtual/petstore.yaml")
+      .output_dir("/virtual/clients/petstore")
+      .extra_tasks("/virtual/shared/tasks.toml")
+      .build()
+      .expect("must build request")
+      .get_cli()
+      .clone();
+    let makefile_spec = MakefileSpec::try_from(&cli).expect("must make makefile");
+    let config = PostGenerationConfig::new(&cli, &makefile_spec).expect("must make config");
+    assert_eq!(
+      config
+        .settings
+        .as_ref()
+        .and_then(|settings| settings.extra_tasks_filepath_opt.as_ref()),
+      Some(&PathBuf::from("../../shared/tasks.toml"))
+    );
+    let moved_cli = config
+      .get_crate_cli(Path::new("/moved/clients/petstore"))
+      .await
+      .expect("must get settings");
+    assert_eq!(
+      moved_cli.inner_cli.extra_tasks_filepath_opt,
+      Some(PathBuf::from("/moved/shared/tasks.toml"))
+    );
+  }
+
+  #[tokio::test]
+  async fn records_only_the_settings_that_hold_for_a_moved_crate() {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let cli = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file("/virtual/specs/petstore.yaml")
+      .workspace("/virtual/clients")
+      .lib_name("petstore")
+      .output_dir_mode(OutputDirModes::Force)
+      .dry_run(true)
+      .step_timeout(Duration::from_secs(60))
+      .build()
+      .expect("must build request")
+      .get_cli()
+      .clone();
+    let makefile_spec = MakefileSpec::try_from(&cli).expect("must make makefile");
+    let config = PostGenerationConfig::new(&cli, &makefile_spec).expect("must make config");
+    let recorded = serde_yaml::to_string(&config.settings).expect("must write settings");
+    assert!(!recorded.contains("/virtual"), "{recorded}");
+    for run_flag in [
+      "force",
+      "dry_run",
+      "step_timeout",
+      "verbose",
+      "output_format",
+    ] {
+      assert!(!recorded.contains(run_flag), "{recorded}");
+    }
+    let moved_cli = config
+      .get_crate_cli(Path::new("/moved/clients/petstore"))
+      .await
+      .expect("must get settings");
+    assert_eq!(
+      moved_cli.inner_cli.workspace_dir_opt,
+      Some(PathBuf::from("/moved/clients"))
+    );
+    assert_eq!(
+      moved_cli.inner_cli.local_api_spec_filepath_opt,
+      Some(PathBuf::from("/moved/clients/petstore/petstore.yaml"))
+    );
+    assert_eq!(moved_cli.generation_timestamp, cli.generation_timestamp);
+    assert!(!moved_cli.force && !moved_cli.dry_run);
+    assert!(moved_cli.step_timeout_secs_opt.is_none());
+  }
+
+  #[tokio::test]
+  async fn rebuilds_the_settings_of_older_configs_from_the_makefile() {
+    let config: PostGenerationConfig =
+      serde_yaml::from_str(CONFIG_WITHOUT_SETTINGS).expect("must parse config");
+    assert!(config.settings.is_none());
+    let crate_dir = Path::new("/virtual/petstore");
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let (missing, rebuilt) = fs::with_file_system(file_system, async {
+      let missing = config.get_crate_cli(crate_dir).await;
+      fs::create_dir_all(crate_dir)
+        .await
+        .expect("must create crate dir");
+      fs::write(
+        crate_dir.join(MakefileEnv::MAKEFILE_NAME),
+        MAKEFILE_V1,
+        None::<&str>,
+      )
+      .await
+      .expect("must write makefile");
+      (missing, config.get_crate_cli(crate_dir).await)
+    })
+    .await;
+    assert!(matches!(
+      missing,
+      Err(CLIError::PostGenerationError(
+        PostGenerationError::MissingSettings(_)
+      ))
+    ));
+    let cli = rebuilt.expect("must rebuild settings");
+    assert_eq!(cli.inner_cli.site_or_api_name, testing::TEST_API_NAME);
+    assert_eq!(
+      cli.inner_cli.api_url.as_str(),
+      "https://www.petshoppe.example/"
+    );
+    assert_eq!(cli.get_lib_name(), "pet_shoppe_openapi_client");
+    assert_eq!(
+      cli.inner_cli.local_api_spec_filepath_opt,
+      Some(crate_dir.join("petstore.yaml"))
+    );
+    assert_eq!(cli.get_output_project_dir(), crate_dir);
+  }
+}
@@ -2,11 +2,14 @@
 
 use crate::{
   cli::{Cli, InnerCli, Paths},
-  generate::utils::*,
+  generate::{errors::ParameterError, specs::*, utils::*},
+};
+use crate::{
+  events,
+  fs::{self, write},
 };
-use fs_err::tokio as fs;
 use serde::{Deserialize, Serialize};
-use std::{io::Error as IOError, string::FromUtf8Error};
+use std::{collections::BTreeMap, io::Error as IOError, string::FromUtf8Error};
 use strum::EnumProperty;
 use thiserror::Error;
 /// Errors that can happen with yaml generation
@@ -16,14 +19,138 @@ pub enum READMEGenerationError {
   IOError(#[from] IOError),
   #[error(transparent)]
   FromUtf8Error(#[from] FromUtf8Error),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
+  #[error("README section `{0}` has a start marker without a matching end marker")]
+  UnterminatedSection(READMESections),
+}
+
+/// Sections of the README managed by this generator
+///
+/// Each section is delimited by HTML comment markers so it can be replaced in place on regeneration.
+/// Anything outside of the markers belongs to the user and is left alone.
+#[derive(
+  Clone, Copy, Debug, Deserialize, Eq, Error, Hash, PartialEq, Serialize, strum::AsRefStr,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum READMESections {
+  #[error("README header section")]
+  Header,
+  #[error("README servers section")]
+  Servers,
+  #[error("README operations section")]
+  Operations,
+  #[error("README security section")]
+  Security,
+  #[error("README quickstart section")]
+  Quickstart,
+  #[error("README tasks section")]
+  Tasks,
+  #[error("README about section")]
+  About,
+}
+impl READMESections {
+  /// Get the start of every start marker
+  fn start_marker_prefix() -> String {
+    format!("<!-- {}:begin:", get_this_crate_name())
+  }
+  /// Whether a document has any managed section
+  pub fn is_any_in(document: &str) -> bool {
+    document.contains(&Self::start_marker_prefix())
+  }
+  /// Get the start marker for this section
+  pub fn start_marker(&self) -> String {
+    format!("<!-- {}:begin:{} -->", get_this_crate_name(), self.as_ref())
+  }
+  /// Get the end marker for this section
+  pub fn end_marker(&self) -> String {
+    format!("<!-- {}:end:{} -->", get_this_crate_name(), self.as_ref())
+  }
+  /// Wrap section contents in markers
+  pub fn wrap(
+    &self,
+    contents: &str,
+  ) -> String {
+    format!(
+      "{}\n{}\n{}",
+      self.start_marker(),
+      contents.trim_matches('\n'),
+      self.end_marker()
+    )
+  }
+  /// Replace the section in the given document if its markers are present
+  ///
+  /// Returns `None` if the section is not present
+  pub fn try_replace_in(
+    &self,
+    document: &str,
+    contents: &str,
+  ) -> Result<Option<String>, READMEGenerationError> {
+    let start_marker = self.start_marker();
+    let end_marker = self.end_marker();
+    match document.find(&start_marker) {
+      None => Ok(None),
+      Some(start) => {
+        let end = document[start..]
+          .find(&end_marker)
+          .map(|i| start + i + end_marker.len())
+          .ok_or(READMEGenerationError::UnterminatedSection(*self))?;
+        let mut replaced = String::with_capacity(document.len() + contents.len());
+        replaced.push_str(&document[..start]);
+        replaced.push_str(&self.wrap(contents));
+        replaced.push_str(&document[end..]);
+        Ok(Some(replaced))
+      }
+    }
+  }
+  /// Replace the section in the given document, or append it if it is not present
+  pub fn upsert_in(
+    &self,
+    mut document: String,
+    contents: &str,
+  ) -> Result<String, READMEGenerationError> {
+    match self.try_replace_in(&document, contents)? {
+      Some(replaced) => Ok(replaced),
+      None => {
+        if !document.is_empty() && !document.ends_with("\n\n") {
+          document.push_str(if document.ends_with('\n') {
+            "\n"
+          } else {
+            "\n\n"
+          });
+        }
+        document.push_str(&self.wrap(contents));
+        document.push('\n');
+        Ok(document)
+      }
+    }
+  }
 }
 
+/// How the about section of a README written before the section markers starts
+const LEGACY_ABOUT_START: &str = "## About working on `";
+/// How the about section of a README written before the section markers ends
+const LEGACY_ABOUT_END: &str = "Try proposing changes to the generator tools instead.";
+/// The line that may follow the about section of a README written before the section markers
+const LEGACY_SPEC_URL_LINE_START: &str =
+  "\n- Uses the corresponding OpenAPI specification found at [";
+/// The line that may precede the about section of a README written before the section markers
+const LEGACY_AUTHORS_LINE_START: &str = "Additional authors: ";
+
 /// Readme generation
 #[derive(Debug, Deserialize, Serialize)]
 #[allow(non_camel_case_types)]
 pub struct READMEGenerator {
   start_readme_string: String,
   end_readme_string: String,
+  /// The generated library name
+  lib_name: String,
+  /// The path of the spec file relative to the generated crate
+  spec_file_path: String,
+  /// The Mermaid task graph of the crate, if it goes into the README
+  #[serde(default)]
+  task_graph_opt: Option<String>,
 }
 impl READMEGenerator {
   /// Get the readme string contents
@@ -42,7 +169,7 @@ impl READMEGenerator {
           if i == 0 {
             s.push_str("\nAdditional authors: ")
           }
-          s.push_str(&format!("{c}"));
+          s.push_str(&c.to_string());
           if i < eal - 1 {
             s.push_str(", ");
           }
@@ -84,20 +211,507 @@ impl READMEGenerator {
     Ok(Self {
       end_readme_string,
       start_readme_string,
+      lib_name: cli.get_lib_name(),
+      spec_file_path: cli.try_get_spec_file_name()?,
+      task_graph_opt: None,
     })
   }
+  /// Describe the tasks of the crate in the README with this Mermaid task graph
+  pub fn with_task_graph(
+    mut self,
+    task_graph: String,
+  ) -> Self {
+    self.task_graph_opt = Some(task_graph);
+    self
+  }
+  /// Get the name of the generated crate as used in rust code
+  fn get_lib_ident(&self) -> String {
+    self.lib_name.replace('-', "_")
+  }
+  /// Make the servers section
+  fn make_servers_string(spec: &OpenAPISpec) -> String {
+    let servers = spec.get_servers();
+    let mut s = "## Servers\n\n".to_string();
+    if servers.is_empty() {
+      s.push_str("The specification does not declare any servers. ");
+    } else {
+      for SpecServer { url, description } in servers.iter() {
+        match description {
+          Some(description) => s.push_str(&format!("- <{url}> — {description}\n")),
+          None => s.push_str(&format!("- <{url}>\n")),
+        }
+      }
+      s.push('\n');
+    }
+    s.push_str("Set `Configuration::base_path` to choose the server requests are sent to.");
+    s
+  }
+  /// Make the operations section
+  fn make_operations_string(
+    &self,
+    spec: &OpenAPISpec,
+  ) -> String {
+    let lib_ident = self.get_lib_ident();
+    let mut operations_by_tag: BTreeMap<String, Vec<SpecOperationEntry>> = BTreeMap::new();
+    for entry in spec.operations() {
+      operations_by_tag
+        .entry(entry.get_tag())
+        .or_default()
+        .push(entry);
+    }
+    let mut s = "## Operations\n".to_string();
+    if operations_by_tag.is_empty() {
+      s.push_str("\nThe specification does not declare any operations.");
+    }
+    for (tag, entries) in operations_by_tag.iter() {
+      let api_module = format!("{}_api", to_snake_case(tag));
+      let api_doc = format!("docs/{}Api.md", to_upper_camel_case(tag));
+      s.push_str(&format!(
+        "\n### {tag}\n\nModule: `{lib_ident}::apis::{api_module}`\n\n"
+      ));
+      s.push_str("| Function | Method | Path | Summary |\n");
+      s.push_str("| --- | --- | --- | --- |\n");
+      for entry in entries.iter() {
+        let function_name = entry.get_function_name();
+        let summary = entry
+          .operation
+          .summary
+          .as_deref()
+          .unwrap_or_default()
+          .replace('|', "\\|")
+          .replace('\n', " ");
+        s.push_str(&format!(
+          "| [`{function_name}`]({api_doc}#{function_name}) | `{}` | `{}` | {summary} |\n",
+          entry.method.to_uppercase(),
+          entry.path,
+        ));
+      }
+    }
+    s
+  }
+  /// Make the security section
+  fn make_security_string(spec: &OpenAPISpec) -> String {
+    let security_schemes = spec.security_schemes();
+    let mut s = "## Authentication\n\n".to_string();
+    if security_schemes.is_empty() {
+      s.push_str("The specification does not declare any security schemes.");
+      return s;
+    }
+    s.push_str("Credentials are set on `apis::configuration::Configuration`:\n\n");
+    for (name, scheme) in security_schemes.iter() {
+      let (kind, setting_opt) = Self::get_security_scheme_setting(scheme);
+      match setting_opt {
+        Some(setting) => s.push_str(&format!("- `{name}` ({kind}): `{setting}`\n")),
+        None => s.push_str(&format!(
+          "- `{name}` ({kind}): not supported by `Configuration`, send the credentials yourself\n"
+        )),
+      }
+    }
+    s
+  }
+  /// Get the kind of a security scheme, and how its credentials are set on the configuration if
+  /// they can be
+  fn get_security_scheme_setting(scheme: &SpecSecurityScheme) -> (String, Option<&'static str>) {
+    let SpecSecurityScheme {
+      scheme_type,
+      scheme: http_scheme_opt,
+      name: key_name_opt,
+      location: key_location_opt,
+      ..
+    } = scheme;
+    let http_scheme_opt = http_scheme_opt.as_deref().map(str::to_ascii_lowercase);
+    match (scheme_type.as_str(), http_scheme_opt.as_deref()) {
+      ("apiKey", _) => (
+        format!(
+          "API key `{}` in {}",
+          key_name_opt.as_deref().unwrap_or_default(),
+          key_location_opt.as_deref().unwrap_or("header")
+        ),
+        Some("configuration.api_key = Some(ApiKey { prefix: None, key: \"KEY\".to_string() });"),
+      ),
+      ("http", Some("basic")) | ("basic", _) => (
+        "HTTP basic".to_string(),
+        Some("configuration.basic_auth = Some((\"USER\".to_string(), Some(\"PASSWORD\".to_string())));"),
+      ),
+      ("http", Some("bearer")) => (
+        "HTTP bearer".to_string(),
+        Some("configuration.bearer_access_token = Some(\"TOKEN\".to_string());"),
+      ),
+      ("http", http_scheme_opt) => (
+        format!("HTTP {}", http_scheme_opt.unwrap_or("without a scheme")),
+        None,
+      ),
+      (other @ ("oauth2" | "openIdConnect"), _) => (
+        other.to_string(),
+        Some("configuration.oauth_access_token = Some(\"TOKEN\".to_string());"),
+      ),
+      (other, _) => (other.to_string(), None),
+    }
+  }
+  /// Make the tasks section
+  fn make_tasks_string(task_graph: &str) -> String {
+    format!(
+      "## Tasks\n\nThe tasks of the crate, with arrows to the tasks they run first:\n\n```mermaid\n{}\n```",
+      task_graph.trim_end()
+    )
+  }
+  /// Make the quickstart section using the operation that needs the fewest arguments
+  fn make_quickstart_string(
+    &self,
+    spec: &OpenAPISpec,
+  ) -> String {
+    let lib_ident = self.get_lib_ident();
+    let operations = spec.operations();
+    let entry_opt = operations.iter().min_by_key(|entry| {
+      entry.operation.parameters.len() + usize::from(entry.operation.request_body.is_some())
+    });
+    let mut s = "## Quickstart\n\n".to_string();
+    let Some(entry) = entry_opt else {
+      s.push_str("The specification does not declare any operations.");
+      return s;
+    };
+    let api_module = format!("{}_api", to_snake_case(&entry.get_tag()));
+    let function_name = entry.get_function_name();
+    let mut args = vec!["&configuration".to_string()];
+    args.extend(entry.operation.parameters.iter().map(|parameter| {
+      let name = parameter.name.as_deref().unwrap_or("parameter");
+      format!("/* {} */", to_snake_case(name))
+    }));
+    if entry.operation.request_body.is_some() {
+      args.push("/* body */".to_string());
+    }
+    // the holes keep the example from compiling, so it is not a doctest
+    let code_block_attributes = match args.len() > 1 {
+      true => {
+        s.push_str(
+          "Fill in the `/* ... */` arguments of the operation, the example does not compile \
+           without them.\n\n",
+        );
+        "rust,ignore"
+      }
+      false => "rust,no_run",
+    };
+    let args = args.join(", ");
+    let mut settings = spec
+      .get_servers()
+      .first()
+      .map(|server| format!("configuration.base_path = \"{}\".to_string();", server.url))
+      .into_iter()
+      .collect::<Vec<_>>();
+    // the credentials of the first scheme the configuration takes
+    let credentials_opt = spec
+      .security_schemes()
+      .values()
+      .find_map(|scheme| Self::get_security_scheme_setting(scheme).1);
+    settings.extend(credentials_opt.map(ToString::to_string));
+    let configuration_imports = match credentials_opt.is_some_and(|c| c.contains("ApiKey")) {
+      true => "configuration::{ApiKey, Configuration}",
+      false => "configuration::Configuration",
+    };
+    // a configuration without settings would warn of an unused `mut`
+    let configuration_binding = match settings.is_empty() {
+      true => "let configuration",
+      false => "let mut configuration",
+    };
+    let settings = settings
+      .iter()
+      .map(|setting| format!("\n          {setting}"))
+      .collect::<String>();
+    s.push_str(&trim_lines(&format!(
+      "
+      ```{code_block_attributes}
+      use {lib_ident}::apis::{{{configuration_imports}, {api_module}}};
+
+      #[tokio::main]
+      async fn main() {{
+          {configuration_binding} = Configuration::new();{settings}
+          let result = {api_module}::{function_name}({args}).await;
+          println!(\"{{result:?}}\");
+      }}
+      ```
+      "
+    )));
+    s
+  }
+  /// Bring a README written before the section markers to the marked layout
+  ///
+  /// Such READMEs got the about section appended on every generation, sometimes on the same line as
+  /// the previous copy. Every copy is removed, so that the about section is appended once with its
+  /// markers, and a header this generator wrote is marked as such.
+  fn migrate_unmarked_readme(
+    &self,
+    mut document: String,
+  ) -> String {
+    while let Some(mut start) = document.find(LEGACY_ABOUT_START) {
+      let Some(end_offset) = document[start..].find(LEGACY_ABOUT_END) else {
+        break;
+      };
+      let mut end = start + end_offset + LEGACY_ABOUT_END.len();
+      if document[end..].starts_with(LEGACY_SPEC_URL_LINE_START) {
+        end = document[end..]
+          .find("].")
+          .map_or(document.len(), |i| end + i + "].".len());
+      }
+      let line_start = document[..start]
+        .trim_end_matches('\n')
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+      if document[line_start..].starts_with(LEGACY_AUTHORS_LINE_START) {
+        start = line_start;
+      }
+      document.replace_range(start..end, "");
+    }
+    let mut document = document.trim_end().to_string();
+    document.push('\n');
+    let header_end = document.find('\n').unwrap_or(document.len());
+    if document[..header_end] == self.start_readme_string {
+      document.replace_range(
+        ..header_end,
+        &READMESections::Header.wrap(&self.start_readme_string),
+      );
+    }
+    document
+  }
+  /// Update the given readme contents with the generated sections
+  ///
+  /// Managed sections already present are replaced in place. A missing header is only added to a new
+  /// readme, and other missing sections are appended. Sections describing the spec are only written
+  /// if the spec is given. A readme without any section markers is migrated first (see
+  /// [Self::migrate_unmarked_readme]).
+  pub fn update_readme_contents(
+    &self,
+    readme_contents_opt: Option<String>,
+    spec_opt: Option<&OpenAPISpec>,
+  ) -> Result<String, READMEGenerationError> {
+    let mut readme_contents = match readme_contents_opt {
+      Some(readme_contents) if !READMESections::is_any_in(&readme_contents) => {
+        self.migrate_unmarked_readme(readme_contents)
+      }
+      Some(readme_contents) => readme_contents,
+      None => format!(
+        "{}\n\n",
+        READMESections::Header.wrap(&self.start_readme_string)
+      ),
+    };
+    if let Some(replaced) =
+      READMESections::Header.try_replace_in(&readme_contents, &self.start_readme_string)?
+    {
+      readme_contents = replaced;
+    }
+    let mut sections = vec![];
+    if let Some(spec) = spec_opt {
+      sections.extend([
+        (READMESections::Servers, Self::make_servers_string(spec)),
+        (
+          READMESections::Operations,
+          self.make_operations_string(spec),
+        ),
+        (READMESections::Security, Self::make_security_string(spec)),
+        (
+          READMESections::Quickstart,
+          self.make_quickstart_string(spec),
+        ),
+      ]);
+    }
+    if let Some(task_graph) = self.task_graph_opt.as_ref() {
+      sections.push((READMESections::Tasks, Self::make_tasks_string(task_graph)));
+    }
+    sections.push((READMESections::About, self.end_readme_string.clone()));
+    for (section, contents) in sections.iter() {
+      readme_contents = section.upsert_in(readme_contents, contents)?;
+    }
+    Ok(readme_contents)
+  }
   /// Write out to readme file
+  #[tracing::instrument(level = "debug", skip_all)]
   pub async fn update_readme_md_file(&self) -> Result<(), READMEGenerationError> {
     let readme_path = Paths::ReadmeMdFile
       .get_str("path")
-      .expect("must get Cargo.toml path");
-    let mut readme_contents = fs::read(&readme_path).await.map_or_else(
-      |_| Ok(format!("{}\n\n", &self.start_readme_string)),
-      |contents| String::from_utf8(contents),
-    )?;
-    readme_contents.push_str(&self.end_readme_string);
-    fs::write(&readme_path, &readme_contents).await?;
-    println!("Wrote README.md `{readme_path:?}`");
+      .expect("must get README.md path");
+    let readme_contents_opt = match fs::read(&readme_path).await {
+      Ok(contents) => Some(String::from_utf8(contents)?),
+      Err(_) => None,
+    };
+    let spec_opt = match OpenAPISpec::try_from_path(&self.spec_file_path).await {
+      Ok(spec) => Some(spec),
+      Err(e) => {
+        events::warning(format!(
+          "Could not read spec `{}` for README: {e}",
+          self.spec_file_path
+        ));
+        None
+      }
+    };
+    let readme_contents = self.update_readme_contents(readme_contents_opt, spec_opt.as_ref())?;
+    write(&readme_path, &readme_contents, Some("README.md")).await?;
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod test_readmes {
+  use super::*;
+  use crate::testing;
+  fn make_generator() -> READMEGenerator {
+    READMEGenerator {
+      start_readme_string: "# some_lib".to_string(),
+      end_readme_string: "## About".to_string(),
+      lib_name: "some_lib".to_string(),
+      spec_file_path: "spec.yaml".to_string(),
+      task_graph_opt: None,
+    }
+  }
+  #[test]
+  fn update_readme_contents_is_idempotent() {
+    let readme_generator = make_generator();
+    let user_readme = "# Generated\n\nUser notes\n".to_string();
+    let once = readme_generator
+      .update_readme_contents(Some(user_readme), None)
+      .expect("must update readme");
+    let twice = readme_generator
+      .update_readme_contents(Some(once.clone()), None)
+      .expect("must update readme");
+    assert_eq!(once, twice);
+    assert!(twice.starts_with("# Generated\n\nUser notes\n"));
+    assert_eq!(twice.matches("## About").count(), 1);
+  }
+  #[test]
+  fn update_readme_contents_replaces_sections() {
+    let readme_generator = make_generator();
+    let old = format!(
+      "{}\nkeep me\n{}\n",
+      READMESections::Header.wrap("# old_lib"),
+      READMESections::About.wrap("## Old about"),
+    );
+    let new = readme_generator
+      .update_readme_contents(Some(old), None)
+      .expect("must update readme");
+    assert_eq!(
+      new,
+      format!(
+        "{}\nkeep me\n{}\n",
+        READMESections::Header.wrap("# some_lib"),
+        READMESections::About.wrap("## About"),
+      )
+    );
+  }
+  #[test]
+  fn update_readme_contents_migrates_unmarked_readme() {
+    let readme_generator = make_generator();
+    let legacy_about =
+      "Additional authors: Someone\n## About working on `some_lib`\nHey! This library:\n\
+      - Implements the [Some API](https://some.example/).\n\
+      For these reasons, proposed changes to this repository will likely not be accepted. \
+      Try proposing changes to the generator tools instead.\n\
+      - Uses the corresponding OpenAPI specification found at [https://some.example/spec.yaml].";
+    let old = format!("# some_lib\n\nUser notes\n{legacy_about}{legacy_about}");
+    let new = readme_generator
+      .update_readme_contents(Some(old), None)
+      .expect("must update readme");
+    assert_eq!(
+      new,
+      format!(
+        "{}\n\nUser notes\n\n{}\n",
+        READMESections::Header.wrap("# some_lib"),
+        READMESections::About.wrap("## About"),
+      )
+    );
+  }
+  #[test]
+  fn update_readme_contents_describes_spec() {
+    let readme_generator = make_generator();
+    let spec = OpenAPISpec::try_from_str(testing::PETSTORE_YAML).expect("must parse spec");
+    let readme = readme_generator
+      .update_readme_contents(None, Some(&spec))
+      .expect("must update readme");
+    assert!(readme.contains("- <http://petstore.swagger.io/v1>"));
+    assert!(readme.contains(
+      "| [`show_pet_by_id`](docs/PetsApi.md#show_pet_by_id) | `GET` | `/pets/{petId}` |"
+    ));
+    assert!(readme.contains("pets_api::create_pets(&configuration).await"));
+  }
+  #[test]
+  fn update_readme_contents_leaves_holes_and_skips_unknown_schemes() {
+    let readme_generator = make_generator();
+    let spec = OpenAPISpec::try_from_str(
+      "
+      openapi: 3.0.0
+      info: {title: Some API, version: 1.0.0}
+      paths:
+        /things/{thingId}:
+          get:
+            operationId: getThing
+            parameters: [{name: thingId, in: path, required: true}]
+      components:
+        securitySchemes:
+          digestAuth: {type: http, scheme: digest}
+          tokenAuth: {type: http, scheme: Bearer}
+      ",
+    )
+    .expect("must parse spec");
+    let readme = readme_generator
+      .update_readme_contents(None, Some(&spec))
+      .expect("must update readme");
+    assert!(readme.contains("default_api::get_thing(&configuration, /* thing_id */).await"));
+    assert!(readme.contains(
+      "    let mut configuration = Configuration::new();\n    configuration.bearer_access_token"
+    ));
+    assert!(readme.contains("- `digestAuth` (HTTP digest): not supported by `Configuration`"));
+    assert!(readme.contains("- `tokenAuth` (HTTP bearer): `configuration.bearer_access_token"));
+  }
+  #[test]
+  fn update_readme_contents_quickstart_compiles_without_warnings() {
+    let readme_generator = make_generator();
+    let spec = OpenAPISpec::try_from_str(
+      "
+      openapi: 3.0.0
+      info: {title: Some API, version: 1.0.0}
+      paths:
+        /things/{thingId}:
+          parameters: [{name: thingId, in: path, required: true}]
+          get:
+            operationId: getThing
+      ",
+    )
+    .expect("must parse spec");
+    let readme = readme_generator
+      .update_readme_contents(None, Some(&spec))
+      .expect("must update readme");
+    assert!(readme.contains("    let configuration = Configuration::new();\n"));
+    assert!(readme.contains("default_api::get_thing(&configuration, /* thing_id */).await"));
+    assert!(readme.contains("| `GET` | `/things/{thingId}` |"));
+    let spec = OpenAPISpec::try_from_str(
+      "
+      openapi: 3.0.0
+      info: {title: Some API, version: 1.0.0}
+      paths:
+        /things:
+          get: {operationId: listThings}
+      components:
+        securitySchemes:
+          keyAuth: {type: apiKey, name: X-Key, in: header}
+      ",
+    )
+    .expect("must parse spec");
+    let readme = readme_generator
+      .update_readme_contents(None, Some(&spec))
+      .expect("must update readme");
+    assert!(
+      readme.contains("use some_lib::apis::{configuration::{ApiKey, Configuration}, default_api};")
+    );
+    assert!(readme.contains("    let mut configuration = Configuration::new();\n    configuration.api_key = Some(ApiKey {"));
+  }
+  #[test]
+  fn update_readme_contents_embeds_task_graph() {
+    let readme_generator =
+      make_generator().with_task_graph("flowchart LR\n  task0[\"generate-all\"]\n".to_string());
+    let readme = readme_generator
+      .update_readme_contents(None, None)
+      .expect("must update readme");
+    assert!(readme.contains(&READMESections::Tasks.wrap(
+      "## Tasks\n\nThe tasks of the crate, with arrows to the tasks they run first:\n\n\
+       ```mermaid\nflowchart LR\n  task0[\"generate-all\"]\n```"
+    )));
+    assert!(readme.find("## Tasks") < readme.find("## About"));
+  }
+}
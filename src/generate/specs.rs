@@ -0,0 +1,294 @@
+//! OpenAPI specification reading
+//!
+//! Only the parts of a specification the generator reports on are modeled. Unknown fields are ignored.
+use crate::{fs, generate::utils::to_snake_case};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Error as SerdeYAMLError, Value};
+use std::{collections::BTreeMap, io::Error as IOError, path::Path};
+use thiserror::Error;
+
+/// Specification reading errors
+#[derive(Debug, Error)]
+pub enum SpecError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+}
+
+/// An OpenAPI (or Swagger 2.0) specification
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenAPISpec {
+  /// OpenAPI version (3.x)
+  #[serde(default)]
+  pub openapi: Option<String>,
+  /// Swagger version (2.0)
+  #[serde(default)]
+  pub swagger: Option<String>,
+  /// API metadata
+  #[serde(default)]
+  pub info: SpecInfo,
+  /// Servers (3.x)
+  #[serde(default)]
+  pub servers: Vec<SpecServer>,
+  /// Host (2.0)
+  #[serde(default)]
+  pub host: Option<String>,
+  /// Base path (2.0)
+  #[serde(default)]
+  pub base_path: Option<String>,
+  /// Schemes (2.0)
+  #[serde(default)]
+  pub schemes: Vec<String>,
+  /// Paths and their operations
+  #[serde(default)]
+  pub paths: BTreeMap<String, SpecPathItem>,
+  /// Reusable components (3.x)
+  #[serde(default)]
+  pub components: SpecComponents,
+  /// Models (2.0)
+  #[serde(default)]
+  pub definitions: BTreeMap<String, Value>,
+  /// Security schemes (2.0)
+  #[serde(default)]
+  pub security_definitions: BTreeMap<String, SpecSecurityScheme>,
+}
+impl OpenAPISpec {
+  /// Parse a yaml or json specification
+  pub fn try_from_str(s: &str) -> Result<Self, SpecError> {
+    serde_yaml::from_str(s).map_err(SpecError::from)
+  }
+  /// Read a yaml or json specification file
+  #[tracing::instrument(level = "debug", skip_all, fields(path = ?path.as_ref()))]
+  pub async fn try_from_path(path: impl AsRef<Path>) -> Result<Self, SpecError> {
+    let contents = fs::read_to_string(path.as_ref()).await?;
+    Self::try_from_str(&contents)
+  }
+  /// Get all operations ordered by path then method
+  pub fn operations(&self) -> Vec<SpecOperationEntry<'_>> {
+    self
+      .paths
+      .iter()
+      .flat_map(|(path, path_item)| {
+        path_item
+          .operations()
+          .into_iter()
+          .map(move |(method, operation)| SpecOperationEntry {
+            path,
+            method,
+            operation,
+          })
+      })
+      .collect()
+  }
+  /// Get model schemas by name
+  pub fn models(&self) -> &BTreeMap<String, Value> {
+    if self.components.schemas.is_empty() {
+      &self.definitions
+    } else {
+      &self.components.schemas
+    }
+  }
+  /// Get security schemes by name
+  pub fn security_schemes(&self) -> &BTreeMap<String, SpecSecurityScheme> {
+    if self.components.security_schemes.is_empty() {
+      &self.security_definitions
+    } else {
+      &self.components.security_schemes
+    }
+  }
+  /// Get servers, including one made from the Swagger 2.0 host if present
+  pub fn get_servers(&self) -> Vec<SpecServer> {
+    match self.host.as_ref() {
+      Some(host) if self.servers.is_empty() => {
+        let base_path = self.base_path.clone().unwrap_or_default();
+        let schemes = if self.schemes.is_empty() {
+          vec!["https".to_string()]
+        } else {
+          self.schemes.clone()
+        };
+        schemes
+          .iter()
+          .map(|scheme| SpecServer {
+            url: format!("{scheme}://{host}{base_path}"),
+            description: None,
+          })
+          .collect()
+      }
+      _ => self.servers.clone(),
+    }
+  }
+}
+
+/// API metadata
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SpecInfo {
+  #[serde(default)]
+  pub title: String,
+  #[serde(default)]
+  pub version: String,
+  #[serde(default)]
+  pub description: Option<String>,
+}
+
+/// A server
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SpecServer {
+  pub url: String,
+  #[serde(default)]
+  pub description: Option<String>,
+}
+
+/// Reusable components
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpecComponents {
+  #[serde(default)]
+  pub schemas: BTreeMap<String, Value>,
+  #[serde(default)]
+  pub security_schemes: BTreeMap<String, SpecSecurityScheme>,
+}
+
+/// A security scheme
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpecSecurityScheme {
+  #[serde(rename = "type", default)]
+  pub scheme_type: String,
+  #[serde(default)]
+  pub scheme: Option<String>,
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(rename = "in", default)]
+  pub location: Option<String>,
+  #[serde(default)]
+  pub bearer_format: Option<String>,
+  #[serde(default)]
+  pub description: Option<String>,
+}
+
+/// Operations available on a path
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SpecPathItem {
+  pub get: Option<SpecOperation>,
+  pub put: Option<SpecOperation>,
+  pub post: Option<SpecOperation>,
+  pub delete: Option<SpecOperation>,
+  pub options: Option<SpecOperation>,
+  pub head: Option<SpecOperation>,
+  pub patch: Option<SpecOperation>,
+  pub trace: Option<SpecOperation>,
+  /// The parameters every operation on this path takes
+  #[serde(default)]
+  pub parameters: Vec<SpecParameter>,
+}
+impl SpecPathItem {
+  /// Get the operations on this path with their http methods, along with the parameters of the path
+  pub fn operations(&self) -> Vec<(&'static str, SpecOperation)> {
+    [
+      ("get", &self.get),
+      ("put", &self.put),
+      ("post", &self.post),
+      ("delete", &self.delete),
+      ("options", &self.options),
+      ("head", &self.head),
+      ("patch", &self.patch),
+      ("trace", &self.trace),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation_opt)| {
+      operation_opt
+        .as_ref()
+        .map(|operation| (method, self.inherit_parameters(operation)))
+    })
+    .collect()
+  }
+  /// Get an operation with the parameters of the path put first
+  ///
+  /// A parameter of the operation overrides the one of the path with the same name and location.
+  fn inherit_parameters(
+    &self,
+    operation: &SpecOperation,
+  ) -> SpecOperation {
+    let mut operation = operation.clone();
+    let inherited = self
+      .parameters
+      .iter()
+      .filter(|path_parameter| {
+        !operation.parameters.iter().any(|parameter| {
+          parameter.name == path_parameter.name
+            && parameter.location == path_parameter.location
+            && parameter.reference == path_parameter.reference
+        })
+      })
+      .cloned()
+      .collect::<Vec<_>>();
+    operation.parameters.splice(0..0, inherited);
+    operation
+  }
+}
+
+/// An operation
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpecOperation {
+  #[serde(default)]
+  pub operation_id: Option<String>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  #[serde(default)]
+  pub summary: Option<String>,
+  #[serde(default)]
+  pub description: Option<String>,
+  #[serde(default)]
+  pub parameters: Vec<SpecParameter>,
+  #[serde(default)]
+  pub request_body: Option<Value>,
+  #[serde(default)]
+  pub deprecated: bool,
+}
+
+/// An operation parameter
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SpecParameter {
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(rename = "in", default)]
+  pub location: Option<String>,
+  #[serde(default)]
+  pub required: bool,
+  #[serde(rename = "$ref", default)]
+  pub reference: Option<String>,
+}
+
+/// An operation along with where it is found
+#[derive(Clone, Debug)]
+pub struct SpecOperationEntry<'a> {
+  pub path: &'a str,
+  pub method: &'static str,
+  /// The operation, with the parameters of its path
+  pub operation: SpecOperation,
+}
+impl<'a> SpecOperationEntry<'a> {
+  /// The tag the generator files this operation under
+  pub fn get_tag(&self) -> String {
+    self
+      .operation
+      .tags
+      .first()
+      .cloned()
+      .unwrap_or_else(|| "default".to_string())
+  }
+  /// The name of the generated function for this operation
+  pub fn get_function_name(&self) -> String {
+    match self.operation.operation_id.as_ref() {
+      Some(operation_id) => to_snake_case(operation_id),
+      None => to_snake_case(&format!("{} {}", self.method, self.path)),
+    }
+  }
+  /// A key that identifies this operation across specification versions
+  pub fn get_key(&self) -> String {
+    format!("{} {}", self.method.to_uppercase(), self.path)
+  }
+}
@@ -0,0 +1,341 @@
+//! Task dependency graphs
+//!
+//! The tasks of a [MakefileSpec] and what they depend on, rendered for `olg tasks graph` or for the
+//! README of a crate generated with `--readme-task-graph`.
+use crate::{
+  cli::CLIError,
+  fs,
+  generate::{
+    makefiles::{MakefileEnv, MakefileSpec},
+    post_generation,
+    task_runners::TaskRunners,
+  },
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use toml::Value;
+
+/// Formats of a task graph
+#[derive(
+  Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, ValueEnum, strum::AsRefStr,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum GraphFormats {
+  /// A Graphviz digraph
+  Dot,
+  /// A Mermaid flowchart
+  Mermaid,
+  /// A tree of the tasks
+  #[default]
+  Text,
+}
+
+/// A task of a graph
+#[derive(Debug, Serialize)]
+pub struct TaskNode {
+  pub name: String,
+  pub description: String,
+  /// Whether users added the task to the makefile
+  pub is_user_task: bool,
+  /// The names of the tasks it depends on, in order
+  pub dependencies: Vec<String>,
+}
+
+/// The tasks of a makefile and their dependencies
+#[derive(Debug, Serialize)]
+pub struct TaskGraph {
+  /// The generated tasks, then the tasks of users
+  pub nodes: Vec<TaskNode>,
+}
+impl From<&MakefileSpec> for TaskGraph {
+  fn from(spec: &MakefileSpec) -> Self {
+    let mut nodes: Vec<TaskNode> = spec
+      .tasks
+      .iter()
+      .map(|(name, task)| TaskNode {
+        name: name.as_ref().to_string(),
+        description: task.description.clone(),
+        is_user_task: false,
+        dependencies: task
+          .dependencies
+          .iter()
+          .map(|dependency| dependency.as_ref().to_string())
+          .collect(),
+      })
+      .collect();
+    nodes.extend(spec.user_tasks.iter().map(|(name, task)| {
+      TaskNode {
+        name: name.clone(),
+        description: task
+          .get("description")
+          .and_then(Value::as_str)
+          .unwrap_or_default()
+          .to_string(),
+        is_user_task: true,
+        dependencies: get_user_task_dependencies(task),
+      }
+    }));
+    Self { nodes }
+  }
+}
+impl TaskGraph {
+  /// Render the graph
+  pub fn render(
+    &self,
+    format: GraphFormats,
+  ) -> String {
+    match format {
+      GraphFormats::Dot => self.to_dot(),
+      GraphFormats::Mermaid => self.to_mermaid(),
+      GraphFormats::Text => self.to_text(),
+    }
+  }
+  /// Get a task by name
+  fn get_node(
+    &self,
+    name: &str,
+  ) -> Option<&TaskNode> {
+    self.nodes.iter().find(|node| node.name == name)
+  }
+  /// Get the names of the tasks, then of the tasks outside of the makefile that tasks depend on
+  fn get_names(&self) -> Vec<&str> {
+    let mut names: Vec<&str> = self.nodes.iter().map(|node| node.name.as_str()).collect();
+    for node in self.nodes.iter() {
+      for dependency in node.dependencies.iter() {
+        if !names.contains(&dependency.as_str()) {
+          names.push(dependency);
+        }
+      }
+    }
+    names
+  }
+  /// Render a Graphviz digraph, with edges from tasks to their dependencies
+  pub fn to_dot(&self) -> String {
+    let mut lines = vec!["digraph tasks {".to_string(), "  rankdir=LR;".to_string()];
+    for node in self.nodes.iter() {
+      let style = match node.is_user_task {
+        true => ", style=dashed",
+        false => "",
+      };
+      lines.push(format!(
+        "  {:?} [tooltip={:?}{style}];",
+        node.name, node.description
+      ));
+    }
+    for node in self.nodes.iter() {
+      for dependency in node.dependencies.iter() {
+        lines.push(format!("  {:?} -> {:?};", node.name, dependency));
+      }
+    }
+    lines.push("}".to_string());
+    lines.join("\n") + "\n"
+  }
+  /// Render a Mermaid flowchart, with edges from tasks to their dependencies
+  ///
+  /// The tasks get ids of their own, as task names are not valid ids.
+  pub fn to_mermaid(&self) -> String {
+    let names = self.get_names();
+    let get_id = |name: &str| {
+      let index = names
+        .iter()
+        .position(|known| *known == name)
+        .unwrap_or_default();
+      format!("task{index}")
+    };
+    let mut lines = vec!["flowchart LR".to_string()];
+    for name in names.iter() {
+      let id = get_id(name);
+      lines.push(match self.get_node(name) {
+        Some(node) if node.is_user_task => format!("  {id}(\"{name}\")"),
+        _ => format!("  {id}[\"{name}\"]"),
+      });
+    }
+    for node in self.nodes.iter() {
+      for dependency in node.dependencies.iter() {
+        lines.push(format!(
+          "  {} --> {}",
+          get_id(&node.name),
+          get_id(dependency)
+        ));
+      }
+    }
+    lines.join("\n") + "\n"
+  }
+  /// Render a tree for every task no other task depends on, with the dependencies of each task under
+  /// it
+  pub fn to_text(&self) -> String {
+    let mut text = String::new();
+    for node in self.nodes.iter() {
+      let is_root = !self
+        .nodes
+        .iter()
+        .any(|other| other.dependencies.contains(&node.name));
+      if is_root {
+        self.write_text_tree(&node.name, "", "", &mut vec![], &mut text);
+      }
+    }
+    text
+  }
+  /// Write a task and the tree of its dependencies
+  fn write_text_tree(
+    &self,
+    name: &str,
+    prefix: &str,
+    child_prefix: &str,
+    path: &mut Vec<String>,
+    text: &mut String,
+  ) {
+    let node_opt = self.get_node(name);
+    let note = match node_opt {
+      None => " (not in the makefile)",
+      Some(node) if node.is_user_task => " (user task)",
+      Some(_) => "",
+    };
+    if path.iter().any(|visited| visited == name) {
+      text.push_str(&format!("{prefix}{name} (cycle)\n"));
+      return;
+    }
+    text.push_str(&format!("{prefix}{name}{note}\n"));
+    let Some(node) = node_opt else {
+      return;
+    };
+    path.push(name.to_string());
+    for (index, dependency) in node.dependencies.iter().enumerate() {
+      let (branch, indent) = match index + 1 == node.dependencies.len() {
+        true => ("└── ", "    "),
+        false => ("├── ", "│   "),
+      };
+      self.write_text_tree(
+        dependency,
+        &format!("{child_prefix}{branch}"),
+        &format!("{child_prefix}{indent}"),
+        path,
+        text,
+      );
+    }
+    path.pop();
+  }
+}
+
+/// Get the names of the dependencies of a task users added, as cargo-make reads them
+fn get_user_task_dependencies(task: &Value) -> Vec<String> {
+  task
+    .get("dependencies")
+    .and_then(Value::as_array)
+    .into_iter()
+    .flatten()
+    .filter_map(|dependency| match dependency {
+      Value::String(name) => Some(name.clone()),
+      Value::Table(definition) => definition
+        .get("name")
+        .and_then(Value::as_str)
+        .map(ToString::to_string),
+      _ => None,
+    })
+    .collect()
+}
+
+/// A task graph as rendered for `olg tasks graph`
+#[derive(Debug, Serialize)]
+pub struct TaskGraphReport {
+  pub format: GraphFormats,
+  pub graph: String,
+}
+impl fmt::Display for TaskGraphReport {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    write!(f, "{}", self.graph)
+  }
+}
+
+/// Render the task graph of the crate in the current dir
+///
+/// The graph of a cargo-make crate is read from its `Makefile.toml`, as it is. The tasks of the
+/// other task runners are not read back, so their graph comes from the settings the crate was
+/// generated with.
+pub async fn run_task_graph(format: GraphFormats) -> Result<TaskGraphReport, CLIError> {
+  let cli = post_generation::read_crate_cli().await?;
+  let spec = match cli.task_runner {
+    TaskRunners::CargoMake => {
+      MakefileSpec::from_makefile(&fs::read_to_string(MakefileEnv::MAKEFILE_NAME).await?)?
+    }
+    TaskRunners::Just | TaskRunners::Xtask => MakefileSpec::try_from(&cli)?,
+  };
+  Ok(TaskGraphReport {
+    format,
+    graph: TaskGraph::from(&spec).render(format),
+  })
+}
+
+#[cfg(test)]
+mod test_task_graphs {
+  use super::*;
+  use crate::{fs::MemoryFileSystem, generate::post_generation::PostGenerationConfig, testing};
+  use std::sync::Arc;
+
+  /// Get the graph of a generation, with a task a user added
+  fn get_graph() -> TaskGraph {
+    let cli = testing::get_test_cli();
+    let mut spec = MakefileSpec::try_from(&cli).expect("must make makefile");
+    spec.user_tasks.insert(
+      "publish".to_string(),
+      toml::from_str("dependencies = [\"generate-all\", \"build\"]").expect("must parse task"),
+    );
+    TaskGraph::from(&spec)
+  }
+
+  #[test]
+  fn renders_the_dependencies_in_every_format() {
+    let graph = get_graph();
+    let dot = graph.render(GraphFormats::Dot);
+    assert!(dot.contains("  \"crate-scaffold\" -> \"output-dir-create\";\n"));
+    assert!(dot.contains("  \"publish\" [tooltip=\"\", style=dashed];\n"));
+    let mermaid = graph.render(GraphFormats::Mermaid);
+    assert!(mermaid.starts_with("flowchart LR\n"));
+    assert!(mermaid.contains("(\"publish\")"));
+    let text = graph.render(GraphFormats::Text);
+    assert!(text.contains(
+      "publish (user task)\n\
+       ├── generate-all\n\
+       │   ├── lib-code-generate\n\
+       │   ├── cargo-fix-generated\n\
+       │   └── olg-install\n\
+       └── build (not in the makefile)\n"
+    ));
+    assert!(!text.contains("\ngenerate-all\n"));
+  }
+
+  #[tokio::test]
+  async fn reads_the_graph_of_a_crate_from_its_makefile() {
+    let cli = testing::get_test_cli();
+    let spec = MakefileSpec::try_from(&cli).expect("must make makefile");
+    let config = PostGenerationConfig::new(&cli, &spec).expect("must make config");
+    let makefile = format!(
+      "{}\n[tasks.lint]\ndescription = \"Lint\"\ndependencies = [\"generate-all\"]\n",
+      spec.to_makefile().expect("must render makefile")
+    );
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let report = fs::with_file_system(file_system, async {
+      fs::write(
+        "post_generation.yaml",
+        serde_yaml::to_string(&config).expect("must write config"),
+        None::<&str>,
+      )
+      .await
+      .expect("must record config");
+      fs::write(MakefileEnv::MAKEFILE_NAME, makefile, None::<&str>)
+        .await
+        .expect("must write makefile");
+      run_task_graph(GraphFormats::Text).await
+    })
+    .await
+    .expect("must render graph");
+    assert!(report
+      .graph
+      .contains("lint (user task)\n└── generate-all\n"));
+  }
+}
@@ -0,0 +1,494 @@
+//! Task runner backends
+//!
+//! The [TaskSpec]s of a [MakefileSpec] are written for the task runner picked with `--task-runner`:
+//! a cargo-make `Makefile.toml` (see [MakefileSpec::to_makefile]), a `justfile`, or an `xtask` crate
+//! run with `cargo xtask`, which needs nothing but cargo.
+use crate::{
+  cli::Cli,
+  generate::{
+    errors::MakefileGenerationError,
+    makefiles::{MakefileEnv, MakefileSpec, TaskActions, TaskNames, TaskSpec},
+  },
+  process::ProcessSpec,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use toml::Value;
+
+/// Name of the `justfile`
+pub const JUSTFILE_NAME: &str = "justfile";
+/// Path of the cargo config with the `xtask` alias, relative to the crate
+pub const XTASK_CARGO_CONFIG_PATH: &str = ".cargo/config.toml";
+/// Path of the manifest of the xtask crate, relative to the crate
+pub const XTASK_MANIFEST_PATH: &str = "xtask/Cargo.toml";
+/// Path of the main file of the xtask crate, relative to the crate
+pub const XTASK_MAIN_PATH: &str = "xtask/src/main.rs";
+/// The task args in the args of a [TaskActions::Command]
+const TASK_ARGS: &str = "${@}";
+
+/// Tools the tasks of a generated crate can be written for
+#[derive(
+  Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, ValueEnum, strum::AsRefStr,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskRunners {
+  /// A `Makefile.toml`, run with `cargo make <task>`
+  #[default]
+  CargoMake,
+  /// A `justfile`, run with `just <task>`
+  Just,
+  /// An `xtask` crate, run with `cargo xtask <task>`
+  Xtask,
+}
+impl TaskRunners {
+  /// Get the command that runs a task in the crate
+  pub fn get_task_spec(
+    &self,
+    task_name: &str,
+  ) -> ProcessSpec {
+    match self {
+      Self::CargoMake => ProcessSpec::new("cargo", &["make", task_name]),
+      Self::Just => ProcessSpec::new("just", &[task_name]),
+      Self::Xtask => ProcessSpec::new("cargo", &["xtask", task_name]),
+    }
+  }
+  /// Get the paths of the files the tasks are written to, relative to the crate
+  pub fn get_file_names(&self) -> Vec<String> {
+    match self {
+      Self::CargoMake => vec![MakefileEnv::MAKEFILE_NAME.to_string()],
+      Self::Just => vec![JUSTFILE_NAME.to_string()],
+      Self::Xtask => vec![
+        XTASK_CARGO_CONFIG_PATH.to_string(),
+        XTASK_MANIFEST_PATH.to_string(),
+        XTASK_MAIN_PATH.to_string(),
+      ],
+    }
+  }
+}
+
+/// An env value of the tasks
+enum TaskEnvValues {
+  /// A value, with the values it refers to filled in
+  Value(String),
+  /// What a shell script prints in the crate dir when the tasks run
+  Script(Vec<String>),
+}
+
+/// Get the env of the tasks by name
+fn get_task_env(
+  env: &MakefileEnv
+) -> Result<BTreeMap<String, TaskEnvValues>, MakefileGenerationError> {
+  let Value::Table(table) = Value::try_from(env)? else {
+    return Err(MakefileGenerationError::EnvMissingKey("env".to_string()));
+  };
+  let values = table
+    .iter()
+    .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+    .collect::<BTreeMap<_, _>>();
+  let task_env = table
+    .iter()
+    .filter_map(|(name, value)| {
+      let task_env_value = match value {
+        Value::String(value) => TaskEnvValues::Value(expand_env(value, &values)),
+        value => TaskEnvValues::Script(
+          value
+            .get("script")?
+            .as_array()?
+            .iter()
+            .filter_map(|line| line.as_str().map(ToString::to_string))
+            .collect(),
+        ),
+      };
+      Some((name.clone(), task_env_value))
+    })
+    .collect();
+  Ok(task_env)
+}
+
+/// Get the values of the env that are known before the tasks run
+fn get_env_values(task_env: &BTreeMap<String, TaskEnvValues>) -> BTreeMap<String, String> {
+  task_env
+    .iter()
+    .filter_map(|(name, value)| match value {
+      TaskEnvValues::Value(value) => Some((name.clone(), value.clone())),
+      TaskEnvValues::Script(_) => None,
+    })
+    .collect()
+}
+
+/// Fill known values into `${NAME}`, including values that refer to other values
+fn expand_env(
+  s: &str,
+  values: &BTreeMap<String, String>,
+) -> String {
+  let mut expanded = s.to_string();
+  for _ in 0..=values.len() {
+    let next = values.iter().fold(expanded.clone(), |next, (name, value)| {
+      next.replace(&format!("${{{name}}}"), value)
+    });
+    if next == expanded {
+      break;
+    }
+    expanded = next;
+  }
+  expanded
+}
+
+/// Quote a command word for the shell unless it is plain, leaving `${NAME}` to expand
+fn quote_shell_word(word: &str) -> String {
+  if word == TASK_ARGS {
+    return r#""$@""#.to_string();
+  }
+  let is_plain = !word.is_empty()
+    && word
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,${}".contains(c));
+  match is_plain {
+    true => word.to_string(),
+    false => format!(
+      "\"{}\"",
+      word
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('`', "\\`")
+    ),
+  }
+}
+
+/// Make a `just` string
+fn to_just_string(s: &str) -> String {
+  match s.contains(['\'', '\n']) {
+    true => format!(
+      "\"{}\"",
+      s.replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+    ),
+    false => format!("'{s}'"),
+  }
+}
+
+/// Get the lines of the body of a `just` recipe
+///
+/// A recipe with a condition becomes a bash script that exits early if the condition fails, since
+/// just has no conditions of its own.
+fn get_just_recipe_lines(task: &TaskSpec) -> Vec<String> {
+  let action_lines = match task.action_opt.as_ref() {
+    Some(TaskActions::Command { program, args }) => vec![[program]
+      .into_iter()
+      .chain(args)
+      .map(|word| quote_shell_word(word))
+      .collect::<Vec<_>>()
+      .join(" ")],
+    Some(TaskActions::Script(lines)) => lines.clone(),
+    None => vec![],
+  };
+  let Some(condition_script) = task.condition_script_opt.as_ref() else {
+    return action_lines;
+  };
+  let is_not_shebang = |line: &&String| !line.starts_with("#!");
+  let mut lines = vec![
+    "#!/usr/bin/env bash".to_string(),
+    "set -e".to_string(),
+    "if ! (".to_string(),
+  ];
+  lines.extend(
+    condition_script
+      .iter()
+      .filter(is_not_shebang)
+      .map(|line| format!("  {line}")),
+  );
+  lines.extend([") ; then", "  exit 0", "fi"].map(ToString::to_string));
+  lines.extend(action_lines.iter().filter(is_not_shebang).cloned());
+  lines
+}
+
+/// Render the `justfile` of the tasks
+pub fn render_justfile(
+  spec: &MakefileSpec,
+  cli: &Cli,
+) -> Result<String, MakefileGenerationError> {
+  let task_env = get_task_env(&spec.env)?;
+  let values = get_env_values(&task_env);
+  let mut lines = vec![
+    format!(
+      "# Tasks of the `{}` crate. Run one with `just <task>`, or list them with `just`",
+      cli.get_lib_name()
+    ),
+    String::new(),
+    "set export".to_string(),
+    "set positional-arguments".to_string(),
+    String::new(),
+  ];
+  for (name, value) in task_env.iter() {
+    lines.push(match value {
+      TaskEnvValues::Value(value) => format!("{name} := {}", to_just_string(value)),
+      TaskEnvValues::Script(script) => format!("{name} := `{}`", script.join("; ")),
+    });
+  }
+  lines.extend(["", "# List the tasks", "default:", "    @just --list"].map(ToString::to_string));
+  for (name, task) in spec.tasks.iter() {
+    lines.push(String::new());
+    lines.push(format!("# {}", expand_env(&task.description, &values)));
+    let mut header = name.as_ref().to_string();
+    if let Some(TaskActions::Command { args, .. }) = task.action_opt.as_ref() {
+      if args.iter().any(|arg| arg == TASK_ARGS) {
+        header.push_str(" *ARGS");
+      }
+    }
+    header.push(':');
+    for dependency in task.dependencies.iter() {
+      header.push(' ');
+      header.push_str(dependency.as_ref());
+    }
+    lines.push(header);
+    // `{{` starts an interpolation in recipes
+    lines.extend(
+      get_just_recipe_lines(task)
+        .iter()
+        .map(|line| format!("    {}", line.replace("{{", "{{ '{{' }}"))),
+    );
+  }
+  Ok(lines.join("\n") + "\n")
+}
+
+/// Make a Rust raw string literal
+fn to_raw_string_literal(s: &str) -> String {
+  let hashes = (1..)
+    .map(|n| "#".repeat(n))
+    .find(|hashes| !s.contains(&format!("\"{hashes}")))
+    .expect("must find a delimiter");
+  format!("r{hashes}\"{s}\"{hashes}")
+}
+
+/// Get the body of the `match` arm running a task of the xtask crate
+fn get_xtask_arm_lines(
+  name: TaskNames,
+  task: &TaskSpec,
+) -> Vec<String> {
+  let mut lines = vec![];
+  if let Some(condition_script) = task.condition_script_opt.as_ref() {
+    lines.extend([
+      format!(
+        "if !is_met({})? {{",
+        to_raw_string_literal(&condition_script.join("\n"))
+      ),
+      format!(
+        "  println!(\"Skipping `{}`, its condition failed\");",
+        name.as_ref()
+      ),
+      "  return Ok(());".to_string(),
+      "}".to_string(),
+    ]);
+  }
+  for dependency in task.dependencies.iter() {
+    lines.push(format!("run_task({:?}, &[])?;", dependency.as_ref()));
+  }
+  lines.push(match task.action_opt.as_ref() {
+    Some(TaskActions::Command { program, args }) => {
+      format!("run_command({program:?}, &{args:?}, task_args)")
+    }
+    Some(TaskActions::Script(lines)) => {
+      format!("run_script({})", to_raw_string_literal(&lines.join("\n")))
+    }
+    None => "Ok(())".to_string(),
+  });
+  lines
+}
+
+/// Render the files of the xtask crate and the cargo config aliasing it, by path relative to the
+/// crate
+pub fn render_xtask(
+  spec: &MakefileSpec,
+  cli: &Cli,
+) -> Result<Vec<(String, String)>, MakefileGenerationError> {
+  let task_env = get_task_env(&spec.env)?;
+  let values = get_env_values(&task_env);
+  let mut files = vec![(
+    XTASK_CARGO_CONFIG_PATH.to_string(),
+    "[alias]\nxtask = \"run --quiet --manifest-path xtask/Cargo.toml --\"\n".to_string(),
+  )];
+  let mut task_entries = String::new();
+  let mut arms = String::new();
+  for (name, task) in spec.tasks.iter() {
+    let description = expand_env(&task.description, &values);
+    task_entries.push_str(&format!("  ({:?}, {description:?}),\n", name.as_ref()));
+    arms.push_str(&format!("    {:?} => {{\n", name.as_ref()));
+    for line in get_xtask_arm_lines(*name, task) {
+      arms.push_str(&format!("      {line}\n"));
+    }
+    arms.push_str("    }\n");
+  }
+  files.push((
+    XTASK_MANIFEST_PATH.to_string(),
+    "[package]\nname = \"xtask\"\nversion = \"0.1.0\"\nedition = \"2021\"\npublish = false\n\n# \
+     keeps the xtask out of any workspace the crate is a member of\n[workspace]\n"
+      .to_string(),
+  ));
+  let mut env_entries = String::new();
+  let mut script_env_entries = String::new();
+  for (name, value) in task_env.iter() {
+    match value {
+      TaskEnvValues::Value(value) => env_entries.push_str(&format!("  ({name:?}, {value:?}),\n")),
+      TaskEnvValues::Script(script) => {
+        script_env_entries.push_str(&format!("  ({name:?}, {:?}),\n", script.join("\n")))
+      }
+    }
+  }
+  let main = XTASK_MAIN_TEMPLATE
+    .replace("{lib_name}", &cli.get_lib_name())
+    .replace("{env_entries}", &env_entries)
+    .replace("{script_env_entries}", &script_env_entries)
+    .replace("{task_entries}", &task_entries)
+    .replace("{arms}", &arms);
+  files.push((XTASK_MAIN_PATH.to_string(), main));
+  Ok(files)
+}
+
+/// The main file of the xtask crate, filled in by [render_xtask]
+const XTASK_MAIN_TEMPLATE: &str = r#"//! Tasks of the `{lib_name}` crate
+//!
+//! Run one with `cargo xtask <task>`, or list them with `cargo xtask`.
+use std::{
+  env,
+  process::{Command, ExitCode},
+};
+
+/// The env of the tasks, filled into `${NAME}` in commands
+const ENV: &[(&str, &str)] = &[
+{env_entries}];
+/// The env of the tasks that bash scripts print in the crate dir
+const SCRIPT_ENV: &[(&str, &str)] = &[
+{script_env_entries}];
+/// Every task and what it does
+const TASKS: &[(&str, &str)] = &[
+{task_entries}];
+
+fn main() -> ExitCode {
+  let args = env::args().skip(1).collect::<Vec<_>>();
+  let Some((task_name, task_args)) = args.split_first() else {
+    println!("Tasks:");
+    for (name, description) in TASKS {
+      println!("  {name:<28} {description}");
+    }
+    return ExitCode::SUCCESS;
+  };
+  match set_env().and_then(|_| run_task(task_name, task_args)) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("error: {e}");
+      ExitCode::FAILURE
+    }
+  }
+}
+
+/// Set the env of the tasks
+fn set_env() -> Result<(), String> {
+  for (name, value) in ENV {
+    env::set_var(name, value);
+  }
+  for (name, script) in SCRIPT_ENV {
+    let output = Command::new("bash")
+      .args(["-c", script])
+      .output()
+      .map_err(|e| format!("Could not work out `{name}`: {e}"))?;
+    env::set_var(name, String::from_utf8_lossy(&output.stdout).trim());
+  }
+  Ok(())
+}
+
+/// Run a task after its dependencies
+fn run_task(
+  task_name: &str,
+  task_args: &[String],
+) -> Result<(), String> {
+  match task_name {
+{arms}    _ => Err(format!(
+      "Unknown task `{task_name}`. Run `cargo xtask` to list the tasks"
+    )),
+  }
+}
+
+/// Fill the env into `${NAME}`
+fn expand(arg: &str) -> String {
+  env::vars().fold(arg.to_string(), |arg, (name, value)| {
+    arg.replace(&format!("${{{name}}}"), &value)
+  })
+}
+
+/// Run a program, with the task args in place of a `${@}` arg
+#[allow(dead_code)]
+fn run_command(
+  program: &str,
+  args: &[&str],
+  task_args: &[String],
+) -> Result<(), String> {
+  let mut command = Command::new(expand(program));
+  for arg in args {
+    match *arg {
+      "${@}" => command.args(task_args),
+      arg => command.arg(expand(arg)),
+    };
+  }
+  run(command)
+}
+
+/// Run a bash script
+#[allow(dead_code)]
+fn run_script(script: &str) -> Result<(), String> {
+  let mut command = Command::new("bash");
+  command.args(["-c", script]);
+  run(command)
+}
+
+/// Check whether the condition script of a task passes
+#[allow(dead_code)]
+fn is_met(condition_script: &str) -> Result<bool, String> {
+  Command::new("bash")
+    .args(["-c", condition_script])
+    .status()
+    .map(|status| status.success())
+    .map_err(|e| format!("Could not run a condition script: {e}"))
+}
+
+/// Run a command, failing if it does
+fn run(mut command: Command) -> Result<(), String> {
+  let status = command
+    .status()
+    .map_err(|e| format!("Could not run {command:?}: {e}"))?;
+  match status.success() {
+    true => Ok(()),
+    false => Err(format!("{command:?} failed with {status}")),
+  }
+}
+"#;
+
+#[cfg(test)]
+mod test_task_runners {
+  use super::*;
+  #[test]
+  fn env_values_refer_to_each_other() {
+    let values = BTreeMap::from_iter(
+      [
+        ("CLI_SUBDIR", "bin/openapitools"),
+        ("CLI_SCRIPT", "openapi-generator-cli"),
+        ("CLI_PATH", "${CLI_SUBDIR}/${CLI_SCRIPT}"),
+      ]
+      .map(|(name, value)| (name.to_string(), value.to_string())),
+    );
+    assert_eq!(
+      expand_env("${CLI_PATH} in ${OUTPUT_DIR}", &values),
+      "bin/openapitools/openapi-generator-cli in ${OUTPUT_DIR}"
+    );
+  }
+  #[test]
+  fn shell_words_are_quoted_unless_plain() {
+    assert_eq!(quote_shell_word("${SPEC_FILE_PATH}"), "${SPEC_FILE_PATH}");
+    assert_eq!(quote_shell_word("${@}"), "\"$@\"");
+    // a literal `*`, like cargo-make passes it, rather than a glob
+    assert_eq!(quote_shell_word("${OUTPUT_DIR}/*"), "\"${OUTPUT_DIR}/*\"");
+    assert_eq!(quote_shell_word("a \"b\""), r#""a \"b\"""#);
+  }
+}
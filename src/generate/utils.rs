@@ -1,22 +1,14 @@
 //! Codegen utilities
-use crate::{cli::Cli, testing};
+use crate::{
+  cli::Cli,
+  process::{self, ProcessError, ProcessSpec},
+  testing,
+};
 use std::{
   env,
-  io::Error as IOError,
   path::{Path, PathBuf},
   process::Output,
 };
-use thiserror::Error;
-use tokio::process::{Child, Command};
-
-/// Testing errors
-#[derive(Debug, Error)]
-pub enum ProcessError {
-  #[error(transparent)]
-  IOError(#[from] IOError),
-  #[error("Process failed \n {0}")]
-  Failure(String),
-}
 
 #[macro_export]
 /// Just makes a vec of specified items from arguments
@@ -26,44 +18,37 @@ macro_rules! vv {
   (as_ref dep_names $($e:expr,)*) => {{ $crate::vv![dep_names $($e.as_ref(),)* ]}};
 }
 
-/// Attempt to run a cargo job
+/// Attempt to run a cargo job, capturing its output. Fails with [ProcessFailure] if the job does.
+///
+/// [ProcessFailure]: crate::process::ProcessFailure
 pub async fn run_cargo_job<T: AsRef<str>, P: AsRef<Path>>(
   args: &[T],
   cwd_opt: Option<P>,
   description_opt: Option<T>,
 ) -> Result<Output, ProcessError> {
-  let mut command = Command::new("cargo");
-  let cwd_string = if let Some(cwd) = cwd_opt.as_ref() {
-    command.current_dir(&cwd.as_ref());
-    format!("in {}", cwd.as_ref().to_string_lossy())
-  } else {
-    String::default()
-  };
-  let args_vec = &Vec::from_iter(args.iter().map(AsRef::as_ref));
-  let arg_string = args_vec
-    .iter()
-    .map(|a| format!("\"{a}\" "))
-    .collect::<String>();
+  let mut spec = ProcessSpec::new("cargo", args);
+  if let Some(cwd) = cwd_opt.as_ref() {
+    spec = spec.cwd(cwd.as_ref());
+  }
   let description = description_opt
     .map(|s| s.as_ref().to_string())
-    .unwrap_or_else(|| format!("Running `cargo` {arg_string} {cwd_string}.",));
-  println!("{description}");
-  let child: Child = command.args(args_vec).spawn()?;
-  child.wait_with_output().await.map_err(ProcessError::from)
+    .unwrap_or_else(|| format!("Running `{spec}`."));
+  process::run(&spec, description).await
 }
 
-/// Attempt to run a cargo make task
-pub async fn run_cargo_make_task<T: AsRef<str>>(
+/// Attempt to run a task of the crate with its task runner, prefixing what it prints with the task
+/// name
+pub async fn run_crate_task<T: AsRef<str>>(
   cli: &Cli,
   task_name: T,
 ) -> Result<Output, ProcessError> {
-  let output_project_dir = &cli.get_output_project_dir();
-  run_cargo_job(
-    &["make", task_name.as_ref()],
-    Some(output_project_dir),
-    None,
-  )
-  .await
+  let task_name = task_name.as_ref();
+  let spec = cli
+    .task_runner
+    .get_task_spec(task_name)
+    .cwd(cli.get_output_project_dir())
+    .label(task_name);
+  process::run(&spec, format!("Running `{spec}`.")).await
 }
 
 /// trim leading whitespace from multiline code resulting in a single string
@@ -83,7 +68,7 @@ pub fn trim_lines_vec(s: &str) -> Vec<String> {
           target = " ".repeat(leading_whitespace);
         }
         if !target.is_empty() {
-          result.push(format!("{}", line.strip_prefix(&target).unwrap_or(line)));
+          result.push(line.strip_prefix(&target).unwrap_or(line).to_string());
         }
         (target, result)
       },
@@ -91,6 +76,45 @@ pub fn trim_lines_vec(s: &str) -> Vec<String> {
     .1
 }
 
+/// Convert an identifier like `getPetById` or `get-pet by id` to snake case (`get_pet_by_id`)
+pub fn to_snake_case(s: &str) -> String {
+  let chars = s.chars().collect::<Vec<_>>();
+  let mut snake = String::with_capacity(s.len());
+  for (i, c) in chars.iter().enumerate() {
+    if c.is_alphanumeric() {
+      if c.is_uppercase() {
+        let is_boundary = match i.checked_sub(1).map(|j| chars[j]) {
+          Some(prev) if prev.is_lowercase() || prev.is_ascii_digit() => true,
+          Some(prev) if prev.is_uppercase() => chars.get(i + 1).is_some_and(|n| n.is_lowercase()),
+          _ => false,
+        };
+        if is_boundary && !snake.is_empty() && !snake.ends_with('_') {
+          snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+      } else {
+        snake.push(*c);
+      }
+    } else if !snake.is_empty() && !snake.ends_with('_') {
+      snake.push('_');
+    }
+  }
+  snake.trim_end_matches('_').to_string()
+}
+/// Convert an identifier like `pet store` or `pet_store` to upper camel case (`PetStore`)
+pub fn to_upper_camel_case(s: &str) -> String {
+  to_snake_case(s)
+    .split('_')
+    .map(|word| {
+      let mut chars = word.chars();
+      match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+        None => String::new(),
+      }
+    })
+    .collect()
+}
+
 /// Get the name of this crate
 pub fn get_this_crate_name() -> &'static str {
   env!("CARGO_CRATE_NAME")
@@ -109,7 +133,7 @@ pub fn get_temp_root_dir() -> PathBuf {
 }
 /// Get temp project subdir
 pub fn get_temp_subdir() -> PathBuf {
-  get_temp_root_dir().join(&format!(
+  get_temp_root_dir().join(format!(
     "{}_{}",
     get_this_crate_name(),
     testing::TEST_SUBDIR_NAME
@@ -127,4 +151,11 @@ mod test_mod_name {
     "#;
     assert_eq!(trim_lines_vec(test), vec!["line zero", "  line one"])
   }
+  #[test]
+  fn case_conversions_work() {
+    assert_eq!(to_snake_case("getPetById"), "get_pet_by_id");
+    assert_eq!(to_snake_case("getHTTPResponse"), "get_http_response");
+    assert_eq!(to_snake_case("get /pets/{petId}"), "get_pets_pet_id");
+    assert_eq!(to_upper_camel_case("pet store"), "PetStore");
+  }
 }
@@ -0,0 +1,255 @@
+//! Makefile validation
+//!
+//! The task files get checked before they are written, so that a broken task fails the generation
+//! instead of the first run of the task. The generated env and tasks may only refer to env entries
+//! of the makefile and depend on tasks of the makefile. A rendered `Makefile.toml` must also load
+//! the way cargo-make loads an external makefile: into its [ExternalConfig], without keys it does not
+//! know. The tasks users added are only checked for the latter, as they may refer to the env of the
+//! shell and depend on the core tasks of cargo-make.
+use crate::generate::makefiles::{MakefileGenerationError, MakefileSpec, TaskNames};
+use cargo_make::types::ExternalConfig;
+use cli as cargo_make;
+use std::{collections::BTreeSet, fmt};
+use thiserror::Error;
+use toml::Value;
+
+/// The prefix of the env entries cargo and cargo-make set themselves
+const CARGO_ENV_PREFIX: &str = "CARGO_";
+/// What stands for the args a task was run with
+const TASK_ARGS_REFERENCE: &str = "@";
+
+/// A problem of a makefile
+#[derive(Debug, Error)]
+pub enum MakefileProblems {
+  #[error("cargo-make cannot load it: {0}")]
+  Unloadable(String),
+  #[error("cargo-make does not know the key `{0}`")]
+  UnknownKey(String),
+  #[error("{location} refers to `${{{name}}}`, which is not in the env")]
+  UndefinedEnvReference { location: String, name: String },
+  #[error("The task `{task}` depends on `{dependency}`, which is not in the makefile")]
+  MissingDependency { task: String, dependency: String },
+  #[error("The tasks depend on each other in a cycle: {}", .0.join(" -> "))]
+  DependencyCycle(Vec<String>),
+}
+
+/// The problems of a makefile
+#[derive(Debug, Error)]
+pub struct MakefileValidationError(pub Vec<MakefileProblems>);
+impl fmt::Display for MakefileValidationError {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    write!(f, "The tasks are not valid:")?;
+    for problem in self.0.iter() {
+      write!(f, "\n  - {problem}")?;
+    }
+    Ok(())
+  }
+}
+
+/// Fail if there are problems
+pub fn ensure_valid(problems: Vec<MakefileProblems>) -> Result<(), MakefileValidationError> {
+  match problems.is_empty() {
+    true => Ok(()),
+    false => Err(MakefileValidationError(problems)),
+  }
+}
+
+/// Check the env references and the dependencies of the generated env and tasks
+pub fn check_spec(spec: &MakefileSpec) -> Result<Vec<MakefileProblems>, MakefileGenerationError> {
+  let mut env_names = spec.env.get_names()?;
+  env_names.extend(spec.user_env.keys().cloned());
+  let mut problems = vec![];
+  if let Value::Table(env) = Value::try_from(&spec.env)? {
+    for (name, value) in env.iter() {
+      check_env_references(
+        &format!("The env entry `{name}`"),
+        value,
+        &env_names,
+        &mut problems,
+      );
+    }
+  }
+  for (name, task) in spec.tasks.iter() {
+    let value = Value::try_from(task.to_cargo_make_task())?;
+    let location = format!("The task `{}`", name.as_ref());
+    check_env_references(&location, &value, &env_names, &mut problems);
+    for dependency in task.dependencies.iter() {
+      if !spec.tasks.contains_key(dependency) {
+        problems.push(MakefileProblems::MissingDependency {
+          task: name.as_ref().to_string(),
+          dependency: dependency.as_ref().to_string(),
+        });
+      }
+    }
+  }
+  problems.extend(find_dependency_cycle(spec).map(MakefileProblems::DependencyCycle));
+  Ok(problems)
+}
+
+/// Check that a rendered `Makefile.toml` loads into the config of cargo-make
+pub fn check_makefile(makefile: &str) -> Vec<MakefileProblems> {
+  let mut unknown_keys = vec![];
+  let deserializer = toml::Deserializer::new(makefile);
+  let result: Result<ExternalConfig, _> = serde_ignored::deserialize(deserializer, |path| {
+    // optional values show up as `?` segments
+    let key = path
+      .to_string()
+      .split('.')
+      .filter(|segment| *segment != "?")
+      .collect::<Vec<_>>()
+      .join(".");
+    unknown_keys.push(MakefileProblems::UnknownKey(key))
+  });
+  match result {
+    Ok(_) => unknown_keys,
+    Err(e) => vec![MakefileProblems::Unloadable(e.to_string())],
+  }
+}
+
+/// Check the `${NAME}` references in the strings of a value
+fn check_env_references(
+  location: &str,
+  value: &Value,
+  env_names: &BTreeSet<String>,
+  problems: &mut Vec<MakefileProblems>,
+) {
+  match value {
+    Value::String(text) => {
+      for name in get_env_references(text) {
+        let is_defined = name == TASK_ARGS_REFERENCE
+          || env_names.contains(name)
+          || name.starts_with(CARGO_ENV_PREFIX);
+        if !is_defined {
+          problems.push(MakefileProblems::UndefinedEnvReference {
+            location: location.to_string(),
+            name: name.to_string(),
+          });
+        }
+      }
+    }
+    Value::Array(values) => values
+      .iter()
+      .for_each(|value| check_env_references(location, value, env_names, problems)),
+    Value::Table(table) => table
+      .values()
+      .for_each(|value| check_env_references(location, value, env_names, problems)),
+    _ => {}
+  }
+}
+
+/// Get the names of the `${NAME}` references in a text
+fn get_env_references(text: &str) -> Vec<&str> {
+  text
+    .split("${")
+    .skip(1)
+    .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+    .collect()
+}
+
+/// Find a cycle of the dependencies of the generated tasks, if there is one
+fn find_dependency_cycle(spec: &MakefileSpec) -> Option<Vec<String>> {
+  /// Walk the dependencies of a task, depth first
+  fn visit(
+    spec: &MakefileSpec,
+    name: TaskNames,
+    path: &mut Vec<TaskNames>,
+    done: &mut BTreeSet<TaskNames>,
+  ) -> Option<Vec<String>> {
+    if let Some(start) = path.iter().position(|visited| *visited == name) {
+      let mut cycle: Vec<String> = path[start..]
+        .iter()
+        .map(|name| name.as_ref().to_string())
+        .collect();
+      cycle.push(name.as_ref().to_string());
+      return Some(cycle);
+    }
+    if done.contains(&name) {
+      return None;
+    }
+    path.push(name);
+    let dependencies = spec
+      .tasks
+      .get(&name)
+      .map(|task| task.dependencies.clone())
+      .unwrap_or_default();
+    for dependency in dependencies {
+      if let Some(cycle) = visit(spec, dependency, path, done) {
+        return Some(cycle);
+      }
+    }
+    path.pop();
+    done.insert(name);
+    None
+  }
+  let mut done = BTreeSet::new();
+  spec
+    .tasks
+    .keys()
+    .find_map(|name| visit(spec, *name, &mut vec![], &mut done))
+}
+
+#[cfg(test)]
+mod test_validations {
+  use super::*;
+  use crate::testing;
+
+  /// Get the makefile of a generation
+  fn get_spec() -> MakefileSpec {
+    let cli = testing::get_test_cli();
+    MakefileSpec::try_from(&cli).expect("must make makefile")
+  }
+
+  #[test]
+  fn passes_the_generated_makefile() {
+    let spec = get_spec();
+    let makefile = spec.to_makefile().expect("must render makefile");
+    assert!(check_spec(&spec).expect("must check spec").is_empty());
+    assert!(check_makefile(&makefile).is_empty());
+  }
+
+  #[test]
+  fn finds_broken_references_and_dependencies() {
+    let mut spec = get_spec();
+    let crate_scaffold = spec
+      .tasks
+      .get_mut(&TaskNames::CrateScaffold)
+      .expect("must have task");
+    crate_scaffold.description = "Setup ${LIB_NAME} in ${NOWHERE}".to_string();
+    crate_scaffold
+      .dependencies
+      .push(TaskNames::SpecDownloadDefault);
+    crate_scaffold.dependencies.push(TaskNames::GenerateAll);
+    spec
+      .tasks
+      .get_mut(&TaskNames::GenerateAll)
+      .expect("must have task")
+      .dependencies
+      .push(TaskNames::CrateScaffold);
+    let problems = check_spec(&spec).expect("must check spec");
+    assert!(matches!(
+      problems.as_slice(),
+      [
+        MakefileProblems::UndefinedEnvReference { name, .. },
+        MakefileProblems::MissingDependency { dependency, .. },
+        MakefileProblems::DependencyCycle(cycle),
+      ] if name == "NOWHERE" && dependency == "spec-download-default" && cycle.len() == 3
+    ));
+  }
+
+  #[test]
+  fn finds_keys_cargo_make_does_not_know() {
+    let problems = check_makefile("[tasks.docs]\ncommand = \"cargo\"\ncomand = \"cargo\"\n");
+    assert!(matches!(
+      problems.as_slice(),
+      [MakefileProblems::UnknownKey(key)] if key == "tasks.docs.comand"
+    ));
+    let problems = check_makefile("[tasks.docs]\ndependencies = \"generate-all\"\n");
+    assert!(matches!(
+      problems.as_slice(),
+      [MakefileProblems::Unloadable(_)]
+    ));
+  }
+}
@@ -0,0 +1,680 @@
+//! Cargo workspace membership
+//!
+//! With `--workspace`, the crate is generated as a member of an existing workspace: it is listed in
+//! the root `[workspace] members`, its registry dependencies are moved into
+//! `[workspace.dependencies]` and the root makefile gets a task that regenerates it. Manifests are
+//! edited with `toml_edit` so that the formatting and comments of the root files survive.
+use crate::{
+  cli::Paths,
+  fs,
+  generate::{
+    makefiles::{MakefileEnv, NamedTask, TaskNames},
+    task_runners::TaskRunners,
+  },
+};
+use std::{
+  io::{Error as IOError, ErrorKind},
+  path::{Component, Path, PathBuf},
+};
+use strum::EnumProperty;
+use thiserror::Error;
+use toml_edit::{table, value, Array, Document, InlineTable, Item, Table, TomlError, Value};
+
+/// Workspace errors
+#[derive(Debug, Error)]
+pub enum WorkspaceError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  TomlError(#[from] TomlError),
+  #[error("No Cargo.toml with a `[workspace]` table in {0}")]
+  NotAWorkspace(PathBuf),
+  #[error("The `tasks` of the makefile {0} are not a table")]
+  InvalidMakefile(PathBuf),
+  #[error("The crate dir {crate_dir} is not inside of the workspace {workspace_dir}")]
+  OutsideWorkspace {
+    crate_dir: PathBuf,
+    workspace_dir: PathBuf,
+  },
+}
+
+/// The root makefile task that regenerates every client of the workspace
+pub const GENERATE_CLIENTS_TASK_NAME: &str = "generate-clients";
+/// Dependency keys that can move into `[workspace.dependencies]`; path, git, registry and renamed
+/// dependencies stay with the member
+const HOISTABLE_DEPENDENCY_KEYS: &[&str] = &[
+  "version",
+  "features",
+  "optional",
+  "default-features",
+  "default_features",
+];
+/// Dependency tables of a member manifest
+const DEPENDENCY_TABLE_NAMES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Get the root makefile task that regenerates a client
+pub fn get_client_task_name(lib_name: &str) -> String {
+  format!("generate-client-{}", lib_name.replace('_', "-"))
+}
+
+/// Resolve `.` and `..` in a path without touching the disk
+pub fn normalize_path(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir => {
+        normalized.pop();
+      }
+      component => normalized.push(component),
+    }
+  }
+  normalized
+}
+
+/// Get a path relative to a dir, going up out of the dir as far as needed
+///
+/// Paths that share no root with the dir are returned as they are.
+pub fn get_path_relative_to(
+  path: &Path,
+  dir: &Path,
+) -> PathBuf {
+  let path = normalize_path(path);
+  let dir = normalize_path(dir);
+  let common_len = path
+    .components()
+    .zip(dir.components())
+    .take_while(|(path_component, dir_component)| path_component == dir_component)
+    .count();
+  if common_len == 0 {
+    return path;
+  }
+  dir
+    .components()
+    .skip(common_len)
+    .map(|_| Component::ParentDir)
+    .chain(path.components().skip(common_len))
+    .collect()
+}
+
+/// Get the path of a crate relative to the workspace, as it is listed in `members`
+pub fn get_member_path(
+  workspace_dir: &Path,
+  crate_dir: &Path,
+) -> Result<String, WorkspaceError> {
+  let outside_workspace = || WorkspaceError::OutsideWorkspace {
+    crate_dir: crate_dir.to_path_buf(),
+    workspace_dir: workspace_dir.to_path_buf(),
+  };
+  let crate_dir_normalized = normalize_path(crate_dir);
+  let relative_path = crate_dir_normalized
+    .strip_prefix(normalize_path(workspace_dir))
+    .map_err(|_| outside_workspace())?;
+  let member_path = relative_path
+    .components()
+    .map(|component| component.as_os_str().to_string_lossy().to_string())
+    .collect::<Vec<_>>()
+    .join("/");
+  match member_path.is_empty() {
+    true => Err(outside_workspace()),
+    false => Ok(member_path),
+  }
+}
+
+/// Whether a path segment matches a `members` glob segment, where `*` matches any characters
+fn matches_glob_segment(
+  pattern: &str,
+  segment: &str,
+) -> bool {
+  match pattern.split_once('*') {
+    None => pattern == segment,
+    Some((prefix, rest)) => {
+      segment.starts_with(prefix)
+        && segment
+          .char_indices()
+          .map(|(i, _)| i)
+          .chain([segment.len()])
+          .filter(|i| *i >= prefix.len())
+          .any(|i| matches_glob_segment(rest, &segment[i..]))
+    }
+  }
+}
+
+/// Whether a member path matches a `members` entry
+fn matches_member_pattern(
+  pattern: &str,
+  member_path: &str,
+) -> bool {
+  let pattern_segments = pattern.trim_end_matches('/').split('/').collect::<Vec<_>>();
+  let path_segments = member_path.split('/').collect::<Vec<_>>();
+  pattern_segments.len() == path_segments.len()
+    && pattern_segments
+      .iter()
+      .zip(path_segments.iter())
+      .all(|(pattern, segment)| matches_glob_segment(pattern, segment))
+}
+
+/// Split a version requirement like `^1.2` into its numbers, if it is that simple
+pub fn parse_version_numbers(version: &str) -> Option<Vec<u64>> {
+  version
+    .trim()
+    .trim_start_matches(['^', '=', '~'])
+    .split('.')
+    .map(|number| number.trim().parse().ok())
+    .collect()
+}
+
+/// Get the numbers that have to match for two versions to be semver compatible
+fn get_compatibility_key(version_numbers: &[u64]) -> &[u64] {
+  match version_numbers {
+    [0, ..] => &version_numbers[..version_numbers.len().min(2)],
+    _ => &version_numbers[..version_numbers.len().min(1)],
+  }
+}
+
+/// The version and default features of a dependency
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct DependencyRequirement {
+  version: String,
+  default_features: bool,
+}
+impl DependencyRequirement {
+  /// Read a dependency given as a version string or a table with a version
+  fn read(dependency: &Item) -> Option<Self> {
+    if let Some(version) = dependency.as_str() {
+      return Some(Self {
+        version: version.to_string(),
+        default_features: true,
+      });
+    }
+    let dependency = dependency.as_table_like()?;
+    let default_features = dependency
+      .get("default-features")
+      .or_else(|| dependency.get("default_features"))
+      .and_then(Item::as_bool)
+      .unwrap_or(true);
+    Some(Self {
+      version: dependency.get("version")?.as_str()?.to_string(),
+      default_features,
+    })
+  }
+  /// Make the `[workspace.dependencies]` entry
+  fn to_workspace_dependency(&self) -> Item {
+    if self.default_features {
+      return value(self.version.as_str());
+    }
+    let mut dependency = InlineTable::new();
+    dependency.insert("version", self.version.as_str().into());
+    dependency.insert("default-features", false.into());
+    value(dependency)
+  }
+}
+
+/// The root manifest of a workspace
+#[derive(Debug)]
+pub struct WorkspaceManifest {
+  /// The workspace dir
+  dir: PathBuf,
+  document: Document,
+}
+impl WorkspaceManifest {
+  /// Get the path of the root manifest
+  fn get_path(dir: &Path) -> PathBuf {
+    dir.join(
+      Paths::CargoTomlFile
+        .get_str("path")
+        .expect("must get Cargo.toml path"),
+    )
+  }
+  /// Read the root manifest of the workspace in a dir
+  pub async fn read(dir: impl AsRef<Path>) -> Result<Self, WorkspaceError> {
+    let dir = dir.as_ref();
+    let contents = match fs::read_to_string(Self::get_path(dir)).await {
+      Ok(contents) => contents,
+      Err(e) if e.kind() == ErrorKind::NotFound => {
+        return Err(WorkspaceError::NotAWorkspace(dir.to_path_buf()));
+      }
+      Err(e) => return Err(e.into()),
+    };
+    let document = contents.parse::<Document>()?;
+    if !document.get("workspace").is_some_and(Item::is_table_like) {
+      return Err(WorkspaceError::NotAWorkspace(dir.to_path_buf()));
+    }
+    Ok(Self {
+      dir: dir.to_path_buf(),
+      document,
+    })
+  }
+  /// Write the root manifest back
+  pub async fn write(&self) -> Result<(), WorkspaceError> {
+    fs::write(
+      Self::get_path(&self.dir),
+      self.document.to_string(),
+      Some("Workspace manifest"),
+    )
+    .await?;
+    Ok(())
+  }
+  /// Get the strings of a `[workspace]` array
+  fn get_workspace_strings(
+    &self,
+    key: &str,
+  ) -> Vec<&str> {
+    self.document["workspace"]
+      .get(key)
+      .and_then(Item::as_array)
+      .map(|array| array.iter().filter_map(Value::as_str).collect())
+      .unwrap_or_default()
+  }
+  /// Whether a member path is already part of the workspace, listed or matched by a glob
+  pub fn has_member(
+    &self,
+    member_path: &str,
+  ) -> bool {
+    !self.get_workspace_strings("exclude").contains(&member_path)
+      && self
+        .get_workspace_strings("members")
+        .iter()
+        .any(|pattern| matches_member_pattern(pattern, member_path))
+  }
+  /// Get the `members` array, adding it if missing
+  fn get_members_mut(&mut self) -> Option<&mut Array> {
+    self.document["workspace"]["members"]
+      .or_insert(value(Array::new()))
+      .as_array_mut()
+  }
+  /// List a member
+  pub fn add_member(
+    &mut self,
+    member_path: &str,
+  ) {
+    if let Some(members) = self.get_members_mut() {
+      members.push(member_path);
+    }
+  }
+  /// Unlist a member
+  pub fn remove_member(
+    &mut self,
+    member_path: &str,
+  ) {
+    if let Some(members) = self.get_members_mut() {
+      members.retain(|member| member.as_str() != Some(member_path));
+    }
+  }
+  /// Move the registry dependencies of a member manifest into `[workspace.dependencies]`
+  ///
+  /// The member keeps its features and whether the dependency is optional. A dependency stays
+  /// with the member if the workspace has a semver incompatible version of it or disagrees on
+  /// default features. Returns the names of the moved dependencies.
+  pub fn hoist_dependencies(
+    &mut self,
+    member_document: &mut Document,
+  ) -> Vec<String> {
+    let mut hoisted_names = vec![];
+    for table_name in DEPENDENCY_TABLE_NAMES {
+      let Some(dependencies) = member_document
+        .get_mut(table_name)
+        .and_then(Item::as_table_like_mut)
+      else {
+        continue;
+      };
+      for (name, dependency) in dependencies.iter_mut() {
+        if let Some(inherited_dependency) = self.hoist_dependency(name.get(), dependency) {
+          *dependency = inherited_dependency;
+          hoisted_names.push(name.get().to_string());
+        }
+      }
+    }
+    hoisted_names
+  }
+  /// Add or update the workspace entry of a dependency, getting what the member should inherit
+  fn hoist_dependency(
+    &mut self,
+    name: &str,
+    dependency: &Item,
+  ) -> Option<Item> {
+    if let Some(dependency) = dependency.as_table_like() {
+      if dependency
+        .iter()
+        .any(|(key, _)| !HOISTABLE_DEPENDENCY_KEYS.contains(&key))
+      {
+        return None;
+      }
+    }
+    let requirement = DependencyRequirement::read(dependency)?;
+    let version_numbers = parse_version_numbers(&requirement.version)?;
+    let workspace_dependencies = self.document["workspace"]
+      .as_table_mut()?
+      .entry("dependencies")
+      .or_insert(table())
+      .as_table_like_mut()?;
+    match workspace_dependencies.get_mut(name) {
+      Some(workspace_dependency) => {
+        let workspace_requirement = DependencyRequirement::read(workspace_dependency)?;
+        let workspace_version_numbers = parse_version_numbers(&workspace_requirement.version)?;
+        if get_compatibility_key(&workspace_version_numbers)
+          != get_compatibility_key(&version_numbers)
+          || workspace_requirement.default_features != requirement.default_features
+        {
+          return None;
+        }
+        if version_numbers > workspace_version_numbers {
+          match workspace_dependency.as_table_like_mut() {
+            Some(workspace_dependency) => {
+              workspace_dependency.insert("version", value(requirement.version.as_str()));
+            }
+            None => *workspace_dependency = value(requirement.version.as_str()),
+          }
+        }
+      }
+      None => {
+        workspace_dependencies.insert(name, requirement.to_workspace_dependency());
+      }
+    }
+    let mut inherited_dependency = InlineTable::new();
+    inherited_dependency.insert("workspace", true.into());
+    if let Some(dependency) = dependency.as_table_like() {
+      for key in ["features", "optional"] {
+        if let Some(item_value) = dependency.get(key).and_then(Item::as_value) {
+          let mut item_value = item_value.clone();
+          item_value.decor_mut().clear();
+          inherited_dependency.insert(key, item_value);
+        }
+      }
+    }
+    Some(value(inherited_dependency))
+  }
+}
+
+/// Add a task regenerating a member client to the root makefile, and to the task regenerating all
+///
+/// The root makefile is created if the workspace has none.
+#[tracing::instrument(level = "debug", skip_all, fields(?workspace_dir, %member_path))]
+pub async fn register_client_task(
+  workspace_dir: &Path,
+  member_path: &str,
+  lib_name: &str,
+  task_runner: TaskRunners,
+) -> Result<(), WorkspaceError> {
+  let makefile_path = workspace_dir.join(MakefileEnv::MAKEFILE_NAME);
+  let mut document = match fs::read_to_string(&makefile_path).await {
+    Ok(contents) => contents.parse::<Document>()?,
+    Err(e) if e.kind() == ErrorKind::NotFound => Document::new(),
+    Err(e) => return Err(e.into()),
+  };
+  let category = NamedTask::default_category().unwrap_or_default();
+  let task_name = get_client_task_name(lib_name);
+  let mut client_task = Table::new();
+  client_task["category"] = value(category.as_str());
+  client_task["description"] = value(format!("Regenerate the `{lib_name}` client"));
+  // run once from the root instead of in every member
+  client_task["workspace"] = value(false);
+  client_task["cwd"] = value(member_path);
+  let task_spec = task_runner.get_task_spec(TaskNames::GenerateAll.as_ref());
+  client_task["command"] = value(task_spec.program);
+  client_task["args"] = value(Array::from_iter(task_spec.args));
+  let mut new_tasks = Table::new();
+  new_tasks.set_implicit(true);
+  let tasks = document
+    .entry("tasks")
+    .or_insert(Item::Table(new_tasks))
+    .as_table_mut()
+    .ok_or_else(|| WorkspaceError::InvalidMakefile(makefile_path.clone()))?;
+  tasks.insert(&task_name, Item::Table(client_task));
+  let clients_task = tasks.entry(GENERATE_CLIENTS_TASK_NAME).or_insert_with(|| {
+    let mut new_clients_task = Table::new();
+    new_clients_task["category"] = value(category.as_str());
+    new_clients_task["description"] = value("Regenerate every client of the workspace");
+    new_clients_task["workspace"] = value(false);
+    Item::Table(new_clients_task)
+  });
+  if let Some(dependencies) = clients_task["dependencies"]
+    .or_insert(value(Array::new()))
+    .as_array_mut()
+  {
+    if !dependencies
+      .iter()
+      .any(|dependency| dependency.as_str() == Some(&task_name))
+    {
+      dependencies.push(task_name.as_str());
+    }
+  }
+  fs::write(
+    &makefile_path,
+    document.to_string(),
+    Some("Workspace makefile"),
+  )
+  .await?;
+  Ok(())
+}
+
+/// The root files of a workspace as they were before generation, to restore if it fails
+#[derive(Debug)]
+pub struct WorkspaceSnapshot {
+  /// Each file with its contents, if it existed
+  files: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+impl WorkspaceSnapshot {
+  /// The root files generation may change
+  const FILE_NAMES: &'static [&'static str] =
+    &["Cargo.toml", "Cargo.lock", MakefileEnv::MAKEFILE_NAME];
+  /// Remember the root files of a workspace
+  pub async fn take(dir: impl AsRef<Path>) -> Result<Self, WorkspaceError> {
+    let mut files = vec![];
+    for file_name in Self::FILE_NAMES {
+      let path = dir.as_ref().join(file_name);
+      let contents_opt = match fs::read(&path).await {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+      };
+      files.push((path, contents_opt));
+    }
+    Ok(Self { files })
+  }
+  /// Write back the root files that changed, and remove the ones that did not exist
+  pub async fn restore(&self) -> Result<(), WorkspaceError> {
+    for (path, contents_opt) in self.files.iter() {
+      match contents_opt {
+        Some(contents) => {
+          if fs::read(path).await.ok().as_ref() != Some(contents) {
+            fs::write(path, contents, Some("Restored workspace file")).await?;
+          }
+        }
+        None => {
+          if fs::is_file(path).await {
+            fs::remove_file(path).await?;
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test_workspaces {
+  use super::*;
+  use crate::{
+    builder::GeneratorBuilder,
+    fs::{FileSystem, MemoryFileSystem},
+    process::RecordingProcessRunner,
+    testing,
+  };
+  use std::sync::Arc;
+  use url::Url;
+  #[test]
+  fn members_match_globs_but_not_excludes() {
+    let document = r#"
+      [workspace]
+      members = ["tools/cli", "clients/*"]
+      exclude = ["clients/legacy"]
+    "#
+    .parse::<Document>()
+    .expect("must parse manifest");
+    let manifest = WorkspaceManifest {
+      dir: PathBuf::from("/repo"),
+      document,
+    };
+    assert!(manifest.has_member("tools/cli"));
+    assert!(manifest.has_member("clients/pets"));
+    assert!(!manifest.has_member("clients/legacy"));
+    assert!(!manifest.has_member("clients/pets/nested"));
+    assert!(!manifest.has_member("pets"));
+    assert_eq!(
+      get_member_path(Path::new("/repo"), Path::new("/repo/tools/../clients/pets"))
+        .expect("must get member path"),
+      "clients/pets"
+    );
+    assert!(get_member_path(Path::new("/repo"), Path::new("/elsewhere/pets")).is_err());
+  }
+  #[test]
+  fn dependencies_are_hoisted_if_compatible() {
+    let mut manifest = WorkspaceManifest {
+      dir: PathBuf::from("/repo"),
+      document: r#"
+        # shared by every client
+        [workspace]
+        members = []
+
+        [workspace.dependencies]
+        serde = "1.0.100"
+        reqwest = "0.10"
+      "#
+      .parse()
+      .expect("must parse workspace manifest"),
+    };
+    let mut member_document = r#"
+      [package]
+      name = "pets"
+
+      [dependencies]
+      serde = { version = "^1.0.160", features = ["derive"] }
+      reqwest = { version = "^0.11", features = ["json", "multipart"] }
+      url = "^2.2"
+      uuid = { version = "^1.0", default-features = false }
+      local = { path = "../local" }
+
+      [dev-dependencies]
+      tokio = "1"
+    "#
+    .parse::<Document>()
+    .expect("must parse member manifest");
+    let hoisted_names = manifest.hoist_dependencies(&mut member_document);
+    assert_eq!(hoisted_names, ["serde", "url", "uuid", "tokio"]);
+    let workspace_dependencies = manifest.document["workspace"]["dependencies"].to_string();
+    assert!(workspace_dependencies.contains(r#"serde = "^1.0.160""#));
+    assert!(workspace_dependencies.contains(r#"reqwest = "0.10""#));
+    assert!(
+      workspace_dependencies.contains(r#"uuid = { version = "^1.0", default-features = false }"#)
+    );
+    assert!(manifest
+      .document
+      .to_string()
+      .contains("# shared by every client"));
+    let dependencies = member_document["dependencies"].to_string();
+    assert!(dependencies.contains(r#"serde = { workspace = true, features = ["derive"] }"#));
+    assert!(dependencies.contains(r#"reqwest = { version = "^0.11""#));
+    assert!(dependencies.contains("url = { workspace = true }"));
+    assert!(dependencies.contains(r#"local = { path = "../local" }"#));
+  }
+  #[tokio::test]
+  async fn generates_a_workspace_member() {
+    let workspace_dir = Path::new("/virtual/repo");
+    let spec_path = Path::new("/virtual/spec.yaml");
+    let file_system = Arc::new(MemoryFileSystem::new());
+    for (path, contents) in [
+      (
+        workspace_dir.join("Cargo.toml"),
+        "[workspace]\nmembers = []\n",
+      ),
+      (spec_path.to_path_buf(), "openapi: 3.0.0\n"),
+    ] {
+      file_system
+        .write(&path, contents.as_bytes())
+        .await
+        .expect("must write file");
+    }
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let report = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file(spec_path)
+      .workspace(workspace_dir)
+      .autogenerate(false)
+      .build()
+      .expect("must build request")
+      .with_file_system(file_system.clone())
+      .with_process_runner(Arc::new(RecordingProcessRunner::default()))
+      .run()
+      .await
+      .expect("must generate");
+    assert_eq!(
+      report.output_dir,
+      workspace_dir.join("pet_shoppe_openapi_client")
+    );
+    let files = file_system.get_files();
+    let root_manifest = String::from_utf8_lossy(&files[&workspace_dir.join("Cargo.toml")]);
+    assert_eq!(
+      root_manifest,
+      "[workspace]\nmembers = [\"pet_shoppe_openapi_client\"]\n"
+    );
+    let root_makefile = String::from_utf8_lossy(&files[&workspace_dir.join("Makefile.toml")]);
+    assert!(root_makefile.contains("[tasks.generate-client-pet-shoppe-openapi-client]"));
+    assert!(root_makefile.contains(r#"cwd = "pet_shoppe_openapi_client""#));
+    assert!(
+      root_makefile.contains(r#"dependencies = ["generate-client-pet-shoppe-openapi-client"]"#)
+    );
+  }
+  #[tokio::test]
+  async fn failing_to_register_the_client_task_restores_the_workspace() {
+    let workspace_dir = Path::new("/virtual/repo");
+    let spec_path = Path::new("/virtual/spec.yaml");
+    let (root_manifest, root_makefile) = ("[workspace]\nmembers = []\n", "tasks = 1\n");
+    let file_system = Arc::new(MemoryFileSystem::new());
+    for (path, contents) in [
+      (workspace_dir.join("Cargo.toml"), root_manifest),
+      (workspace_dir.join("Makefile.toml"), root_makefile),
+      (spec_path.to_path_buf(), "openapi: 3.0.0\n"),
+    ] {
+      file_system
+        .write(&path, contents.as_bytes())
+        .await
+        .expect("must write file");
+    }
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let result = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file(spec_path)
+      .workspace(workspace_dir)
+      .autogenerate(false)
+      .build()
+      .expect("must build request")
+      .with_file_system(file_system.clone())
+      .with_process_runner(Arc::new(RecordingProcessRunner::default()))
+      .run()
+      .await;
+    assert!(result.is_err());
+    let files = file_system.get_files();
+    assert_eq!(
+      String::from_utf8_lossy(&files[&workspace_dir.join("Cargo.toml")]),
+      root_manifest
+    );
+    assert_eq!(
+      String::from_utf8_lossy(&files[&workspace_dir.join("Makefile.toml")]),
+      root_makefile
+    );
+    assert!(
+      !file_system
+        .is_dir(&workspace_dir.join("pet_shoppe_openapi_client"))
+        .await
+    );
+    assert_eq!(
+      file_system
+        .read_dir(workspace_dir)
+        .await
+        .expect("must list workspace"),
+      [
+        workspace_dir.join("Cargo.toml"),
+        workspace_dir.join("Makefile.toml")
+      ]
+    );
+  }
+}
@@ -2,11 +2,10 @@
 
 use crate::{
   cli::Cli,
-  fs::write,
+  fs::{self, write},
   generate::{errors::ParameterError, makefiles::MakefileEnv},
   testing,
 };
-use fs_err::tokio as fs;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Error as SerdeYAMLError;
 use std::io::Error as IOError;
@@ -80,20 +79,26 @@ impl OpenAPIRustGeneratorConfigs {
     }
   }
   /// Copy spec file if applicable
+  #[tracing::instrument(level = "debug", skip_all)]
   pub async fn copy_spec_file(
     &self,
     cli: &Cli,
   ) -> Result<(), YAMLGenerationError> {
     if let Some(local_api_spec_filepath) = cli.inner_cli.local_api_spec_filepath_opt.as_ref() {
-      let spec_file_name = cli.try_get_spec_file_name()?;
-      let contents = fs::read(local_api_spec_filepath).await?;
-      write(spec_file_name, contents, Some("Copy spec file")).await?;
+      let spec_file_path = cli
+        .get_output_project_dir()
+        .join(cli.try_get_spec_file_name()?);
+      if local_api_spec_filepath != &spec_file_path {
+        let contents = fs::read(local_api_spec_filepath).await?;
+        write(spec_file_path, contents, Some("Copy spec file")).await?;
+      }
       Ok(())
     } else {
       Ok(())
     }
   }
   /// Write configs to yaml file
+  #[tracing::instrument(level = "debug", skip_all)]
   pub async fn write_to_yaml_file(
     &self,
     cli: &Cli,
@@ -114,15 +119,12 @@ impl OpenAPIRustGeneratorConfigs {
 /// Create a testing spec file in given directory
 ///
 /// Returns the name of the spec created
+#[tracing::instrument(level = "debug", skip_all)]
 pub async fn create_testing_spec_file(cli: &Cli) -> Result<(), YAMLGenerationError> {
   let petstore_yaml: &'static str = testing::PETSTORE_YAML;
-  let output_file_path = cli
-    .inner_cli
-    .local_api_spec_filepath_opt
-    .clone()
-    .ok_or_else(|| {
-      YAMLGenerationError::ParameterError(ParameterError::TestingYAMLSpecPathMissing)
-    })?;
+  let output_file_path = cli.inner_cli.local_api_spec_filepath_opt.clone().ok_or(
+    YAMLGenerationError::ParameterError(ParameterError::TestingYAMLSpecPathMissing),
+  )?;
   write(
     &output_file_path,
     petstore_yaml,
@@ -1,6 +1,14 @@
 #![doc = include_str!("../README.md")]
 
+pub mod batch;
+pub mod builder;
 pub mod cli;
+pub mod diagnostics;
+pub mod events;
 pub mod fs;
 pub mod generate;
+pub mod logging;
+pub mod process;
+pub mod self_update;
 pub mod testing;
+pub mod wizard;
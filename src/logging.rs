@@ -0,0 +1,226 @@
+//! Logging
+//!
+//! Generation is instrumented with `tracing`. [init] installs a subscriber that prints to stderr at the
+//! level asked for with `-v`/`-q` and also keeps every record at debug level and above in memory, in
+//! the buffer of the run in scope (see [with_run_log]). The kept records, subprocess output included,
+//! are written to the generated crate's log file ([Paths::LogFile]) once the run is over (see
+//! [write_run_log]). At most [LogBuffer::MAX_LEN] bytes
+//! are kept, later records are dropped.
+use crate::{
+  cli::{Cli, Paths},
+  fs,
+  generate::utils::{get_temp_root_dir, get_this_crate_name},
+};
+use std::{
+  future::Future,
+  io::{self, Error as IOError, ErrorKind, Write},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+use tracing::Subscriber;
+use tracing_subscriber::{
+  filter::{filter_fn, LevelFilter},
+  fmt::{self, MakeWriter},
+  prelude::*,
+  registry::LookupSpan,
+  Layer,
+};
+
+/// The `tracing` target of logged [GenerationEvent](crate::events::GenerationEvent)s
+///
+/// Observers already show events, so the terminal log leaves them out.
+pub const EVENTS_TARGET: &str = "olg::events";
+
+/// The records kept so far, and the length of the ones dropped past the limit
+#[derive(Debug, Default)]
+struct LogRecords {
+  contents: Vec<u8>,
+  dropped_len: usize,
+}
+
+/// Log records kept in memory until they can be written to a log file
+#[derive(Clone, Debug, Default)]
+pub struct LogBuffer(Arc<Mutex<LogRecords>>);
+impl LogBuffer {
+  /// The most bytes of records kept until they are taken
+  pub const MAX_LEN: usize = 16 * 1024 * 1024;
+  /// Take the records logged so far, noting how much was dropped past [Self::MAX_LEN]
+  pub fn take(&self) -> Vec<u8> {
+    let LogRecords {
+      mut contents,
+      dropped_len,
+    } = std::mem::take(&mut *self.0.lock().expect("must lock log buffer"));
+    if dropped_len > 0 {
+      contents.extend_from_slice(
+        format!(
+          "{dropped_len} bytes of log records were dropped past the limit of {} bytes\n",
+          Self::MAX_LEN
+        )
+        .as_bytes(),
+      );
+    }
+    contents
+  }
+}
+impl Write for LogBuffer {
+  fn write(
+    &mut self,
+    buf: &[u8],
+  ) -> io::Result<usize> {
+    let mut records = self.0.lock().expect("must lock log buffer");
+    if records.contents.len() + buf.len() > Self::MAX_LEN {
+      records.dropped_len += buf.len();
+    } else {
+      records.contents.extend_from_slice(buf);
+    }
+    Ok(buf.len())
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+tokio::task_local! {
+  static RUN_LOG: LogBuffer;
+}
+
+/// Run a future with its log records kept in a buffer of its own
+///
+/// Records logged outside of any run are not kept.
+pub async fn with_run_log<F: Future>(f: F) -> F::Output {
+  RUN_LOG.scope(LogBuffer::default(), f).await
+}
+
+/// Writes log records to the buffer of the run in scope, if any
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunLogWriter;
+impl Write for RunLogWriter {
+  fn write(
+    &mut self,
+    buf: &[u8],
+  ) -> io::Result<usize> {
+    RUN_LOG
+      .try_with(|buffer| buffer.clone().write(buf))
+      .unwrap_or(Ok(buf.len()))
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+impl<'a> MakeWriter<'a> for RunLogWriter {
+  type Writer = RunLogWriter;
+  fn make_writer(&'a self) -> Self::Writer {
+    *self
+  }
+}
+
+/// Get the terminal log level for a verbosity (`-v` count minus `-q` count)
+pub fn get_terminal_level_filter(verbosity: i8) -> LevelFilter {
+  match verbosity {
+    i8::MIN..=-2 => LevelFilter::OFF,
+    -1 => LevelFilter::ERROR,
+    0 => LevelFilter::WARN,
+    1 => LevelFilter::INFO,
+    2 => LevelFilter::DEBUG,
+    _ => LevelFilter::TRACE,
+  }
+}
+
+/// Get the layer keeping the records at debug level and above in the buffer of the run in scope
+pub fn get_run_log_layer<S>() -> impl Layer<S>
+where
+  S: Subscriber + for<'a> LookupSpan<'a>,
+{
+  fmt::layer()
+    .with_ansi(false)
+    .with_writer(RunLogWriter)
+    .with_filter(LevelFilter::DEBUG)
+}
+
+/// Install the global subscriber. Does nothing if one is already installed.
+pub fn init(verbosity: i8) {
+  let terminal_layer = fmt::layer()
+    .with_writer(io::stderr)
+    .with_filter(get_terminal_level_filter(verbosity))
+    .with_filter(filter_fn(|metadata| metadata.target() != EVENTS_TARGET));
+  let _ = tracing_subscriber::registry()
+    .with(get_run_log_layer())
+    .with(terminal_layer)
+    .try_init();
+}
+
+/// Get where to write the log of a run whose crate dir is gone
+pub fn get_fallback_log_file_path(cli: &Cli) -> PathBuf {
+  get_temp_root_dir().join(format!(
+    "{}-{}-{}.log",
+    get_this_crate_name(),
+    cli.get_lib_name(),
+    cli.generation_timestamp.format("%Y%m%dT%H%M%SZ")
+  ))
+}
+
+/// Get where to write the log of a run: the crate's log file, or a fallback path if the crate dir
+/// does not exist (anymore)
+pub async fn get_run_log_file_path(cli: &Cli) -> PathBuf {
+  if fs::is_dir(cli.get_output_project_dir()).await {
+    cli.get_output_project_subpath(&Paths::LogFile)
+  } else {
+    get_fallback_log_file_path(cli)
+  }
+}
+
+/// Append the records the run in scope logged so far to a log file through the filesystem in scope.
+/// Returns whether anything was written.
+pub async fn write_run_log(log_file_path: &Path) -> Result<bool, IOError> {
+  let contents = RUN_LOG.try_with(LogBuffer::take).unwrap_or_default();
+  if contents.is_empty() {
+    return Ok(false);
+  }
+  if let Some(parent) = log_file_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+    fs::create_dir_all(parent).await?;
+  }
+  append(log_file_path, &contents).await?;
+  Ok(true)
+}
+
+/// Append to a file, creating it if needed
+async fn append(
+  path: &Path,
+  contents: &[u8],
+) -> Result<(), IOError> {
+  let file_system = fs::current();
+  let mut appended = match file_system.read(path).await {
+    Ok(existing) => existing,
+    Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+    Err(e) => return Err(e),
+  };
+  appended.extend_from_slice(contents);
+  file_system.write(path, &appended).await
+}
+
+#[cfg(test)]
+mod test_logging {
+  use super::*;
+  #[test]
+  fn verbosity_maps_to_levels() {
+    assert_eq!(get_terminal_level_filter(0), LevelFilter::WARN);
+    assert_eq!(get_terminal_level_filter(2), LevelFilter::DEBUG);
+    assert_eq!(get_terminal_level_filter(-1), LevelFilter::ERROR);
+    assert_eq!(get_terminal_level_filter(-5), LevelFilter::OFF);
+    assert_eq!(get_terminal_level_filter(9), LevelFilter::TRACE);
+  }
+  #[test]
+  fn buffer_drops_records_past_the_limit() {
+    let mut buffer = LogBuffer::default();
+    buffer
+      .write_all(&vec![b'a'; LogBuffer::MAX_LEN - 1])
+      .expect("must write");
+    buffer.write_all(b"bc").expect("must write");
+    let contents = buffer.take();
+    assert_eq!(
+      String::from_utf8_lossy(&contents[LogBuffer::MAX_LEN - 1..]),
+      "2 bytes of log records were dropped past the limit of 16777216 bytes\n"
+    );
+    assert!(buffer.take().is_empty());
+  }
+}
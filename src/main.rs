@@ -1,84 +1,181 @@
 //! Open api lib generator CLI
 
 use openapi_lib_generator::{
+  batch,
+  builder::{GenerationReport, GenerationRequest},
   cli::*,
-  generate::{
-    crate_scaffolds,
-    makefiles::{MakefileSpec, TaskNames},
-    utils::{run_cargo_make_task, ProcessError},
-    yamls::OpenAPIRustGeneratorConfigs,
-    CrateScaffoldingError,
-  },
-  testing::TestingError,
+  diagnostics::Diagnostic,
+  events::{self, OutputFormats},
+  generate::{post_generation, task_graphs},
+  logging,
+  process::{self, SystemProcessRunner},
+  self_update,
+  testing::matrix,
+  wizard::{self, InitOutcomes, TerminalPrompter},
 };
+use serde::Serialize;
+use std::{env, fmt::Display, process::ExitCode, sync::Arc};
 
-/// Run a subcommand
-async fn run_subcommands(cli: &Cli) -> Result<(), CLIError> {
-  let Cli {
-    inner_cli: InnerCli {
-      api_spec_url_opt,
-      autogenerate,
-      ..
-    },
-    ..
-  } = cli;
-  match cli.command.as_ref() {
-    Some(SubCommands::TestGeneration { .. }) => {
-      let task_name = TaskNames::GenerateAll;
-      let output = run_cargo_make_task(cli, task_name)
-        .await
-        .map_err(TestingError::from)
-        .map_err(CLIError::from)?;
-      if !output.status.success() {
-        Err(CLIError::from(TestingError::ProcessError(
-          ProcessError::Failure(format!("{output:#?}")),
-        )))
-      } else {
-        Ok(())
+#[tokio::main]
+async fn main() -> ExitCode {
+  let inner_cli = match CommandLines::parse_from(env::args_os()) {
+    CommandLines::Generation(inner_cli) => *inner_cli,
+    CommandLines::Tool(tool_cli) => return run_tool(tool_cli).await,
+  };
+  let output_format = inner_cli.output_format;
+  let verbosity = inner_cli.get_verbosity();
+  logging::init(verbosity);
+  match run(inner_cli, output_format, verbosity).await {
+    Ok(report) => {
+      match (output_format, report.plan_opt.as_ref()) {
+        (OutputFormats::Text, Some(plan)) => print!("{plan}"),
+        (OutputFormats::Text, None) if verbosity < 0 => {}
+        (OutputFormats::Text, None) => events::message(format!(
+          "Generated `{}` in `{}`",
+          report.lib_name,
+          report.output_dir.to_string_lossy()
+        )),
+        (OutputFormats::Json, _) => println!(
+          "{}",
+          serde_json::json!({ "event": "report", "report": report })
+        ),
+      }
+      ExitCode::from(report.status.get_exit_code())
+    }
+    Err(e) => report_error(&e, output_format),
+  }
+}
+
+/// Print an error and get the exit code for it
+fn report_error(
+  error: &CLIError,
+  output_format: OutputFormats,
+) -> ExitCode {
+  let diagnostic = Diagnostic::from(error);
+  match output_format {
+    OutputFormats::Text => eprint!("{diagnostic}"),
+    OutputFormats::Json => println!(
+      "{}",
+      serde_json::json!({ "event": "error", "error": diagnostic })
+    ),
+  }
+  ExitCode::from(diagnostic.exit_code)
+}
+
+/// Generate as asked on the command line
+async fn run(
+  inner_cli: InnerCli,
+  output_format: OutputFormats,
+  verbosity: i8,
+) -> Result<GenerationReport, CLIError> {
+  let cli = Cli::try_from_inner_cli(inner_cli)?;
+  // a dry run prints its plan instead of progress that did not really happen
+  let observer_verbosity = match cli.dry_run {
+    true => verbosity.min(-1),
+    false => verbosity,
+  };
+  GenerationRequest::from(cli)
+    .with_observer(output_format.make_observer(observer_verbosity))
+    .run_until(async {
+      let _ = tokio::signal::ctrl_c().await;
+    })
+    .await
+}
+
+/// Run one of the [ToolCommands]
+async fn run_tool(tool_cli: ToolCli) -> ExitCode {
+  let output_format = tool_cli.output_format;
+  let verbosity = tool_cli.get_verbosity();
+  logging::init(verbosity);
+  let observer = output_format.make_observer(verbosity);
+  match tool_cli.command {
+    ToolCommands::TestMatrix(args) => {
+      let process_runner = SystemProcessRunner::with_default_timeout(args.get_step_timeout_opt());
+      let run =
+        process::with_process_runner(Arc::new(process_runner), matrix::run_test_matrix(&args));
+      match events::with_observer(observer, run).await {
+        Ok(report) => print_tool_report(&report, report.is_passing(), output_format),
+        Err(e) => report_error(&e.into(), output_format),
+      }
+    }
+    ToolCommands::Batch(args) => {
+      let process_runner = SystemProcessRunner::with_default_timeout(args.get_step_timeout_opt());
+      let cancel = async {
+        let _ = tokio::signal::ctrl_c().await;
+      };
+      let run =
+        process::with_process_runner(Arc::new(process_runner), batch::run_batch(&args, cancel));
+      match events::with_observer(observer, run).await {
+        Ok(report) => print_tool_report(&report, report.is_passing(), output_format),
+        Err(e) => report_error(&e.into(), output_format),
       }
     }
-    None => {
-      if *autogenerate && api_spec_url_opt.is_some() {
-        let task_name = TaskNames::SpecDownloadDefault;
-        let output = run_cargo_make_task(cli, task_name)
-          .await
-          .map_err(CrateScaffoldingError::from)
-          .map_err(CLIError::from)?;
-        if !output.status.success() {
-          Err(CLIError::from(ProcessError::Failure(format!(
-            "{output:#?}"
-          ))))
-        } else {
-          let task_name = TaskNames::GenerateAll;
-          let output = run_cargo_make_task(cli, task_name)
-            .await
-            .map_err(CrateScaffoldingError::from)
-            .map_err(CLIError::from)?;
-          if !output.status.success() {
-            Err(CLIError::from(ProcessError::Failure(format!(
-              "{output:#?}"
-            ))))
-          } else {
-            Ok(())
-          }
+    ToolCommands::Init(args) => {
+      let cancel = async {
+        let _ = tokio::signal::ctrl_c().await;
+      };
+      let mut prompter = TerminalPrompter;
+      let run = wizard::run_init_wizard(&args, &mut prompter, cancel);
+      match events::with_observer(observer, run).await {
+        Ok(InitOutcomes::WroteConfig(_)) => ExitCode::SUCCESS,
+        Ok(InitOutcomes::Generated(report)) => {
+          events::message(format!(
+            "Generated `{}` in `{}`",
+            report.lib_name,
+            report.output_dir.to_string_lossy()
+          ));
+          ExitCode::SUCCESS
         }
-      } else {
-        Ok(())
+        Err(e) => report_error(&e, output_format),
+      }
+    }
+    ToolCommands::RegenerateTasks => {
+      match events::with_observer(observer, post_generation::regenerate_task_files()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report_error(&e, output_format),
+      }
+    }
+    ToolCommands::SelfUpdate(args) => {
+      match events::with_observer(observer, self_update::run_self_update(&args)).await {
+        Ok(report) => print_tool_report(&report, true, output_format),
+        Err(e) => report_error(&e, output_format),
+      }
+    }
+    ToolCommands::Tasks(TasksCommands::Graph(args)) => {
+      match events::with_observer(observer, task_graphs::run_task_graph(args.format)).await {
+        Ok(report) => print_tool_report(&report, true, output_format),
+        Err(e) => report_error(&e, output_format),
+      }
+    }
+    ToolCommands::PostGenerate => {
+      let run = async {
+        post_generation::run_post_generation().await?;
+        events::message("Post-generation updates complete");
+        Ok::<_, CLIError>(())
+      };
+      match events::with_observer(observer, run).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report_error(&e, output_format),
       }
     }
   }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), CLIError> {
-  let cli = &Cli::new().await?;
-  crate_scaffolds::scaffold_crate(cli).await?;
-  let makefile_spec = MakefileSpec::try_from(cli)?;
-  makefile_spec.write_to_makefile(cli).await?;
-  let rust_generator_configs = OpenAPIRustGeneratorConfigs::new(cli);
-  rust_generator_configs.copy_spec_file(cli).await?;
-  rust_generator_configs.write_to_yaml_file(cli).await?;
-  run_subcommands(cli).await?;
-
-  Ok(())
+/// Print the report of a tool command and get the exit code for whether it passed
+fn print_tool_report(
+  report: &(impl Display + Serialize),
+  is_passing: bool,
+  output_format: OutputFormats,
+) -> ExitCode {
+  match output_format {
+    OutputFormats::Text => print!("{report}"),
+    OutputFormats::Json => println!(
+      "{}",
+      serde_json::json!({ "event": "report", "report": report })
+    ),
+  }
+  match is_passing {
+    true => ExitCode::SUCCESS,
+    false => ExitCode::FAILURE,
+  }
 }
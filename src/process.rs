@@ -0,0 +1,461 @@
+//! Subprocesses
+//!
+//! Generation runs subprocesses through the [ProcessRunner] in scope (see [with_process_runner]), or
+//! spawns them for real if there is none. A [RecordingProcessRunner] only records what would run.
+use crate::events::{self, GenerationEvent, OutputStreams};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::BTreeMap,
+  fmt,
+  future::Future,
+  io::Error as IOError,
+  path::PathBuf,
+  process::{ExitStatus, Output, Stdio},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+  io::{AsyncBufReadExt, AsyncRead, BufReader},
+  process::{Child, Command},
+};
+
+/// Process errors
+#[derive(Debug, Error)]
+pub enum ProcessError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error("Could not run `{program}`: {source}")]
+  CouldNotStart { program: String, source: IOError },
+  #[error(transparent)]
+  Failure(#[from] Box<ProcessFailure>),
+}
+
+/// A process that did not exit successfully
+#[derive(Clone, Debug, Deserialize, Error, Serialize)]
+#[error("`{}` {}", self.get_command_line(), self.get_exit_description())]
+pub struct ProcessFailure {
+  pub program: String,
+  pub args: Vec<String>,
+  pub cwd: Option<PathBuf>,
+  pub exit_code: Option<i32>,
+  /// How long the process was allowed to run if it was killed for taking longer
+  pub timeout_opt: Option<Duration>,
+  /// The last lines of stderr, or of stdout if nothing was printed to stderr
+  pub output_tail: Vec<String>,
+}
+impl ProcessFailure {
+  /// How many output lines to keep
+  pub const OUTPUT_TAIL_LINES: usize = 20;
+  /// Describe a failed run from its output
+  pub fn new(
+    spec: &ProcessSpec,
+    output: &Output,
+  ) -> Self {
+    Self {
+      program: spec.program.clone(),
+      args: spec.args.clone(),
+      cwd: spec.cwd.clone(),
+      exit_code: output.status.code(),
+      timeout_opt: None,
+      output_tail: Self::get_tail(&output.stdout, &output.stderr),
+    }
+  }
+  /// Describe a run that was killed after its timeout, from what it printed until then
+  pub fn timed_out(
+    spec: &ProcessSpec,
+    timeout: Duration,
+    stdout: &[u8],
+    stderr: &[u8],
+  ) -> Self {
+    Self {
+      program: spec.program.clone(),
+      args: spec.args.clone(),
+      cwd: spec.cwd.clone(),
+      exit_code: None,
+      timeout_opt: Some(timeout),
+      output_tail: Self::get_tail(stdout, stderr),
+    }
+  }
+  /// Get the last non-empty lines of stderr, or of stdout if nothing was printed to stderr
+  fn get_tail(
+    stdout: &[u8],
+    stderr: &[u8],
+  ) -> Vec<String> {
+    let output_bytes = if stderr.iter().any(|b| !b.is_ascii_whitespace()) {
+      stderr
+    } else {
+      stdout
+    };
+    let output_string = String::from_utf8_lossy(output_bytes);
+    let lines = output_string
+      .lines()
+      .map(str::trim_end)
+      .filter(|line| !line.is_empty())
+      .collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(Self::OUTPUT_TAIL_LINES)..]
+      .iter()
+      .map(ToString::to_string)
+      .collect()
+  }
+  /// Get the command line that was run, quoting arguments with spaces
+  pub fn get_command_line(&self) -> String {
+    ProcessSpec::new(&self.program, &self.args).get_command_line()
+  }
+  /// Describe how the process exited
+  fn get_exit_description(&self) -> String {
+    match (self.timeout_opt, self.exit_code) {
+      (Some(timeout), _) => format!("timed out after {}s", timeout.as_secs()),
+      (None, Some(code)) => format!("exited with code {code}"),
+      (None, None) => "was killed by a signal".to_string(),
+    }
+  }
+}
+
+/// A command to run
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ProcessSpec {
+  pub program: String,
+  pub args: Vec<String>,
+  pub cwd: Option<PathBuf>,
+  /// Variables to set in the environment of the process, on top of the inherited ones
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub env: BTreeMap<String, String>,
+  /// How long the process may run before it is killed. The runner's default applies if unset.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub timeout_opt: Option<Duration>,
+  /// What to prefix the lines the process prints with. Defaults to the program name.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub label_opt: Option<String>,
+}
+impl ProcessSpec {
+  /// Run a program with arguments
+  pub fn new<T: AsRef<str>>(
+    program: impl Into<String>,
+    args: &[T],
+  ) -> Self {
+    Self {
+      program: program.into(),
+      args: args.iter().map(|arg| arg.as_ref().to_string()).collect(),
+      ..Self::default()
+    }
+  }
+  /// Run in a dir
+  pub fn cwd(
+    mut self,
+    cwd: impl Into<PathBuf>,
+  ) -> Self {
+    self.cwd = Some(cwd.into());
+    self
+  }
+  /// Set an environment variable
+  pub fn env(
+    mut self,
+    key: impl Into<String>,
+    value: impl Into<String>,
+  ) -> Self {
+    self.env.insert(key.into(), value.into());
+    self
+  }
+  /// Kill the process if it runs for longer than this
+  pub fn timeout(
+    mut self,
+    timeout: Duration,
+  ) -> Self {
+    self.timeout_opt = Some(timeout);
+    self
+  }
+  /// Prefix the lines the process prints with a label
+  pub fn label(
+    mut self,
+    label: impl Into<String>,
+  ) -> Self {
+    self.label_opt = Some(label.into());
+    self
+  }
+  /// Get what to prefix the lines the process prints with
+  pub fn get_label(&self) -> &str {
+    self.label_opt.as_deref().unwrap_or(&self.program)
+  }
+  /// Get the command line, quoting arguments with spaces
+  pub fn get_command_line(&self) -> String {
+    std::iter::once(self.program.clone())
+      .chain(self.args.iter().map(|arg| {
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+          format!("{arg:?}")
+        } else {
+          arg.clone()
+        }
+      }))
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+}
+impl fmt::Display for ProcessSpec {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    match self.cwd.as_ref() {
+      Some(cwd) => write!(
+        f,
+        "{} (in {})",
+        self.get_command_line(),
+        cwd.to_string_lossy()
+      ),
+      None => write!(f, "{}", self.get_command_line()),
+    }
+  }
+}
+
+/// Runs subprocesses
+#[async_trait]
+pub trait ProcessRunner: Send + Sync {
+  /// Run a command to completion, capturing its output. Fails with [ProcessFailure] if the command
+  /// is killed for running longer than its timeout, but not if it just exits unsuccessfully.
+  async fn run(
+    &self,
+    spec: &ProcessSpec,
+  ) -> Result<Output, ProcessError>;
+}
+
+/// Spawns real processes, reporting each line they print as a [GenerationEvent::ProcessOutput]
+///
+/// Each process runs in a process group of its own. If the run is dropped before the process exits,
+/// for example on Ctrl-C, the group is interrupted, so that grandchildren like the OpenAPI generator
+/// stop too.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemProcessRunner {
+  /// How long a process may run if its spec sets no timeout
+  pub default_timeout_opt: Option<Duration>,
+}
+impl SystemProcessRunner {
+  /// A runner that kills processes without a timeout of their own after the given one
+  pub fn with_default_timeout(default_timeout_opt: Option<Duration>) -> Self {
+    Self {
+      default_timeout_opt,
+    }
+  }
+  /// Report each line of a stream and collect it
+  async fn forward_output(
+    label: &str,
+    stream: OutputStreams,
+    reader_opt: Option<impl AsyncRead + Unpin>,
+    collected: &mut Vec<u8>,
+  ) -> Result<(), IOError> {
+    let Some(reader) = reader_opt else {
+      return Ok(());
+    };
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    while reader.read_until(b'\n', &mut line).await? > 0 {
+      collected.extend_from_slice(&line);
+      events::emit(GenerationEvent::ProcessOutput {
+        label: label.to_string(),
+        stream,
+        line: String::from_utf8_lossy(&line).trim_end().to_string(),
+      });
+      line.clear();
+    }
+    Ok(())
+  }
+}
+#[async_trait]
+impl ProcessRunner for SystemProcessRunner {
+  async fn run(
+    &self,
+    spec: &ProcessSpec,
+  ) -> Result<Output, ProcessError> {
+    let mut command = Command::new(&spec.program);
+    if let Some(cwd) = spec.cwd.as_ref() {
+      command.current_dir(cwd);
+    }
+    #[cfg(unix)]
+    command.process_group(0);
+    #[cfg(not(unix))]
+    command.kill_on_drop(true);
+    let mut child = command
+      .args(&spec.args)
+      .envs(&spec.env)
+      .stdin(Stdio::null())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|source| ProcessError::CouldNotStart {
+        program: spec.program.clone(),
+        source,
+      })?;
+    let mut guard = ChildGuard::new(&child);
+    let (stdout_opt, stderr_opt) = (child.stdout.take(), child.stderr.take());
+    let (mut stdout, mut stderr) = (vec![], vec![]);
+    let label = spec.get_label();
+    let run = async {
+      tokio::try_join!(
+        Self::forward_output(label, OutputStreams::Stdout, stdout_opt, &mut stdout),
+        Self::forward_output(label, OutputStreams::Stderr, stderr_opt, &mut stderr),
+      )?;
+      child.wait().await
+    };
+    let status_result = match spec.timeout_opt.or(self.default_timeout_opt) {
+      None => Ok(run.await),
+      Some(timeout) => tokio::time::timeout(timeout, run)
+        .await
+        .map_err(|_| timeout),
+    };
+    let status = match status_result {
+      Ok(status_result) => status_result?,
+      Err(timeout) => {
+        guard.kill(&mut child);
+        let _ = child.wait().await;
+        guard.disarm();
+        let failure = ProcessFailure::timed_out(spec, timeout, &stdout, &stderr);
+        return Err(Box::new(failure).into());
+      }
+    };
+    guard.disarm();
+    Ok(Output {
+      status,
+      stdout,
+      stderr,
+    })
+  }
+}
+
+/// Stops a child and everything it started if dropped before the child exits
+struct ChildGuard {
+  /// The id of the child, which is also its process group id
+  pid_opt: Option<u32>,
+}
+impl ChildGuard {
+  /// Guard a child that was just spawned
+  fn new(child: &Child) -> Self {
+    Self {
+      pid_opt: child.id(),
+    }
+  }
+  /// Kill the child and its process group now
+  fn kill(
+    &mut self,
+    child: &mut Child,
+  ) {
+    #[cfg(unix)]
+    if let Some(pid) = self.pid_opt {
+      // SAFETY: signalling a process group has no memory safety requirements
+      unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+    }
+    let _ = child.start_kill();
+  }
+  /// Leave the child be: it already exited
+  fn disarm(&mut self) {
+    self.pid_opt = None;
+  }
+}
+impl Drop for ChildGuard {
+  fn drop(&mut self) {
+    // interrupt the group the same way Ctrl-C in a terminal would have
+    #[cfg(unix)]
+    if let Some(pid) = self.pid_opt.take() {
+      // SAFETY: signalling a process group has no memory safety requirements
+      unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGINT) };
+    }
+  }
+}
+
+/// Records commands instead of running them, reporting each as successful
+#[derive(Debug, Default)]
+pub struct RecordingProcessRunner {
+  runs: Mutex<Vec<ProcessSpec>>,
+}
+impl RecordingProcessRunner {
+  /// Get the commands recorded so far
+  pub fn get_runs(&self) -> Vec<ProcessSpec> {
+    self.runs.lock().expect("must lock runs").clone()
+  }
+}
+#[async_trait]
+impl ProcessRunner for RecordingProcessRunner {
+  async fn run(
+    &self,
+    spec: &ProcessSpec,
+  ) -> Result<Output, ProcessError> {
+    self.runs.lock().expect("must lock runs").push(spec.clone());
+    Ok(Output {
+      status: ExitStatus::default(),
+      stdout: vec![],
+      stderr: vec![],
+    })
+  }
+}
+
+tokio::task_local! {
+  static PROCESS_RUNNER: Arc<dyn ProcessRunner>;
+}
+
+/// Run a future with subprocesses going through the given runner
+pub async fn with_process_runner<F: Future>(
+  process_runner: Arc<dyn ProcessRunner>,
+  f: F,
+) -> F::Output {
+  PROCESS_RUNNER.scope(process_runner, f).await
+}
+
+/// Get the runner in scope
+pub fn current() -> Arc<dyn ProcessRunner> {
+  PROCESS_RUNNER
+    .try_with(Arc::clone)
+    .unwrap_or_else(|_| Arc::new(SystemProcessRunner::default()))
+}
+
+/// Run a command with the runner in scope, reporting it as it starts and finishes. Fails with
+/// [ProcessFailure] if the command does.
+#[tracing::instrument(level = "debug", skip_all, fields(command = %spec))]
+pub async fn run(
+  spec: &ProcessSpec,
+  description: impl Into<String>,
+) -> Result<Output, ProcessError> {
+  events::emit(GenerationEvent::ProcessStarted {
+    program: spec.program.clone(),
+    args: spec.args.clone(),
+    cwd: spec.cwd.clone(),
+    description: description.into(),
+  });
+  let result = current().run(spec).await;
+  let (success, exit_code, timed_out) = match result.as_ref() {
+    Ok(output) => (output.status.success(), output.status.code(), false),
+    Err(ProcessError::Failure(failure)) => (false, None, failure.timeout_opt.is_some()),
+    Err(ProcessError::IOError(_) | ProcessError::CouldNotStart { .. }) => return result,
+  };
+  events::emit(GenerationEvent::ProcessFinished {
+    program: spec.program.clone(),
+    args: spec.args.clone(),
+    success,
+    exit_code,
+    timed_out,
+  });
+  let output = result?;
+  if output.status.success() {
+    Ok(output)
+  } else {
+    Err(Box::new(ProcessFailure::new(spec, &output)).into())
+  }
+}
+
+#[cfg(all(test, unix))]
+mod test_process {
+  use super::*;
+  #[tokio::test]
+  async fn system_runner_kills_on_timeout() {
+    let spec = ProcessSpec::new("sh", &["-c", "echo started; sleep 5"])
+      .env("OLG_TEST", "1")
+      .timeout(Duration::from_millis(300));
+    let started = std::time::Instant::now();
+    let result = SystemProcessRunner::default().run(&spec).await;
+    assert!(started.elapsed() < Duration::from_secs(4));
+    let Err(ProcessError::Failure(failure)) = result else {
+      panic!("must time out: {result:?}");
+    };
+    assert_eq!(failure.timeout_opt, Some(Duration::from_millis(300)));
+    assert_eq!(failure.output_tail, vec!["started"]);
+  }
+}
@@ -0,0 +1,462 @@
+//! Generator self-update
+//!
+//! `olg self-update`, which the `olg-self-update` task of a generated crate runs, looks up the newest
+//! published version of this crate in a crates.io index, installs it in the temp dir of the crate
+//! and has it write the task files again. Only then is the generator dev-dependency of the crate
+//! bumped, and the report lists the tasks that changed. Subprocesses run through the
+//! [ProcessRunner](crate::process::ProcessRunner) in scope.
+use crate::{
+  cli::{CLIError, Paths, SelfUpdateArgs},
+  fs,
+  generate::{
+    parameters::{get_crates_index_subdir, ParameterError},
+    post_generation,
+    task_runners::{TaskRunners, JUSTFILE_NAME, XTASK_MAIN_PATH},
+    utils::get_this_crate_name,
+    workspaces::parse_version_numbers,
+  },
+  process::{self, ProcessSpec},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Error as SerdeJSONError;
+use std::{
+  collections::BTreeMap,
+  env, fmt,
+  io::Error as IOError,
+  path::{Path, PathBuf},
+};
+use strum::EnumProperty;
+use thiserror::Error;
+use toml_edit::{value, Document, Item, TomlError};
+use url::Url;
+
+/// The sparse index of crates.io
+pub const CRATES_IO_INDEX_URL: &str = "https://index.crates.io";
+
+/// Self-update errors
+#[derive(Debug, Error)]
+pub enum SelfUpdateError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  SerdeJSONError(#[from] SerdeJSONError),
+  #[error(transparent)]
+  TomlError(#[from] TomlError),
+  #[error("{manifest} has no `{crate_name}` dev-dependency to update")]
+  NoGeneratorDependency {
+    manifest: PathBuf,
+    crate_name: String,
+  },
+  #[error("The index {index} has no published version of `{crate_name}`")]
+  NotInIndex { index: String, crate_name: String },
+}
+
+/// Where published versions are looked up
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CrateIndexes {
+  /// A local checkout of a git index, laid out like the crates.io index
+  Local(PathBuf),
+  /// A sparse index served over HTTP
+  Sparse(Url),
+}
+impl CrateIndexes {
+  /// Read an `--index` argument, which is an HTTP URL or a dir
+  pub fn parse(index: &str) -> Self {
+    match Url::parse(index) {
+      Ok(url) if matches!(url.scheme(), "http" | "https") => Self::Sparse(url),
+      _ => Self::Local(PathBuf::from(index)),
+    }
+  }
+  /// Read the index file listing every published version of a crate
+  async fn read_crate_entries(
+    &self,
+    crate_name: &str,
+  ) -> Result<String, CLIError> {
+    let index_path = get_crates_index_subdir(crate_name).join(crate_name);
+    match self {
+      Self::Local(index_dir) => {
+        if !fs::is_file(index_dir.join("config.json")).await {
+          return Err(ParameterError::NotACratesIndex(index_dir.clone()).into());
+        }
+        match fs::read_to_string(index_dir.join(index_path)).await {
+          Ok(entries) => Ok(entries),
+          Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+          Err(e) => Err(e.into()),
+        }
+      }
+      Self::Sparse(index_url) => {
+        let entries_url = format!(
+          "{}/{}",
+          index_url.as_str().trim_end_matches('/'),
+          index_path.to_string_lossy()
+        );
+        let spec = ProcessSpec::new("wget", &["-q", "-O", "-", &entries_url]);
+        let output =
+          process::run(&spec, format!("Looking up the versions of {crate_name}.")).await?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+      }
+    }
+  }
+  /// Get the `cargo install` args that install from this index
+  fn get_install_args(&self) -> Vec<String> {
+    match self {
+      Self::Local(index_dir) => {
+        let index_dir = env::current_dir()
+          .map(|cwd| cwd.join(index_dir))
+          .unwrap_or_else(|_| index_dir.clone());
+        vec![
+          "--index".to_string(),
+          format!("file://{}", index_dir.to_string_lossy()),
+        ]
+      }
+      Self::Sparse(index_url)
+        if index_url.as_str().trim_end_matches('/') == CRATES_IO_INDEX_URL =>
+      {
+        vec![]
+      }
+      Self::Sparse(index_url) => vec!["--index".to_string(), format!("sparse+{index_url}")],
+    }
+  }
+}
+impl fmt::Display for CrateIndexes {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    match self {
+      Self::Local(index_dir) => write!(f, "{}", index_dir.to_string_lossy()),
+      Self::Sparse(index_url) => write!(f, "{index_url}"),
+    }
+  }
+}
+
+/// Get the newest version an index file lists that is neither yanked nor a pre-release
+pub fn get_newest_version(index_entries: &str) -> Result<Option<String>, SelfUpdateError> {
+  /// A line of an index file
+  #[derive(Deserialize)]
+  struct IndexEntry {
+    vers: String,
+    #[serde(default)]
+    yanked: bool,
+  }
+  let mut newest_opt: Option<(Vec<u64>, String)> = None;
+  for line in index_entries.lines().filter(|line| !line.trim().is_empty()) {
+    let entry: IndexEntry = serde_json::from_str(line)?;
+    let Some(version_numbers) = parse_version_numbers(&entry.vers) else {
+      continue;
+    };
+    let is_newest = newest_opt
+      .as_ref()
+      .is_none_or(|(newest_numbers, _)| &version_numbers > newest_numbers);
+    if !entry.yanked && is_newest {
+      newest_opt = Some((version_numbers, entry.vers));
+    }
+  }
+  Ok(newest_opt.map(|(_, version)| version))
+}
+
+/// Get the version item of the generator dev-dependency, given as a string or in a table
+fn get_generator_version_item<'a>(
+  manifest: &'a mut Document,
+  crate_name: &str,
+) -> Option<&'a mut Item> {
+  let dependency = manifest
+    .get_mut("dev-dependencies")?
+    .as_table_like_mut()?
+    .get_mut(crate_name)?;
+  match dependency.is_str() {
+    true => Some(dependency),
+    false => dependency.as_table_like_mut()?.get_mut("version"),
+  }
+}
+
+/// What happened to a task
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskChanges {
+  Added,
+  Removed,
+  Changed,
+}
+
+/// Split the task files of a crate into the text of each task, by task name
+fn get_task_texts(
+  task_runner: TaskRunners,
+  contents: &str,
+) -> BTreeMap<String, String> {
+  let mut task_texts = BTreeMap::new();
+  match task_runner {
+    TaskRunners::CargoMake => {
+      let tasks_opt = contents
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut makefile| makefile.remove("tasks"));
+      if let Some(toml::Value::Table(tasks)) = tasks_opt {
+        for (name, task) in tasks {
+          task_texts.insert(name, task.to_string());
+        }
+      }
+    }
+    TaskRunners::Just => {
+      // a recipe is a paragraph with a header, after the comment describing it
+      let mut name_opt = None;
+      let mut paragraph = vec![];
+      for line in contents.lines().chain([""]) {
+        if line.is_empty() {
+          if let Some(name) = name_opt.take() {
+            task_texts.insert(name, paragraph.join("\n"));
+          }
+          paragraph.clear();
+          continue;
+        }
+        paragraph.push(line);
+        let is_header =
+          !line.starts_with([' ', '\t', '#', '[']) && line.contains(':') && !line.contains(":=");
+        if is_header {
+          name_opt = line.split([' ', ':']).next().map(ToString::to_string);
+        }
+      }
+    }
+    TaskRunners::Xtask => {
+      // each task is an arm of the `match` in `run_task`
+      let mut name_opt: Option<String> = None;
+      for line in contents.lines() {
+        if let Some(name) = line
+          .strip_prefix("    \"")
+          .and_then(|rest| rest.strip_suffix("\" => {"))
+        {
+          name_opt = Some(name.to_string());
+        } else if line == "    }" {
+          name_opt = None;
+        } else if let Some(name) = name_opt.as_ref() {
+          let text = task_texts.entry(name.clone()).or_default();
+          text.push_str(line);
+          text.push('\n');
+        }
+      }
+    }
+  }
+  task_texts
+}
+
+/// Get the changes between the tasks of two versions of the task files
+fn get_task_changes(
+  task_runner: TaskRunners,
+  old_contents: &str,
+  new_contents: &str,
+) -> Vec<(String, TaskChanges)> {
+  let old_texts = get_task_texts(task_runner, old_contents);
+  let new_texts = get_task_texts(task_runner, new_contents);
+  let mut task_changes = vec![];
+  for (name, new_text) in new_texts.iter() {
+    match old_texts.get(name) {
+      None => task_changes.push((name.clone(), TaskChanges::Added)),
+      Some(old_text) if old_text != new_text => {
+        task_changes.push((name.clone(), TaskChanges::Changed))
+      }
+      Some(_) => {}
+    }
+  }
+  for name in old_texts
+    .keys()
+    .filter(|name| !new_texts.contains_key(*name))
+  {
+    task_changes.push((name.clone(), TaskChanges::Removed));
+  }
+  task_changes.sort_by(|(name, _), (other_name, _)| name.cmp(other_name));
+  task_changes
+}
+
+/// Read the file holding the tasks of a crate, or nothing if it is missing
+async fn read_task_file(
+  crate_dir: &Path,
+  task_runner: TaskRunners,
+) -> Result<String, IOError> {
+  let task_file_name = match task_runner {
+    TaskRunners::CargoMake => Paths::CargoMakefile
+      .get_str("path")
+      .expect("must get makefile path"),
+    TaskRunners::Just => JUSTFILE_NAME,
+    TaskRunners::Xtask => XTASK_MAIN_PATH,
+  };
+  match fs::read_to_string(crate_dir.join(task_file_name)).await {
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+    result => result,
+  }
+}
+
+/// What a self-update did
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SelfUpdateReport {
+  pub crate_name: String,
+  /// The generator version the crate was on
+  pub current_version: String,
+  /// The newest published generator version
+  pub newest_version: String,
+  /// Whether the crate was moved to the newest version
+  pub is_updated: bool,
+  /// The tasks that changed with the update, by name
+  pub task_changes: Vec<(String, TaskChanges)>,
+}
+impl SelfUpdateReport {
+  /// Whether a newer version than the crate's is out
+  pub fn is_outdated(&self) -> bool {
+    parse_version_numbers(&self.newest_version) > parse_version_numbers(&self.current_version)
+  }
+}
+impl fmt::Display for SelfUpdateReport {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    let Self {
+      crate_name,
+      current_version,
+      newest_version,
+      ..
+    } = self;
+    if !self.is_outdated() {
+      return writeln!(f, "`{crate_name}` {current_version} is the newest version");
+    }
+    if !self.is_updated {
+      return writeln!(
+        f,
+        "`{crate_name}` {newest_version} is out, the crate is on {current_version}. Run the \
+         `olg-self-update` task to update"
+      );
+    }
+    writeln!(
+      f,
+      "Updated `{crate_name}` from {current_version} to {newest_version}\n"
+    )?;
+    if self.task_changes.is_empty() {
+      return writeln!(f, "No tasks changed");
+    }
+    writeln!(f, "| task | change |")?;
+    writeln!(f, "|---|---|")?;
+    for (name, change) in self.task_changes.iter() {
+      writeln!(f, "| {name} | {} |", change.as_ref())?;
+    }
+    Ok(())
+  }
+}
+
+/// Move the crate in the current dir to the newest published version of this generator
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn run_self_update(args: &SelfUpdateArgs) -> Result<SelfUpdateReport, CLIError> {
+  let crate_name = get_this_crate_name();
+  let crate_dir = env::current_dir()?;
+  let manifest_path = crate_dir.join(
+    Paths::CargoTomlFile
+      .get_str("path")
+      .expect("must get Cargo.toml path"),
+  );
+  let mut manifest = fs::read_to_string(&manifest_path)
+    .await?
+    .parse::<Document>()
+    .map_err(SelfUpdateError::from)?;
+  let current_version = get_generator_version_item(&mut manifest, crate_name)
+    .and_then(|version| version.as_str())
+    .map(ToString::to_string)
+    .ok_or_else(|| SelfUpdateError::NoGeneratorDependency {
+      manifest: manifest_path.clone(),
+      crate_name: crate_name.to_string(),
+    })?;
+  let index = CrateIndexes::parse(&args.index);
+  let index_entries = index.read_crate_entries(crate_name).await?;
+  let newest_version =
+    get_newest_version(&index_entries)?.ok_or_else(|| SelfUpdateError::NotInIndex {
+      index: index.to_string(),
+      crate_name: crate_name.to_string(),
+    })?;
+  let mut report = SelfUpdateReport {
+    crate_name: crate_name.to_string(),
+    current_version,
+    newest_version,
+    is_updated: false,
+    task_changes: vec![],
+  };
+  if !report.is_outdated() || args.check_only {
+    return Ok(report);
+  }
+  let task_runner = post_generation::read_crate_cli().await?.task_runner;
+  let old_tasks = read_task_file(&crate_dir, task_runner).await?;
+  let install_root = crate_dir
+    .join(Paths::TempDir.get_str("path").expect("must get temp path"))
+    .join(format!("olg-{}", report.newest_version));
+  let mut install_args = vec![
+    "install".to_string(),
+    crate_name.to_string(),
+    "--version".to_string(),
+    report.newest_version.clone(),
+    "--root".to_string(),
+    install_root.to_string_lossy().to_string(),
+    "--bin".to_string(),
+    "olg".to_string(),
+  ];
+  install_args.extend(index.get_install_args());
+  process::run(
+    &ProcessSpec::new("cargo", &install_args),
+    format!("Installing {crate_name} {}.", report.newest_version),
+  )
+  .await?;
+  let newer_olg_path = install_root.join("bin").join("olg");
+  process::run(
+    &ProcessSpec::new(newer_olg_path.to_string_lossy(), &["regenerate-tasks"]).cwd(&crate_dir),
+    format!(
+      "Regenerating the tasks with {crate_name} {}.",
+      report.newest_version
+    ),
+  )
+  .await?;
+  if let Some(version) = get_generator_version_item(&mut manifest, crate_name) {
+    *version = value(report.newest_version.as_str());
+  }
+  fs::write(
+    &manifest_path,
+    manifest.to_string(),
+    Some("Generator dev-dependency"),
+  )
+  .await?;
+  let new_tasks = read_task_file(&crate_dir, task_runner).await?;
+  report.task_changes = get_task_changes(task_runner, &old_tasks, &new_tasks);
+  report.is_updated = true;
+  Ok(report)
+}
+
+#[cfg(test)]
+mod test_self_update {
+  use super::*;
+
+  #[test]
+  fn newest_version_skips_yanked_and_pre_releases() {
+    let index_entries = [
+      r#"{"name":"openapi_lib_generator","vers":"0.2.9","yanked":false}"#,
+      r#"{"name":"openapi_lib_generator","vers":"0.2.312","yanked":false}"#,
+      r#"{"name":"openapi_lib_generator","vers":"0.3.0-beta.1","yanked":false}"#,
+      r#"{"name":"openapi_lib_generator","vers":"0.2.400","yanked":true}"#,
+    ]
+    .join("\n");
+    assert_eq!(
+      get_newest_version(&index_entries).expect("must read index"),
+      Some("0.2.312".to_string())
+    );
+  }
+
+  #[test]
+  fn reports_added_changed_and_removed_just_recipes() {
+    let old_justfile = "set export\n\n# List\ndefault:\n    @just --list\n\n# Old\ngone:\n    \
+                        echo gone\n\n# Same\nkept: gone\n    echo kept\n";
+    let new_justfile = "set export\n\n# List the tasks\ndefault:\n    @just --list\n\n# Same\n\
+                        kept: gone\n    echo kept\n\n# New\nnew *ARGS:\n    echo \"$@\"\n";
+    assert_eq!(
+      get_task_changes(TaskRunners::Just, old_justfile, new_justfile),
+      [
+        ("default".to_string(), TaskChanges::Changed),
+        ("gone".to_string(), TaskChanges::Removed),
+        ("new".to_string(), TaskChanges::Added),
+      ]
+    );
+  }
+}
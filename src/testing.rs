@@ -1,20 +1,43 @@
 //! Testing specifications
 //!
 //! These tests fall beyond the scope of cargo test because they are invoked from the CLI
-use crate::generate::utils::ProcessError;
+use crate::process::ProcessError;
+#[cfg(test)]
+use crate::{builder::GeneratorBuilder, cli::Cli};
 use std::io::Error as IOError;
 use thiserror::Error;
+#[cfg(test)]
+use url::Url;
+
+#[cfg(all(test, unix))]
+mod e2e;
+pub mod matrix;
+#[cfg(test)]
+mod snapshots;
+pub mod specs;
 
 /// A fake OpenAPI specification
-pub static PETSTORE_YAML: &'static str = include_str!("testing/petstore.yaml");
+pub static PETSTORE_YAML: &str = include_str!("testing/petstore.yaml");
 /// A name for a testing OpenAPI yaml spec file
-pub static TESTING_SPEC_FILE_NAME: &'static str = "petshoppe_test_spec.yaml";
+pub static TESTING_SPEC_FILE_NAME: &str = "petshoppe_test_spec.yaml";
 /// A testing folder name
-pub static TEST_SUBDIR_NAME: &'static str = "testing";
+pub static TEST_SUBDIR_NAME: &str = "testing";
 /// A testing api url
-pub static TEST_API_URL: &'static str = "https://www.petshoppe.example";
+pub static TEST_API_URL: &str = "https://www.petshoppe.example";
 /// A testing api name
-pub static TEST_API_NAME: &'static str = "PetShoppe";
+pub static TEST_API_NAME: &str = "PetShoppe";
+/// Get the settings of a generation of the testing spec into a virtual dir
+#[cfg(test)]
+pub fn get_test_cli() -> Cli {
+  let api_url = Url::parse(TEST_API_URL).expect("must parse url");
+  GeneratorBuilder::new(TEST_API_NAME, api_url)
+    .spec_file("/virtual/petstore.yaml")
+    .output_dir("/virtual/petstore")
+    .build()
+    .expect("must build request")
+    .get_cli()
+    .clone()
+}
 /// Testing errors
 #[derive(Debug, Error)]
 pub enum TestingError {
@@ -0,0 +1,128 @@
+//! Offline end-to-end generation
+//!
+//! Runs a whole test generation for real: `cargo init`, `cargo make generate-all` and the
+//! post-generation updates, which build `olg` from this crate. A stub `openapi-generator-cli` (see
+//! `fixtures/openapi-generator-cli`) is put first on the PATH, so no Java and no download is needed,
+//! and cargo runs with `CARGO_NET_OFFLINE=true`. The box still needs `cargo-make`, so the test is
+//! skipped, saying why, when `cargo make` does not run.
+use crate::{
+  builder::{GenerationSteps, GeneratorBuilder},
+  cli::Paths,
+  generate::utils::get_temp_subdir,
+  process::{ProcessError, ProcessRunner, ProcessSpec, SystemProcessRunner},
+  testing,
+};
+use async_trait::async_trait;
+use std::{
+  env,
+  os::unix::fs::PermissionsExt,
+  path::{Path, PathBuf},
+  process::Output,
+  sync::Arc,
+  time::Duration,
+};
+use url::Url;
+
+/// The stub OpenAPI generator
+const STUB_GENERATOR_SCRIPT: &str = include_str!("fixtures/openapi-generator-cli");
+/// How long any step may take, including building `olg` for the post-generation updates
+const STEP_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Runs processes for real, with the stub generator first on the PATH and cargo kept offline
+struct OfflineProcessRunner {
+  stub_bin_dir: PathBuf,
+}
+#[async_trait]
+impl ProcessRunner for OfflineProcessRunner {
+  async fn run(
+    &self,
+    spec: &ProcessSpec,
+  ) -> Result<Output, ProcessError> {
+    let path_var = env::var_os("PATH").unwrap_or_default();
+    let path = env::join_paths(
+      std::iter::once(self.stub_bin_dir.clone()).chain(env::split_paths(&path_var)),
+    )
+    .expect("must join PATH");
+    let spec = spec
+      .clone()
+      .env("PATH", path.to_string_lossy())
+      .env("CARGO_NET_OFFLINE", "true");
+    SystemProcessRunner::with_default_timeout(Some(STEP_TIMEOUT))
+      .run(&spec)
+      .await
+  }
+}
+
+/// Get why `cargo make` cannot run, if it cannot
+async fn get_missing_cargo_make_reason_opt(process_runner: &dyn ProcessRunner) -> Option<String> {
+  match process_runner
+    .run(&ProcessSpec::new("cargo", &["make", "--version"]))
+    .await
+  {
+    Ok(output) if output.status.success() => None,
+    Ok(output) => Some(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    Err(e) => Some(e.to_string()),
+  }
+}
+
+/// Write the stub generator into a bin dir
+fn install_stub_generator(stub_bin_dir: &Path) {
+  std::fs::create_dir_all(stub_bin_dir).expect("must create stub bin dir");
+  let stub_path = stub_bin_dir.join("openapi-generator-cli");
+  std::fs::write(&stub_path, STUB_GENERATOR_SCRIPT).expect("must write stub generator");
+  std::fs::set_permissions(&stub_path, std::fs::Permissions::from_mode(0o755))
+    .expect("must make stub generator executable");
+}
+
+#[tokio::test]
+async fn test_generation_runs_offline_with_stub_generator() {
+  let work_dir = get_temp_subdir().join(format!("e2e-{}", std::process::id()));
+  let stub_bin_dir = work_dir.join("bin");
+  let process_runner = OfflineProcessRunner {
+    stub_bin_dir: stub_bin_dir.clone(),
+  };
+  if let Some(reason) = get_missing_cargo_make_reason_opt(&process_runner).await {
+    eprintln!("Skipping the end-to-end generation, as `cargo make` does not run: {reason}");
+    return;
+  }
+  install_stub_generator(&stub_bin_dir);
+  let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+  let request = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+    .lib_name("petshoppe_e2e")
+    .output_dir(work_dir.join("petshoppe_e2e"))
+    .test_generation(Some(PathBuf::from(env!("CARGO_MANIFEST_DIR"))), None)
+    .build()
+    .expect("must build request")
+    .with_process_runner(Arc::new(process_runner));
+  let cli = request.get_cli().clone();
+  let result = request.run().await;
+  let read = |path: &Paths| {
+    std::fs::read_to_string(cli.get_output_project_subpath(path)).unwrap_or_default()
+  };
+  let (cargo_toml, readme, changelog) = (
+    read(&Paths::CargoTomlFile),
+    read(&Paths::ReadmeMdFile),
+    read(&Paths::ChangelogMdFile),
+  );
+  let lib_rs = std::fs::read_to_string(cli.get_output_project_dir().join("src/lib.rs"));
+  let _ = std::fs::remove_dir_all(&work_dir);
+  let report = result.expect("must generate");
+  assert_eq!(
+    report.completed_steps.last(),
+    Some(&GenerationSteps::Commit)
+  );
+  assert_eq!(
+    lib_rs.expect("must read generated lib"),
+    "pub mod apis;\npub mod models;\n"
+  );
+  assert!(cargo_toml.contains("edition = \"2021\""), "{cargo_toml}");
+  assert!(
+    cargo_toml.contains("[dev-dependencies.openapi_lib_generator]"),
+    "{cargo_toml}"
+  );
+  assert!(
+    readme.contains("## About working on `petshoppe_e2e`"),
+    "{readme}"
+  );
+  assert!(!changelog.is_empty());
+}
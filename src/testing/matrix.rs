@@ -0,0 +1,381 @@
+//! Compatibility matrix
+//!
+//! Generates a crate from each spec of the [SPEC_CORPUS](super::specs::SPEC_CORPUS) and runs it
+//! through every [MatrixStages], then reports which stages passed as a markdown table. Subprocesses
+//! run through the [ProcessRunner](crate::process::ProcessRunner) in scope.
+use crate::{
+  builder::GeneratorBuilder,
+  cli::{CLIError, Cli, TestMatrixArgs},
+  diagnostics::Diagnostic,
+  events, fs,
+  generate::{
+    makefiles::{MakefileEnv, TaskNames},
+    utils::to_snake_case,
+  },
+  process::{self, ProcessSpec},
+  testing::{self, specs::CorpusSpec, TestingError},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  env, fmt,
+  path::{Path, PathBuf},
+};
+use url::Url;
+
+/// Stages a spec goes through, in order
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum MatrixStages {
+  /// The OpenAPI generator validates the spec
+  Validate,
+  /// The crate is scaffolded and its code generated
+  Generate,
+  /// The generated crate builds
+  Compile,
+  /// The tests of the generated crate pass
+  Test,
+}
+impl MatrixStages {
+  /// Every stage, in order
+  pub const ALL: [Self; 4] = [Self::Validate, Self::Generate, Self::Compile, Self::Test];
+}
+
+/// How a stage went
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum StageOutcomes {
+  Passed,
+  Failed {
+    diagnostic: Box<Diagnostic>,
+  },
+  /// An earlier stage failed
+  Skipped,
+}
+
+/// How a stage of a spec went
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StageResult {
+  pub stage: MatrixStages,
+  #[serde(flatten)]
+  pub outcome: StageOutcomes,
+}
+
+/// How every stage of a spec went
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MatrixRow {
+  /// The corpus spec name
+  pub spec_name: String,
+  /// The dir its crate was generated in
+  pub output_dir: PathBuf,
+  pub stages: Vec<StageResult>,
+}
+impl MatrixRow {
+  /// Whether every stage passed
+  pub fn is_passing(&self) -> bool {
+    self
+      .stages
+      .iter()
+      .all(|result| matches!(result.outcome, StageOutcomes::Passed))
+  }
+}
+
+/// What a test matrix run found
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MatrixReport {
+  pub rows: Vec<MatrixRow>,
+}
+impl MatrixReport {
+  /// Whether every stage of every spec passed
+  pub fn is_passing(&self) -> bool {
+    self.rows.iter().all(MatrixRow::is_passing)
+  }
+}
+impl fmt::Display for MatrixReport {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    write!(f, "| spec |")?;
+    for stage in MatrixStages::ALL {
+      write!(f, " {} |", stage.as_ref())?;
+    }
+    writeln!(f)?;
+    writeln!(f, "|---|{}", "---|".repeat(MatrixStages::ALL.len()))?;
+    for row in self.rows.iter() {
+      write!(f, "| {} |", row.spec_name)?;
+      for StageResult { outcome, .. } in row.stages.iter() {
+        let cell = match outcome {
+          StageOutcomes::Passed => "pass",
+          StageOutcomes::Failed { .. } => "**FAIL**",
+          StageOutcomes::Skipped => "skipped",
+        };
+        write!(f, " {cell} |")?;
+      }
+      writeln!(f)?;
+    }
+    let failures = self
+      .rows
+      .iter()
+      .flat_map(|row| row.stages.iter().map(move |result| (row, result)))
+      .filter_map(|(row, StageResult { stage, outcome })| match outcome {
+        StageOutcomes::Failed { diagnostic } => Some((row, stage, diagnostic)),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    if !failures.is_empty() {
+      writeln!(f, "\n### Failures\n")?;
+    }
+    for (row, stage, diagnostic) in failures {
+      let message = diagnostic.message.lines().next().unwrap_or_default();
+      writeln!(
+        f,
+        "- `{}` failed to {}: {message} (in `{}`)",
+        row.spec_name,
+        stage.as_ref(),
+        row.output_dir.to_string_lossy()
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Run every picked corpus spec through the stages, writing the report if asked to
+pub async fn run_test_matrix(args: &TestMatrixArgs) -> Result<MatrixReport, TestingError> {
+  let work_dir = args.get_work_dir();
+  let mut report = MatrixReport::default();
+  for corpus_spec in args.get_corpus_specs() {
+    events::message(format!("Testing the `{}` spec", corpus_spec.name));
+    let row = run_spec(corpus_spec, &work_dir).await?;
+    match row.is_passing() {
+      true => events::message(format!("`{}` passed", corpus_spec.name)),
+      false => events::warning(format!("`{}` failed", corpus_spec.name)),
+    }
+    report.rows.push(row);
+  }
+  if let Some(report_file) = args.report_file_opt.as_ref() {
+    fs::write(report_file, report.to_string(), Some("Test matrix report")).await?;
+  }
+  Ok(report)
+}
+
+/// Run a corpus spec through the stages, skipping the rest once one fails
+async fn run_spec(
+  corpus_spec: &CorpusSpec,
+  work_dir: &Path,
+) -> Result<MatrixRow, TestingError> {
+  let specs_dir = work_dir.join("specs");
+  fs::create_dir_all(&specs_dir).await?;
+  let spec_path = specs_dir.join(corpus_spec.file_name);
+  fs::write(&spec_path, corpus_spec.contents, Some("Corpus spec")).await?;
+  let output_dir = work_dir.join(corpus_spec.name);
+  if fs::is_dir(&output_dir).await {
+    fs::remove_dir_all(&output_dir).await?;
+  }
+  let api_url = Url::parse(testing::TEST_API_URL).expect("must parse testing url");
+  let request = GeneratorBuilder::new(corpus_spec.name, api_url)
+    .lib_name(format!("matrix_{}", to_snake_case(corpus_spec.name)))
+    .spec_file(&spec_path)
+    .output_dir(&output_dir)
+    .autogenerate(false)
+    .build()
+    .expect("must build corpus spec request")
+    .with_process_runner(process::current());
+  let mut row = MatrixRow {
+    spec_name: corpus_spec.name.to_string(),
+    output_dir,
+    stages: vec![],
+  };
+  let mut has_failed = false;
+  for stage in MatrixStages::ALL {
+    let outcome = if has_failed {
+      StageOutcomes::Skipped
+    } else {
+      let label = format!("{} {}", corpus_spec.name, stage.as_ref());
+      let result = match stage {
+        MatrixStages::Validate => {
+          let spec = ProcessSpec::new(
+            get_open_api_generator_cli_program().await,
+            &[
+              "validate",
+              "--input-spec",
+              &spec_path.to_string_lossy(),
+              "--recommend",
+            ],
+          );
+          run_stage(spec.label(label)).await
+        }
+        // boxed like in the builder, to keep the generation future off the stack
+        MatrixStages::Generate => match Box::pin(request.run()).await {
+          Ok(_) => {
+            let spec = request
+              .get_cli()
+              .task_runner
+              .get_task_spec(TaskNames::GenerateAll.as_ref());
+            run_in_crate(request.get_cli(), spec.label(label)).await
+          }
+          Err(e) => Err(e),
+        },
+        MatrixStages::Compile => {
+          let spec = ProcessSpec::new("cargo", &["build", "--all-targets"]);
+          run_in_crate(request.get_cli(), spec.label(label)).await
+        }
+        MatrixStages::Test => {
+          let spec = ProcessSpec::new("cargo", &["test"]);
+          run_in_crate(request.get_cli(), spec.label(label)).await
+        }
+      };
+      match result {
+        Ok(()) => StageOutcomes::Passed,
+        Err(e) => {
+          has_failed = true;
+          StageOutcomes::Failed {
+            diagnostic: Box::new(Diagnostic::from(&e)),
+          }
+        }
+      }
+    };
+    row.stages.push(StageResult { stage, outcome });
+  }
+  Ok(row)
+}
+
+/// Get the OpenAPI generator script the `openapi-cli-bash-install` task of the crates installs, or
+/// the one on the PATH if it is not installed
+async fn get_open_api_generator_cli_program() -> String {
+  let installed_path_opt = env::var_os("HOME").map(|home_dir| {
+    Path::new(&home_dir)
+      .join(MakefileEnv::OPEN_API_GENERATOR_CLI_SUBDIR)
+      .join(MakefileEnv::OPEN_API_GENERATOR_CLI_SCRIPT)
+  });
+  match installed_path_opt {
+    Some(installed_path) if fs::is_file(&installed_path).await => {
+      installed_path.to_string_lossy().to_string()
+    }
+    _ => MakefileEnv::OPEN_API_GENERATOR_CLI_SCRIPT.to_string(),
+  }
+}
+
+/// Run a stage's command in the generated crate
+async fn run_in_crate(
+  cli: &Cli,
+  spec: ProcessSpec,
+) -> Result<(), CLIError> {
+  run_stage(spec.cwd(cli.get_output_project_dir())).await
+}
+
+/// Run a stage's command
+async fn run_stage(spec: ProcessSpec) -> Result<(), CLIError> {
+  process::run(&spec, format!("Running `{spec}`."))
+    .await
+    .map(|_| ())
+    .map_err(|e| TestingError::from(e).into())
+}
+
+#[cfg(test)]
+mod test_matrix {
+  use super::*;
+  use crate::{
+    fs::{FileSystem, MemoryFileSystem},
+    process::{ProcessError, ProcessFailure, RecordingProcessRunner},
+  };
+  use std::{process::Output, sync::Arc};
+  #[tokio::test]
+  async fn runs_every_stage_of_a_spec() {
+    let process_runner = Arc::new(RecordingProcessRunner::default());
+    let args = TestMatrixArgs {
+      spec_names: vec!["enums".to_string()],
+      work_dir_opt: Some(PathBuf::from("/virtual/matrix")),
+      ..Default::default()
+    };
+    let run = process::with_process_runner(process_runner.clone(), run_test_matrix(&args));
+    let report = fs::with_file_system(Arc::new(MemoryFileSystem::new()), run)
+      .await
+      .expect("must run matrix");
+    assert!(report.is_passing(), "{report}");
+    let commands = process_runner
+      .get_runs()
+      .iter()
+      .map(|spec| format!("{} {}", spec.program, spec.args.join(" ")))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      commands,
+      [
+        "openapi-generator-cli validate --input-spec /virtual/matrix/specs/enums.yaml --recommend",
+        "cargo init --lib --name matrix_enums /virtual/matrix/.enums.openapi_lib_generator-staging",
+        "cargo make generate-all",
+        "cargo build --all-targets",
+        "cargo test",
+      ]
+    );
+  }
+  #[tokio::test]
+  async fn validates_with_the_installed_generator_script() {
+    let home_dir = env::var_os("HOME").expect("must have a home dir");
+    let script_path = Path::new(&home_dir)
+      .join(MakefileEnv::OPEN_API_GENERATOR_CLI_SUBDIR)
+      .join(MakefileEnv::OPEN_API_GENERATOR_CLI_SCRIPT);
+    let file_system = Arc::new(MemoryFileSystem::new());
+    file_system
+      .write(&script_path, b"#!/bin/sh\n")
+      .await
+      .expect("must install script");
+    let process_runner = Arc::new(RecordingProcessRunner::default());
+    let args = TestMatrixArgs {
+      spec_names: vec!["enums".to_string()],
+      work_dir_opt: Some(PathBuf::from("/virtual/matrix")),
+      ..Default::default()
+    };
+    let run = process::with_process_runner(process_runner.clone(), run_test_matrix(&args));
+    fs::with_file_system(file_system, run)
+      .await
+      .expect("must run matrix");
+    assert_eq!(
+      process_runner.get_runs()[0].program,
+      script_path.to_string_lossy()
+    );
+  }
+  #[cfg(unix)]
+  #[test]
+  fn report_renders_as_markdown() {
+    let spec = ProcessSpec::new("cargo", &["build", "--all-targets"]);
+    let output = Output {
+      status: std::os::unix::process::ExitStatusExt::from_raw(256),
+      stdout: vec![],
+      stderr: b"error[E0412]: cannot find type `Pet`\n".to_vec(),
+    };
+    let error = CLIError::from(TestingError::from(ProcessError::from(Box::new(
+      ProcessFailure::new(&spec, &output),
+    ))));
+    let stage = |stage, outcome| StageResult { stage, outcome };
+    let report = MatrixReport {
+      rows: vec![MatrixRow {
+        spec_name: "polymorphism".to_string(),
+        output_dir: PathBuf::from("/matrix/polymorphism"),
+        stages: vec![
+          stage(MatrixStages::Validate, StageOutcomes::Passed),
+          stage(MatrixStages::Generate, StageOutcomes::Passed),
+          stage(
+            MatrixStages::Compile,
+            StageOutcomes::Failed {
+              diagnostic: Box::new(Diagnostic::from(&error)),
+            },
+          ),
+          stage(MatrixStages::Test, StageOutcomes::Skipped),
+        ],
+      }],
+    };
+    assert!(!report.is_passing());
+    assert_eq!(
+      report.to_string(),
+      "| spec | validate | generate | compile | test |\n\
+       |---|---|---|---|---|\n\
+       | polymorphism | pass | pass | **FAIL** | skipped |\n\
+       \n\
+       ### Failures\n\
+       \n\
+       - `polymorphism` failed to compile: `cargo build --all-targets` exited with code 1 (in \
+       `/matrix/polymorphism`)\n"
+    );
+  }
+}
@@ -0,0 +1,240 @@
+//! Golden snapshots of generated artifacts
+//!
+//! Each case generates a crate from a fixture spec with in-memory backends, then compares what was
+//! generated to the snapshots in `src/testing/snapshots`. A mismatch writes the new output next to its
+//! snapshot as `<name>.snap.new` and fails. Review pending snapshots with `cargo make snapshots-review`
+//! and accept them with `cargo make snapshots-accept`, or accept every change at once by running the
+//! tests with [UPDATE_SNAPSHOTS_ENV] set.
+use crate::{
+  builder::{GenerationRequest, GeneratorBuilder},
+  cli::Cli,
+  fs::{FileSystem, MemoryFileSystem},
+  generate::{
+    cargos::CargoConfigurator,
+    readmes::READMEGenerator,
+    specs::OpenAPISpec,
+    task_runners::TaskRunners,
+    utils::{get_temp_root_dir, get_temp_subdir, get_this_crate_ver},
+  },
+  process::RecordingProcessRunner,
+  testing,
+};
+use cargo_toml::Manifest;
+use chrono::{TimeZone, Utc};
+use similar::TextDiff;
+use std::{
+  env,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+use url::Url;
+
+/// Set this to accept every snapshot change
+const UPDATE_SNAPSHOTS_ENV: &str = "OLG_UPDATE_SNAPSHOTS";
+/// What stands in for the version of this crate, so that releases do not change snapshots
+const VERSION_REDACTION: &str = "[version]";
+/// What stands in for the system temp dir, which test generations must use
+const TEMP_DIR_REDACTION: &str = "[temp]";
+/// A fixture `Cargo.toml` as written by `cargo init`
+const CARGO_INIT_TOML: &str = include_str!("fixtures/Cargo.init.toml");
+
+/// Get the dir of the snapshots
+fn get_snapshots_dir() -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testing/snapshots")
+}
+
+/// Compare output to its snapshot
+fn assert_snapshot(
+  name: &str,
+  actual: &str,
+) {
+  let actual = actual
+    .replace(get_this_crate_ver(), VERSION_REDACTION)
+    .replace(
+      get_temp_root_dir().to_string_lossy().as_ref(),
+      TEMP_DIR_REDACTION,
+    );
+  let snapshot_path = get_snapshots_dir().join(format!("{name}.snap"));
+  let new_snapshot_path = snapshot_path.with_extension("snap.new");
+  let expected_opt = std::fs::read_to_string(&snapshot_path).ok();
+  if expected_opt.as_deref() == Some(actual.as_str()) {
+    let _ = std::fs::remove_file(&new_snapshot_path);
+    return;
+  }
+  if env::var_os(UPDATE_SNAPSHOTS_ENV).is_some() {
+    std::fs::write(&snapshot_path, &actual).expect("must write snapshot");
+    let _ = std::fs::remove_file(&new_snapshot_path);
+    return;
+  }
+  std::fs::write(&new_snapshot_path, &actual).expect("must write new snapshot");
+  let diff = TextDiff::from_lines(expected_opt.as_deref().unwrap_or_default(), &actual)
+    .unified_diff()
+    .header(
+      &snapshot_path.to_string_lossy(),
+      &new_snapshot_path.to_string_lossy(),
+    )
+    .to_string();
+  panic!(
+    "snapshot `{name}` does not match. Review it with `cargo make snapshots-review` or rerun with \
+     `{UPDATE_SNAPSHOTS_ENV}=1` to accept it:\n{diff}"
+  );
+}
+
+/// A generation to snapshot
+struct SnapshotCase {
+  /// The name snapshots of this case start with
+  name: &'static str,
+  /// The spec fixture, written to the in-memory filesystem as [Self::spec_file_name]
+  spec: &'static str,
+  spec_file_name: &'static str,
+  /// Settings on top of the spec file and an output dir under `/virtual`
+  configure: fn(GeneratorBuilder) -> GeneratorBuilder,
+}
+impl SnapshotCase {
+  /// Get the settings of this case, as of a fixed time
+  fn get_cli(&self) -> Cli {
+    let api_url = Url::parse(testing::TEST_API_URL).expect("must parse url");
+    let builder = GeneratorBuilder::new(testing::TEST_API_NAME, api_url)
+      .spec_file(Path::new("/virtual").join(self.spec_file_name))
+      .output_dir(Path::new("/virtual").join(self.name));
+    let mut cli = (self.configure)(builder)
+      .build()
+      .expect("must build request")
+      .get_cli()
+      .clone();
+    cli.generation_timestamp = Utc
+      .with_ymd_and_hms(2023, 4, 1, 12, 0, 0)
+      .single()
+      .expect("must make timestamp");
+    cli
+  }
+  /// Generate the crate and snapshot every file written, the commands run, the README and the
+  /// `Cargo.toml` edits
+  async fn assert_snapshots(&self) {
+    let cli = self.get_cli();
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let spec_path = cli
+      .local_api_spec_filepath_opt
+      .clone()
+      .expect("must have spec file");
+    file_system
+      .write(&spec_path, self.spec.as_bytes())
+      .await
+      .expect("must write spec");
+    let process_runner = Arc::new(RecordingProcessRunner::default());
+    GenerationRequest::from(cli.clone())
+      .with_file_system(file_system.clone())
+      .with_process_runner(process_runner.clone())
+      .run()
+      .await
+      .expect("must generate");
+    let output_dir = cli.get_output_project_dir();
+    for (path, contents) in file_system.get_files() {
+      let Ok(relative_path) = path.strip_prefix(&output_dir) else {
+        continue;
+      };
+      if relative_path.to_string_lossy() == self.spec_file_name {
+        continue;
+      }
+      let contents = String::from_utf8(contents).expect("must be utf-8");
+      // nested files get flat snapshot names
+      let relative_name = relative_path.to_string_lossy().replace(['/', '\\'], "__");
+      assert_snapshot(&format!("{}__{relative_name}", self.name), &contents);
+    }
+    let commands = process_runner
+      .get_runs()
+      .iter()
+      .map(|spec| format!("{spec}\n"))
+      .collect::<String>();
+    assert_snapshot(&format!("{}__commands", self.name), &commands);
+    let spec = OpenAPISpec::try_from_str(self.spec).expect("must parse spec");
+    let readme = READMEGenerator::new(&cli)
+      .expect("must make readme generator")
+      .update_readme_contents(None, Some(&spec))
+      .expect("must update readme");
+    assert_snapshot(&format!("{}__README.md", self.name), &readme);
+    let cargo_manifest =
+      &mut Manifest::<String>::from_slice_with_metadata(CARGO_INIT_TOML.as_bytes())
+        .expect("must parse manifest");
+    CargoConfigurator::new(&cli)
+      .expect("must make cargo configurator")
+      .update_manifest_post_generation(cargo_manifest)
+      .expect("must update manifest");
+    CargoConfigurator::update_manifest_post_fix_edition(cargo_manifest)
+      .expect("must update manifest edition");
+    let cargo_toml = toml::to_string_pretty(cargo_manifest).expect("must serialize manifest");
+    assert_snapshot(&format!("{}__Cargo.toml", self.name), &cargo_toml);
+  }
+}
+
+#[tokio::test]
+async fn petstore_from_spec_file() {
+  SnapshotCase {
+    name: "petstore",
+    spec: testing::PETSTORE_YAML,
+    spec_file_name: "petstore.yaml",
+    configure: |builder| builder,
+  }
+  .assert_snapshots()
+  .await
+}
+
+#[tokio::test]
+async fn bookshelf_with_lib_name_and_authors() {
+  SnapshotCase {
+    name: "bookshelf",
+    spec: include_str!("fixtures/bookshelf.json"),
+    spec_file_name: "bookshelf.json",
+    configure: |builder| {
+      builder
+        .lib_name("bookshelf_client")
+        .spec_url(
+          Url::parse("https://api.bookshelf.example/v2/openapi.json").expect("must parse url"),
+        )
+        .extra_authors(&["Ada <ada@bookshelf.example>", "Grace"])
+        .autogenerate(false)
+    },
+  }
+  .assert_snapshots()
+  .await
+}
+
+#[tokio::test]
+async fn weather_swagger2_test_generation() {
+  SnapshotCase {
+    name: "weather",
+    spec: include_str!("fixtures/weather_swagger2.yaml"),
+    spec_file_name: "weather_swagger2.yaml",
+    configure: |builder| {
+      builder
+        .test_generation(Some(PathBuf::from("/virtual/openapi_lib_generator")), None)
+        .output_dir(get_temp_subdir().join("weather"))
+    },
+  }
+  .assert_snapshots()
+  .await
+}
+
+#[tokio::test]
+async fn petstore_with_just() {
+  SnapshotCase {
+    name: "petstore_just",
+    spec: testing::PETSTORE_YAML,
+    spec_file_name: "petstore.yaml",
+    configure: |builder| builder.task_runner(TaskRunners::Just),
+  }
+  .assert_snapshots()
+  .await
+}
+
+#[tokio::test]
+async fn petstore_with_xtask() {
+  SnapshotCase {
+    name: "petstore_xtask",
+    spec: testing::PETSTORE_YAML,
+    spec_file_name: "petstore.yaml",
+    configure: |builder| builder.task_runner(TaskRunners::Xtask),
+  }
+  .assert_snapshots()
+  .await
+}
@@ -0,0 +1,101 @@
+//! Spec fixture corpus
+//!
+//! Specs in `src/testing/specs` that each exercise a feature generators tend to get wrong. `olg
+//! test-matrix` generates a crate from every one of them.
+
+/// A spec of the corpus
+#[derive(Clone, Copy, Debug)]
+pub struct CorpusSpec {
+  /// The name to pick the spec by
+  pub name: &'static str,
+  /// The file name the spec is written as
+  pub file_name: &'static str,
+  /// What the spec exercises
+  pub description: &'static str,
+  /// The spec itself
+  pub contents: &'static str,
+}
+
+/// Every spec of the corpus
+pub static SPEC_CORPUS: &[CorpusSpec] = &[
+  CorpusSpec {
+    name: "petstore",
+    file_name: "petstore.yaml",
+    description: "The classic petstore",
+    contents: super::PETSTORE_YAML,
+  },
+  CorpusSpec {
+    name: "polymorphism",
+    file_name: "polymorphism.yaml",
+    description: "oneOf with a discriminator and anyOf",
+    contents: include_str!("specs/polymorphism.yaml"),
+  },
+  CorpusSpec {
+    name: "inheritance",
+    file_name: "inheritance.yaml",
+    description: "allOf inheritance over several levels",
+    contents: include_str!("specs/inheritance.yaml"),
+  },
+  CorpusSpec {
+    name: "enums",
+    file_name: "enums.yaml",
+    description: "String and integer enums, inline and shared",
+    contents: include_str!("specs/enums.yaml"),
+  },
+  CorpusSpec {
+    name: "file-uploads",
+    file_name: "file_uploads.yaml",
+    description: "Multipart forms and binary bodies",
+    contents: include_str!("specs/file_uploads.yaml"),
+  },
+  CorpusSpec {
+    name: "nested-refs",
+    file_name: "nested_refs.yaml",
+    description: "Refs to refs, recursive models and shared components",
+    contents: include_str!("specs/nested_refs.yaml"),
+  },
+  CorpusSpec {
+    name: "openapi-3-1",
+    file_name: "openapi_3_1.yaml",
+    description: "OpenAPI 3.1 type arrays, const and webhooks",
+    contents: include_str!("specs/openapi_3_1.yaml"),
+  },
+  CorpusSpec {
+    name: "swagger-2-0",
+    file_name: "swagger_2_0.json",
+    description: "Swagger 2.0 definitions and form data",
+    contents: include_str!("specs/swagger_2_0.json"),
+  },
+];
+
+/// Get the names of the corpus specs
+pub fn get_corpus_spec_names() -> impl Iterator<Item = &'static str> {
+  SPEC_CORPUS.iter().map(|spec| spec.name)
+}
+/// Get a corpus spec by name
+pub fn get_corpus_spec(name: &str) -> Option<&'static CorpusSpec> {
+  SPEC_CORPUS.iter().find(|spec| spec.name == name)
+}
+
+#[cfg(test)]
+mod test_specs {
+  use super::*;
+  use crate::generate::specs::OpenAPISpec;
+  #[test]
+  fn corpus_specs_parse() {
+    for corpus_spec in SPEC_CORPUS {
+      let spec = OpenAPISpec::try_from_str(corpus_spec.contents)
+        .unwrap_or_else(|e| panic!("`{}` must parse: {e}", corpus_spec.name));
+      assert!(
+        spec.openapi.is_some() || spec.swagger.is_some(),
+        "`{}` has no version",
+        corpus_spec.name
+      );
+      assert!(
+        !spec.operations().is_empty(),
+        "`{}` has no operations",
+        corpus_spec.name
+      );
+    }
+  }
+}
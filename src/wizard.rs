@@ -0,0 +1,464 @@
+//! Interactive setup
+//!
+//! `olg init` asks for the settings of a generation one at a time, checking each answer before
+//! moving on, then either generates the crate or saves the settings to an `olg.toml` that
+//! `olg batch` can run later.
+use crate::{
+  batch::{BatchEntry, BatchManifest},
+  builder::{GenerationReport, GeneratorBuilder},
+  cli::{CLIError, InitArgs},
+  fs,
+  generate::{
+    parameters::{self, try_file_name_from_path_url},
+    specs::OpenAPISpec,
+    utils, workspaces,
+  },
+  process::{self, ProcessSpec},
+};
+use futures::future::Future;
+use std::{
+  collections::VecDeque,
+  env,
+  io::{self, BufRead, Error as IOError, ErrorKind, Write},
+  path::{Path, PathBuf},
+};
+use thiserror::Error;
+use toml::{de::Error as TomlDeError, ser::Error as TomlSerError};
+use url::Url;
+
+/// Wizard errors
+#[derive(Debug, Error)]
+pub enum WizardError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  TomlDeError(#[from] TomlDeError),
+  #[error(transparent)]
+  TomlSerError(#[from] TomlSerError),
+  #[error("The config {path} already lists an API named `{name}`")]
+  DuplicateName { path: PathBuf, name: String },
+}
+
+/// Asks the questions of the wizard
+pub trait Prompter {
+  /// Ask a question, getting the default if the answer is blank
+  fn ask(
+    &mut self,
+    question: &str,
+    default_opt: Option<&str>,
+  ) -> Result<String, IOError>;
+  /// Show something without expecting an answer
+  fn tell(
+    &mut self,
+    message: &str,
+  );
+}
+
+/// Asks on the terminal
+#[derive(Debug, Default)]
+pub struct TerminalPrompter;
+impl Prompter for TerminalPrompter {
+  fn ask(
+    &mut self,
+    question: &str,
+    default_opt: Option<&str>,
+  ) -> Result<String, IOError> {
+    let mut stdout = io::stdout();
+    match default_opt {
+      Some(default) => write!(stdout, "{question} [{default}]: ")?,
+      None => write!(stdout, "{question}: ")?,
+    }
+    stdout.flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+      return Err(IOError::new(
+        ErrorKind::UnexpectedEof,
+        "The input ended before the wizard finished",
+      ));
+    }
+    Ok(get_answer(&answer, default_opt))
+  }
+  fn tell(
+    &mut self,
+    message: &str,
+  ) {
+    println!("{message}");
+  }
+}
+
+/// Answers from a script, for tests
+#[derive(Debug, Default)]
+pub struct ScriptedPrompter {
+  pub answers: VecDeque<String>,
+  /// The questions asked and messages shown, in order
+  pub transcript: Vec<String>,
+}
+impl ScriptedPrompter {
+  pub fn new(answers: &[&str]) -> Self {
+    Self {
+      answers: answers.iter().map(ToString::to_string).collect(),
+      transcript: vec![],
+    }
+  }
+}
+impl Prompter for ScriptedPrompter {
+  fn ask(
+    &mut self,
+    question: &str,
+    default_opt: Option<&str>,
+  ) -> Result<String, IOError> {
+    self.transcript.push(question.to_string());
+    let answer = self.answers.pop_front().ok_or_else(|| {
+      IOError::new(
+        ErrorKind::UnexpectedEof,
+        format!("No scripted answer to `{question}`"),
+      )
+    })?;
+    Ok(get_answer(&answer, default_opt))
+  }
+  fn tell(
+    &mut self,
+    message: &str,
+  ) {
+    self.transcript.push(message.to_string());
+  }
+}
+
+/// Get the trimmed answer, or the default if it is blank
+fn get_answer(
+  answer: &str,
+  default_opt: Option<&str>,
+) -> String {
+  match (answer.trim(), default_opt) {
+    ("", Some(default)) => default.to_string(),
+    (answer, _) => answer.to_string(),
+  }
+}
+
+/// What the wizard did
+#[derive(Debug)]
+pub enum InitOutcomes {
+  /// Added the settings to this config file
+  WroteConfig(PathBuf),
+  Generated(Box<GenerationReport>),
+}
+
+/// Where the spec comes from
+enum SpecSources {
+  Url(Url),
+  File(PathBuf),
+}
+
+/// Ask for the settings of a generation, then generate the crate or save them to the config
+///
+/// Invalid answers are explained and asked again. A generation is rolled back if `cancel` finishes.
+pub async fn run_init_wizard(
+  args: &InitArgs,
+  prompter: &mut impl Prompter,
+  cancel: impl Future<Output = ()>,
+) -> Result<InitOutcomes, CLIError> {
+  let config_path = get_absolute_path(&args.config_path).map_err(WizardError::from)?;
+  let config_dir = config_path
+    .parent()
+    .map(Path::to_path_buf)
+    .unwrap_or_default();
+  let name = ask_until(prompter, "Site or API name", None, |answer| {
+    match answer.is_empty() {
+      true => Err("The name cannot be empty".to_string()),
+      false => Ok(answer.to_string()),
+    }
+  })?;
+  let api_url = ask_until(prompter, "API url", None, |answer| {
+    Url::parse(answer).map_err(|e| format!("`{answer}` is not a url: {e}"))
+  })?;
+  let spec_source = ask_spec(prompter).await?;
+  let default_lib_name = GeneratorBuilder::new(&name, api_url.clone()).get_lib_name();
+  let lib_name = ask_until(
+    prompter,
+    "Library name",
+    Some(&default_lib_name),
+    |answer| {
+      parameters::validate_lib_name(answer)
+        .map(|_| answer.to_string())
+        .map_err(|e| e.to_string())
+    },
+  )?;
+  let output_dir = ask_until(
+    prompter,
+    "Output dir, relative to the config",
+    Some(&lib_name),
+    |answer| get_absolute_path(&config_dir.join(answer)).map_err(|e| e.to_string()),
+  )?;
+  let (spec_url, spec_file) = match spec_source {
+    SpecSources::Url(spec_url) => (Some(spec_url), None),
+    SpecSources::File(spec_file) => (None, Some(get_relative_path(&spec_file, &config_dir))),
+  };
+  let entry = BatchEntry {
+    name,
+    api_url,
+    spec_url,
+    spec_file,
+    lib_name: (lib_name != default_lib_name).then_some(lib_name.clone()),
+    output: (output_dir != config_dir.join(&lib_name))
+      .then(|| get_relative_path(&output_dir, &config_dir)),
+    workspace: None,
+    task_runner: None,
+    extra_tasks: None,
+    readme_task_graph: false,
+    authors: vec![],
+    autogenerate: None,
+    force: false,
+    merge: false,
+    keep_failed: false,
+  };
+  let manifest = BatchManifest {
+    apis: vec![entry.clone()],
+  };
+  let config = toml::to_string(&manifest).map_err(WizardError::from)?;
+  prompter.tell(&format!("\nThe settings:\n\n{config}"));
+  let action = ask_until(
+    prompter,
+    "Generate the crate now (g) or write them to the config (w)?",
+    Some("g"),
+    |answer| match answer {
+      "g" | "w" => Ok(answer.to_string()),
+      _ => Err("Answer `g` or `w`".to_string()),
+    },
+  )?;
+  if action == "w" {
+    write_config(&config_path, &entry.name, &config).await?;
+    prompter.tell(&format!(
+      "Wrote {}. Run `olg batch {}` to generate it",
+      config_path.to_string_lossy(),
+      args.config_path.to_string_lossy()
+    ));
+    return Ok(InitOutcomes::WroteConfig(config_path));
+  }
+  let request = entry
+    .to_builder(&config_dir)
+    .build()?
+    .with_process_runner(process::current());
+  // boxed like in the builder, to keep the generation futures off the stack
+  let report = Box::pin(request.run_until(cancel)).await?;
+  Ok(InitOutcomes::Generated(Box::new(report)))
+}
+
+/// Ask until the answer parses, explaining what is wrong with each one that does not
+fn ask_until<T>(
+  prompter: &mut impl Prompter,
+  question: &str,
+  default_opt: Option<&str>,
+  mut parse: impl FnMut(&str) -> Result<T, String>,
+) -> Result<T, WizardError> {
+  loop {
+    let answer = prompter.ask(question, default_opt)?;
+    match parse(&answer) {
+      Ok(value) => return Ok(value),
+      Err(problem) => prompter.tell(&problem),
+    }
+  }
+}
+
+/// Ask for a spec url or file until one can be read, then summarize it
+///
+/// A url is downloaded to the temp dir, since the generated crate downloads it again anyway.
+async fn ask_spec(prompter: &mut impl Prompter) -> Result<SpecSources, WizardError> {
+  loop {
+    let answer = prompter.ask("Spec url or file (yaml or json)", None)?;
+    let (spec_source, spec_path) = match Url::parse(&answer) {
+      Ok(spec_url) if ["http", "https"].contains(&spec_url.scheme()) => {
+        match download_spec(&spec_url).await {
+          Ok(spec_path) => (SpecSources::Url(spec_url), spec_path),
+          Err(problem) => {
+            prompter.tell(&problem);
+            continue;
+          }
+        }
+      }
+      _ => {
+        let spec_path = get_absolute_path(Path::new(&answer))?;
+        if !fs::is_file(&spec_path).await {
+          prompter.tell(&format!("{} is not a file", spec_path.to_string_lossy()));
+          continue;
+        }
+        (SpecSources::File(spec_path.clone()), spec_path)
+      }
+    };
+    match OpenAPISpec::try_from_path(&spec_path).await {
+      Ok(spec) => {
+        prompter.tell(&summarize_spec(&spec));
+        return Ok(spec_source);
+      }
+      Err(e) => prompter.tell(&format!("Could not read the spec: {e}")),
+    }
+  }
+}
+
+/// Download a spec to the temp dir, explaining why if it cannot be
+async fn download_spec(spec_url: &Url) -> Result<PathBuf, String> {
+  let file_name = try_file_name_from_path_url(spec_url)
+    .ok()
+    .filter(|file_name| !file_name.is_empty())
+    .unwrap_or_else(|| "spec.yaml".to_string());
+  let download_dir = utils::get_temp_subdir().join("init");
+  fs::create_dir_all(&download_dir)
+    .await
+    .map_err(|e| e.to_string())?;
+  let spec_path = download_dir.join(file_name);
+  let spec = ProcessSpec::new(
+    "wget",
+    &[spec_url.as_str(), "-O", &spec_path.to_string_lossy()],
+  );
+  match process::run(&spec, format!("Downloading {spec_url}.")).await {
+    Ok(_) => Ok(spec_path),
+    Err(e) => Err(format!("Could not download the spec: {e}")),
+  }
+}
+
+/// Describe a spec in a few lines
+fn summarize_spec(spec: &OpenAPISpec) -> String {
+  let mut summary = format!(
+    "Found `{}` version {}: {} operations and {} models",
+    spec.info.title,
+    spec.info.version,
+    spec.operations().len(),
+    spec.models().len()
+  );
+  for server in spec.get_servers() {
+    summary.push_str(&format!("\n  server {}", server.url));
+  }
+  summary
+}
+
+/// Add the settings of an API to the config, creating it if needed
+async fn write_config(
+  config_path: &Path,
+  name: &str,
+  config: &str,
+) -> Result<(), WizardError> {
+  if !fs::is_file(config_path).await {
+    fs::write(config_path, config, Some("olg config")).await?;
+    return Ok(());
+  }
+  let mut contents = fs::read_to_string(config_path).await?;
+  let manifest: BatchManifest = toml::from_str(&contents)?;
+  if manifest.apis.iter().any(|entry| entry.name == name) {
+    return Err(WizardError::DuplicateName {
+      path: config_path.to_path_buf(),
+      name: name.to_string(),
+    });
+  }
+  if !contents.is_empty() && !contents.ends_with('\n') {
+    contents.push('\n');
+  }
+  contents.push('\n');
+  contents.push_str(config);
+  fs::write(config_path, contents, Some("olg config")).await?;
+  Ok(())
+}
+
+/// Make a path absolute against the current dir
+fn get_absolute_path(path: &Path) -> Result<PathBuf, IOError> {
+  Ok(workspaces::normalize_path(&env::current_dir()?.join(path)))
+}
+
+/// Get a path relative to a dir when it is inside of it
+fn get_relative_path(
+  path: &Path,
+  dir: &Path,
+) -> PathBuf {
+  path
+    .strip_prefix(dir)
+    .map(Path::to_path_buf)
+    .unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod test_wizard {
+  use super::*;
+  use crate::{
+    fs::{FileSystem, MemoryFileSystem},
+    process::RecordingProcessRunner,
+  };
+  use futures::future;
+  use std::sync::Arc;
+  const PETS_SPEC: &str = "openapi: 3.0.0\ninfo:\n  title: Pets\n  version: 1.0.0\nservers:\n  - \
+                           url: https://pets.example/v1\npaths:\n  /pets:\n    get:\n      \
+                           operationId: listPets\n";
+  /// Run the wizard against memory backends
+  async fn run_wizard(
+    file_system: Arc<MemoryFileSystem>,
+    prompter: &mut ScriptedPrompter,
+  ) -> Result<InitOutcomes, CLIError> {
+    file_system
+      .write(Path::new("/virtual/specs/pets.yaml"), PETS_SPEC.as_bytes())
+      .await
+      .expect("must write spec");
+    let args = InitArgs {
+      config_path: PathBuf::from("/virtual/olg.toml"),
+    };
+    let run = process::with_process_runner(
+      Arc::new(RecordingProcessRunner::default()),
+      run_init_wizard(&args, prompter, future::pending()),
+    );
+    fs::with_file_system(file_system, run).await
+  }
+  #[tokio::test]
+  async fn writes_the_config_after_asking_again() {
+    let file_system = Arc::new(MemoryFileSystem::new());
+    let mut prompter = ScriptedPrompter::new(&[
+      "",
+      "Pet Shoppe",
+      "not a url",
+      "https://www.petshoppe.example",
+      "/virtual/specs/missing.yaml",
+      "/virtual/specs/pets.yaml",
+      "pet-shoppe",
+      "pets",
+      "",
+      "w",
+    ]);
+    let outcome = run_wizard(file_system.clone(), &mut prompter)
+      .await
+      .expect("must run wizard");
+    assert!(matches!(outcome, InitOutcomes::WroteConfig(path) if path.ends_with("olg.toml")));
+    assert!(prompter.transcript.contains(
+      &"Found `Pets` version 1.0.0: 1 operations and 0 models\n  server https://pets.example/v1"
+        .to_string()
+    ));
+    assert!(prompter
+      .transcript
+      .iter()
+      .any(|line| line.starts_with("Library name `pet-shoppe`")));
+    let config = fs::with_file_system(file_system, fs::read_to_string("/virtual/olg.toml"))
+      .await
+      .expect("must read config");
+    assert_eq!(
+      config,
+      "[[apis]]\n\
+       name = \"Pet Shoppe\"\n\
+       api-url = \"https://www.petshoppe.example/\"\n\
+       spec-file = \"specs/pets.yaml\"\n\
+       lib-name = \"pets\"\n"
+    );
+  }
+  #[tokio::test]
+  async fn generates_the_crate() {
+    let mut prompter = ScriptedPrompter::new(&[
+      "PetShoppe",
+      "https://www.petshoppe.example",
+      "/virtual/specs/pets.yaml",
+      "",
+      "",
+      "",
+    ]);
+    let outcome = run_wizard(Arc::new(MemoryFileSystem::new()), &mut prompter)
+      .await
+      .expect("must run wizard");
+    assert!(matches!(
+      outcome,
+      InitOutcomes::Generated(report)
+        if report.output_dir == Path::new("/virtual/pet_shoppe_openapi_client")
+    ));
+  }
+}